@@ -20,6 +20,14 @@
           src = ./.;
         };
 
+        # For `nix flake check`: the optional JPEG XL decoder builds and passes the tests
+        checks.jxl = naersk'.buildPackage {
+          src = ./.;
+          cargoBuildOptions = options: options ++ [ "--features" "jxl" ];
+          cargoTestOptions = options: options ++ [ "--features" "jxl" ];
+          doCheck = true;
+        };
+
         # For `nix develop` (optional, can be skipped):
         devShell = pkgs.mkShell {
           nativeBuildInputs = with pkgs; [ rustc cargo ];
@@ -0,0 +1,44 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+/// Palettes and LUTs that were already read, so `dipc daemon` parses them once instead of for
+/// every request. A file is read again when its modification time changes.
+pub struct FileCache<T> {
+    entries: Mutex<BTreeMap<PathBuf, (Option<SystemTime>, T)>>,
+}
+
+impl<T: Clone> FileCache<T> {
+    pub const fn new() -> Self {
+        FileCache {
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The value read from `source` before, or the one `load` reads now. Sources that are not
+    /// files (URLs) are kept until the process exits.
+    pub fn get<E>(&self, source: &Path, load: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        let modified = fs::metadata(source).and_then(|metadata| metadata.modified());
+        // Relative paths are resolved, a daemon serves requests from different directories
+        let key = match modified {
+            Ok(_) => std::path::absolute(source).unwrap_or_else(|_| source.to_path_buf()),
+            Err(_) => source.to_path_buf(),
+        };
+        let modified = modified.ok();
+        if let Some((time, value)) = self.entries.lock().unwrap().get(&key) {
+            if *time == modified {
+                return Ok(value.clone());
+            }
+        }
+        let value = load()?;
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key, (modified, value.clone()));
+        Ok(value)
+    }
+}
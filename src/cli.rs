@@ -1,13 +1,44 @@
-use std::{fs::File, io::BufReader, path::PathBuf, str::FromStr};
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use serde_json::Value;
 
-use crate::delta::CLIDEMethod;
+use crate::{cache::FileCache, convert::Mode, convert_image_format::ToneMap, delta::CLIDEMethod};
+
+const EXIT_CODES: &str = "\
+Exit codes:
+    0    success
+    1    invalid command line arguments
+    2    palette could not be parsed or a style does not exist
+    3    an input image could not be read or decoded
+    4    an output image could not be encoded or saved
+    5    other IO error (e.g. creating the output directory)
+    130  interrupted with Ctrl-C
+    With several images, the code of the first image that failed (see --fail-fast)
+
+Configuration:
+    Defaults for the palette, styles, method and output directory are read from
+    $XDG_CONFIG_HOME/dipc/config.toml (~/.config/dipc/config.toml). Command line flags override them.";
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    after_long_help = EXIT_CODES,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     // Options
     /// The color palette variation(s) to use
     /// Run with --help instead of -h for a list of all possible values
@@ -15,6 +46,7 @@ pub struct Cli {
     /// Possible values:
     ///     - `all` to generate an image for each of the variations
     ///     - `none` if you are using a flat theme without variations
+    ///     - `auto` to use the dark or light variation depending on how bright each image is
     ///     - or a comma-delimited list of the names of variations it should use
     #[arg(
         short,
@@ -33,39 +65,1173 @@ pub struct Cli {
     #[arg(short, long, value_name = "PATH")]
     pub dir_output: Option<PathBuf>,
 
+    /// Output image format, defaults to the format of the input image if it can be written and
+    /// PNG otherwise.
+    /// Animations keep all of their frames as gif, webp or png/apng (APNG)
+    #[arg(short, long, value_enum, verbatim_doc_comment)]
+    pub format: Option<OutputFormat>,
+
+    /// Sizes of the square images in an .ico (`--format ico`), as a comma-delimited list
+    #[arg(
+        long,
+        value_name = "SIZES",
+        value_delimiter = ',',
+        value_parser = clap::value_parser!(u32).range(1..=256),
+        default_value = "16,32,48,256"
+    )]
+    pub sizes: Vec<u32>,
+
+    /// Save PNGs with an indexed color table holding the palette colors instead of RGBA,
+    /// which makes them a lot smaller
+    #[arg(long)]
+    pub indexed: bool,
+
+    /// Only map to the palette colors with these names, as a comma-delimited list
+    #[arg(
+        long,
+        value_name = "COLORS",
+        value_delimiter = ',',
+        conflicts_with = "apply_lut"
+    )]
+    pub only_colors: Option<Vec<String>>,
+
+    /// Leave out the palette colors with these names, as a comma-delimited list
+    #[arg(
+        long,
+        value_name = "COLORS",
+        value_delimiter = ',',
+        conflicts_with = "apply_lut"
+    )]
+    pub exclude_colors: Option<Vec<String>>,
+
+    /// JSON file of explicit source → target colors, e.g. `{"#2e3440": "base"}`, with targets
+    /// given as hex colors or palette color names. Pixels close to a source color get its target,
+    /// all others the nearest palette color
+    #[arg(long, value_name = "FILE", conflicts_with = "apply_lut")]
+    pub map: Option<PathBuf>,
+
+    /// Maximum DeltaE between a pixel and a source color of --map
+    #[arg(long, value_name = "DELTAE", default_value = "3.0")]
+    pub map_tolerance: f32,
+
+    /// Crop the image to WxH starting X,Y pixels from the top left before converting it
+    #[arg(long, value_name = "WxH+X+Y", conflicts_with = "fit")]
+    pub crop: Option<Crop>,
+
+    /// Crop the center of the image to this aspect ratio, like `16:9`, before converting it
+    #[arg(long, value_name = "W:H")]
+    pub fit: Option<Aspect>,
+
+    /// Resize the image to WxH before converting it. Leave out the width or height (`1920x`,
+    /// `x1080`) to keep the aspect ratio
+    #[arg(long, value_name = "WxH", conflicts_with = "scale")]
+    pub resize: Option<Size>,
+
+    /// Scale the image by this factor before converting it
+    #[arg(long, value_name = "FACTOR", value_parser = parse_scale)]
+    pub scale: Option<f32>,
+
+    /// Apply --resize or --scale to the converted image instead of the input
+    #[arg(long)]
+    pub resize_after: bool,
+
+    /// Drop frames of animations to show at most N frames per second, e.g. for chat apps with
+    /// size limits. The animations keep their length
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub gif_fps: Option<u32>,
+
+    /// Scale animations by this factor before converting them, still images keep their size
+    #[arg(long, value_name = "FACTOR", value_parser = parse_scale)]
+    pub gif_scale: Option<f32>,
+
+    /// Convert only every Nth pixel of every Nth row and save the result as `<name>_preview`,
+    /// with an estimate of how long the full image would take. Quick for trying palettes and
+    /// methods on large images
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(2..))]
+    pub fast_preview: Option<u32>,
+
+    /// Pixel art: convert the image downscaled by this factor, then scale it back up with
+    /// nearest-neighbor to keep the blocks crisp
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(2..))]
+    pub pixelate: Option<u32>,
+
+    /// Simulate a color vision deficiency on the input before mapping. See the `simulate`
+    /// subcommand to check an image that was already converted
+    #[arg(long, value_enum, value_name = "DEFICIENCY")]
+    pub simulate: Option<Deficiency>,
+
+    /// Gaussian blur with this sigma before mapping, to reduce speckles from noise
+    #[arg(long, value_name = "SIGMA")]
+    pub blur: Option<f32>,
+
+    /// Sharpen the result by this amount (unsharp mask) after mapping
+    #[arg(long, value_name = "AMOUNT", conflicts_with = "indexed")]
+    pub sharpen: Option<f32>,
+
+    /// Add monochromatic film grain of up to this many levels (out of 255) after mapping, a cheap
+    /// way to hide banding in dark gradients
+    #[arg(long, value_name = "AMOUNT", conflicts_with = "indexed")]
+    pub grain: Option<f32>,
+
+    /// Seed of the --grain noise, the same seed always gives the same grain
+    #[arg(long, value_name = "N", default_value = "0")]
+    pub seed: u64,
+
+    /// Convert on a single thread, so even the work is done in the same order on every machine.
+    /// The output never depends on the number of threads, this is for verifying that it doesn't
+    #[arg(long)]
+    pub deterministic: bool,
+
+    /// Blend and resample colors (--blur, --pixelate, --mask) in linear light instead of sRGB,
+    /// and index the LUT of --apply-lut by linear light values. Matching is always gamma
+    /// correct, as the conversion to Lab decodes sRGB
+    #[arg(long)]
+    pub linear_light: bool,
+
+    /// How pixels are mapped to the palette colors
+    #[arg(long, value_enum, default_value = "nearest")]
+    pub mode: Mode,
+
+    /// Blend smoothly between the palette colors with --mode gradient-map instead of posterizing
+    #[arg(long, conflicts_with = "indexed")]
+    pub interpolate: bool,
+
+    /// Blend the palette with another one, e.g. `gruvbox:0.3` to move every color 30% of the way
+    /// to the color of the same name in gruvbox, or the nearest one. The ratio defaults to 0.5
+    #[arg(long, value_name = "PALETTE[:RATIO]")]
+    pub blend_with: Option<BlendWith>,
+
+    /// Shift every palette color before matching, e.g. `lightness=+10,chroma=-20` for a brighter,
+    /// muted palette. Takes `lightness`, `chroma` and `hue` (degrees) in LCh
+    #[arg(long, value_name = "ADJUSTMENTS")]
+    pub palette_adjust: Option<PaletteAdjust>,
+
+    /// Add N lighter and N darker shades of every palette color, giving gradients more colors
+    /// within the hues of the theme
+    #[arg(long, value_name = "N")]
+    pub expand_shades: Option<u32>,
+
+    /// Merge palette colors closer than this DeltaE, keeping the first name. By default only
+    /// identical colors are merged
+    #[arg(long, value_name = "N", conflicts_with = "no_dedup")]
+    pub dedup_delta: Option<f32>,
+
+    /// Keep duplicate palette colors
+    #[arg(long)]
+    pub no_dedup: bool,
+
     /// CIELAB DeltaE method to use
     #[arg(short, long, value_enum, default_value = "de2000")]
     pub method: CLIDEMethod,
 
-    /// Verbose mode (-v, -vv, -vvv)
+    /// Map the images through a 3D LUT (.cube file or HALD CLUT image) instead of a palette.
+    /// PALETTE is left out in that case
+    #[arg(long, value_name = "LUT", conflicts_with_all = ["separate", "indexed"])]
+    pub apply_lut: Option<PathBuf>,
+
+    /// Tone mapping operator for HDR inputs (.hdr and .exr)
+    #[arg(long, value_enum, default_value = "reinhard")]
+    pub tone_map: ToneMap,
+
+    /// Write a separate image for each of the selected variations instead of merging them into one
+    /// palette
+    #[arg(long)]
+    pub separate: bool,
+
+    /// Grayscale mask image limiting the conversion: white areas are converted, black areas are
+    /// left untouched and gray areas are blended. It is stretched to the size of each image
+    #[arg(long, value_name = "MASK")]
+    pub mask: Option<PathBuf>,
+
+    /// Leave pixels close to these colors untouched, e.g. chroma-key greens or brand colors, as a
+    /// comma-delimited list of hex colors
+    #[arg(long, value_name = "COLOR", value_delimiter = ',', value_parser = parse_color)]
+    pub keep_color: Vec<[u8; 3]>,
+
+    /// Maximum DeltaE between a pixel and a color of --keep-color
+    #[arg(long, value_name = "DELTAE", default_value = "5.0")]
+    pub keep_tolerance: f32,
+
+    /// Only convert pixels with a hue within this range of degrees (HSV), e.g. `180-260` for blues.
+    /// Ranges can wrap around, e.g. `330-30` for reds
+    #[arg(long, value_name = "START-END")]
+    pub hue_range: Option<HueRange>,
+
+    /// JSON file assigning palettes to the regions of a color-coded mask, e.g.
+    /// `{"mask": "regions.png", "regions": {"#ff0000": {"palette": "nord", "styles": "Frost"}}}`.
+    /// Pixels outside of the listed regions use PALETTE
+    #[arg(long, value_name = "FILE", conflicts_with = "apply_lut")]
+    pub regions: Option<PathBuf>,
+
+    /// Keep the left half of the image original and only convert the right half, with a thin
+    /// divider in between, for before/after comparisons
+    #[arg(long)]
+    pub split_preview: bool,
+
+    /// Show the converted image in the terminal before saving it, with the kitty graphics
+    /// protocol, sixel or colored half blocks. The protocol is detected unless it is given
+    #[arg(
+        long,
+        value_enum,
+        value_name = "PROTOCOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "auto",
+        conflicts_with_all = ["stream", "raw_frames"]
+    )]
+    pub preview_term: Option<PreviewProtocol>,
+
+    /// Only show the preview of --preview-term, without saving the converted images
+    #[arg(long, requires = "preview_term")]
+    pub no_save: bool,
+
+    /// Print how many pixels were mapped to each palette color and which colors were never
+    /// used, as a table or JSON (`--stats=json`)
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text",
+        conflicts_with = "apply_lut"
+    )]
+    pub stats: Option<StatsFormat>,
+
+    /// How to report conversion progress: a progress bar, or newline-delimited JSON events on
+    /// stderr for GUIs and scripts wrapping dipc
+    #[arg(long, value_enum, value_name = "FORMAT", default_value = "bar")]
+    pub progress: ProgressFormat,
+
+    /// Print the mean DeltaE (CIEDE2000), PSNR and SSIM between the input and the converted image
+    #[arg(long)]
+    pub metrics: bool,
+
+    /// Write a JSON report next to each output image (`<output>.json`) with the palette, method,
+    /// duration, dimensions and number of palette colors used
+    #[arg(long)]
+    pub report: bool,
+
+    /// Verbose mode, -v logs debug messages such as timings and -vv everything
     #[arg(short, long, action = clap::ArgAction::Count)]
     pub verbose: u8,
 
+    /// Only print errors, warnings and the output asked for (`--stats`, `--metrics`): no banner,
+    /// palette swatches or per-file messages
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Read images from stdin and write the converted images to stdout, for use as a filter in
+    /// pipelines. Every image is prefixed by its length in bytes as a big-endian 64 bit integer,
+    /// in both directions
+    #[arg(
+        long,
+        conflicts_with_all = ["process", "output", "dir_output", "stats", "metrics", "report", "incremental"]
+    )]
+    pub stream: bool,
+
+    /// Read raw RGBA frames of WxH pixels from stdin and write the converted frames to stdout,
+    /// e.g. to convert a video piped from and to ffmpeg with `-f rawvideo -pix_fmt rgba`
+    #[arg(
+        long,
+        value_name = "WxH",
+        value_parser = parse_dimensions,
+        conflicts_with_all = ["process", "output", "dir_output", "stats", "metrics", "report", "incremental", "stream", "separate", "fast_preview"]
+    )]
+    pub raw_frames: Option<(u32, u32)>,
+
+    /// Convert the images in directories given as FILE and in all of their subdirectories.
+    /// Files and directories matched by a `.dipcignore` file (gitignore syntax) are skipped
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Follow symbolic links to files and directories with --recursive instead of skipping them
+    #[arg(long, requires = "recursive")]
+    pub follow_symlinks: bool,
+
+    /// Include hidden files and directories (starting with a `.`) with --recursive
+    #[arg(long, requires = "recursive")]
+    pub hidden: bool,
+
+    /// Copy the files that are not images into --dir-output with --recursive, so the whole
+    /// directory tree is recreated there
+    #[arg(long, requires = "recursive")]
+    pub copy_others: bool,
+
+    /// Match every pixel on its own instead of every distinct color once. Skips looking for the
+    /// distinct colors, which only pays off for screenshots and flat art and gives up quickly on
+    /// photos
+    #[arg(long)]
+    pub no_unique_pass: bool,
+
+    /// Run at a lower priority and leave one CPU core free, so long conversions don't make the
+    /// desktop stutter
+    #[arg(long)]
+    pub nice: bool,
+
+    /// Keep the memory used to convert an image below SIZE, e.g. `512M` or `2G`. Large images
+    /// are converted without their Lab representation in memory, which is slower with several
+    /// palettes, and PNGs that can't even be decoded within SIZE are converted a strip of rows at
+    /// a time
+    #[arg(long, value_name = "SIZE", value_parser = parse_memory)]
+    pub max_memory: Option<u64>,
+
+    /// Skip the images whose output already exists and is newer than the image, so a batch can
+    /// be rerun after adding new images. Delete the outputs to redo them after changing options
+    #[arg(long)]
+    pub incremental: bool,
+
+    /// Run a command for every converted image, e.g. `--exec 'swww img {output}'`. `{input}`,
+    /// `{output}` and `{palette}` are replaced by the image, the converted image and the palette
+    #[arg(long, value_name = "COMMAND", conflicts_with_all = ["stream", "raw_frames"])]
+    pub exec: Option<String>,
+
+    /// Set the converted image as the desktop wallpaper. The backend is detected unless it is
+    /// given: swww, hyprpaper or feh on Linux, the desktop settings on macOS and Windows
+    #[arg(
+        long,
+        value_enum,
+        value_name = "BACKEND",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "auto",
+        conflicts_with_all = ["stream", "raw_frames", "separate", "incremental"]
+    )]
+    pub set_wallpaper: Option<WallpaperBackend>,
+
+    /// Keep converting the remaining images when one fails, and list the failures at the end.
+    /// This is the default when converting several images
+    #[arg(long)]
+    pub keep_going: bool,
+
+    /// Stop at the first image that fails, even when converting several images
+    #[arg(long, conflicts_with = "keep_going")]
+    pub fail_fast: bool,
+
+    /// When to color the output: palette swatches, color names and errors. `auto` colors
+    /// terminals, unless `NO_COLOR` is set, and anything when `CLICOLOR_FORCE` is set
+    #[arg(
+        long,
+        value_enum,
+        value_name = "WHEN",
+        default_value = "auto",
+        global = true
+    )]
+    pub color: ColorWhen,
+
+    /// Also log to a file, with timestamps, appending to it if it exists. Messages are logged
+    /// even with `--quiet`
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Don't show the progress bar, e.g. when logging to a file
+    #[arg(long, conflicts_with = "progress")]
+    pub no_progress: bool,
+
     // Arguments
     /// The color palette to use:
     ///     - name of a builtin theme
-    ///     - path to a theme in JSON
+    ///     - path to a theme in JSON, YAML or TOML
+    ///     - path to a Base16/Base24 scheme (.yaml), a terminal configuration (.Xresources,
+    ///       kitty.conf, alacritty.yml/.toml), or CSS/SCSS color variables
+    ///     - name of a theme in $XDG_DATA_HOME/dipc/palettes/<name>.json (or .yaml/.toml)
+    ///     - an http(s) URL of a theme in any of the formats above, e.g. a gist
     ///     - a JSON string with the theme (starting with `JSON: {}`)
     /// Run with --help instead of -h for a list of all builtin themes
     ///
     /// Builtin themes:
+    ///     - ayu
     ///     - catppuccin
     ///     - dracula
     ///     - edge
     ///     - everforest
+    ///     - github
     ///     - gruvbox
     ///     - gruvbox-material
+    ///     - horizon
+    ///     - kanagawa
+    ///     - material
+    ///     - monokai
+    ///     - night-owl
     ///     - nord
     ///     - onedark
     ///     - rose-pine
     ///     - solarized
     ///     - tokyo-night
-    #[arg(value_name = "PALETTE", verbatim_doc_comment)]
-    pub color_palette: ColorPalette,
+    ///     - zenburn
+    ///
+    /// Can be left out if a default `palette` is set in the configuration file, or with --apply-lut
+    #[arg(
+        value_name = "PALETTE",
+        required_unless_present = "apply_lut",
+        verbatim_doc_comment
+    )]
+    pub palette: Option<String>,
 
-    /// The image(s) to process
+    /// The image(s) to process, or http(s) URLs of images
     #[arg(value_name = "FILE", value_delimiter = ',')]
     pub process: Vec<PathBuf>,
+
+    /// The palette resolved from `palette` and the configuration file, see `Settings::apply`
+    #[arg(skip = ColorPalette::RawJSON { map: Default::default() })]
+    pub color_palette: ColorPalette,
+
+    /// The directory of every image in `process` relative to the directory --recursive found it
+    /// in, see `walk::expand`
+    #[arg(skip)]
+    pub input_dirs: Vec<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Inspect builtin and custom palettes
+    #[command(subcommand)]
+    Palettes(PalettesCommand),
+    /// Render the palette mapping into a 3D LUT, to apply the same transform in video editors, OBS
+    /// or darktable
+    Lut(LutArgs),
+    /// Generate a terminal color scheme (pywal colors.json or Base16 YAML) from an image
+    Theme(ThemeArgs),
+    /// Rank the builtin palettes and their variations by how well they fit an image
+    Suggest(SuggestArgs),
+    /// Show the dimensions, format, transparency and dominant colors of an image
+    Info(InfoArgs),
+    /// Show how an image looks with a color vision deficiency
+    Simulate(SimulateArgs),
+    /// Convert a video with ffmpeg, keeping its audio
+    Video(VideoArgs),
+    /// Save images in another format without changing their colors, keeping animations,
+    /// metadata and 16 bit precision
+    ConvertFormat(ConvertFormatArgs),
+    /// Run the conversions described in a TOML file, e.g. different palettes for different
+    /// images
+    Batch(BatchArgs),
+    /// Serve conversions sent with `dipc client` over a unix socket, keeping palettes and LUTs
+    /// in memory between them
+    Daemon(DaemonArgs),
+    /// Send a conversion to a running `dipc daemon`
+    Client(ClientArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct SimulateArgs {
+    /// The color vision deficiency to simulate
+    #[arg(value_enum, value_name = "DEFICIENCY")]
+    pub deficiency: Deficiency,
+
+    /// The image to process
+    #[arg(value_name = "FILE")]
+    pub image: PathBuf,
+
+    /// Output file name/path, defaults to `<name>_<deficiency>.<ext>` next to the image
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct VideoArgs {
+    /// The color palette to use, accepts the same values as the main PALETTE argument
+    #[arg(value_name = "PALETTE")]
+    pub palette: String,
+
+    /// The video to convert, in any format ffmpeg reads
+    #[arg(value_name = "FILE")]
+    pub input: PathBuf,
+
+    /// Output file name/path, defaults to `<name>_<palette>.<ext>` next to the video
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Options for the conversion of the frames, e.g. `-- --styles Frost --method de1976`.
+    /// Options that change the size of the frames can't be used
+    #[arg(last = true, value_name = "OPTIONS")]
+    pub options: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ConvertFormatArgs {
+    /// The image(s) to convert
+    #[arg(value_name = "FILE", value_delimiter = ',', required = true)]
+    pub images: Vec<PathBuf>,
+
+    /// The format to save the images as, unless the extension of an --output name picks one
+    #[arg(short, long, value_enum, value_name = "FORMAT")]
+    pub to: Option<OutputFormat>,
+
+    /// Output file names/paths, one for every image, as a comma-delimited list. Defaults to the
+    /// name of the image with the extension of the format
+    #[arg(short, long, value_name = "PATH", value_delimiter = ',')]
+    pub output: Option<Vec<PathBuf>>,
+
+    /// Output directory, defaults to the directory of each image
+    #[arg(short, long, value_name = "PATH")]
+    pub dir_output: Option<PathBuf>,
+
+    /// Sizes of the square images in an .ico, as a comma-delimited list
+    #[arg(
+        long,
+        value_name = "SIZES",
+        value_delimiter = ',',
+        value_parser = clap::value_parser!(u32).range(1..=256),
+        default_value = "16,32,48,256"
+    )]
+    pub sizes: Vec<u32>,
+}
+
+#[derive(Args, Debug)]
+pub struct BatchArgs {
+    /// The TOML file with a `[[job]]` table for every conversion and optional `[defaults]` for
+    /// all of them. The keys are the long options of dipc, plus `palette` and `input`
+    #[arg(value_name = "FILE")]
+    pub manifest: PathBuf,
+
+    /// Stop at the first job that fails
+    #[arg(long)]
+    pub fail_fast: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DaemonArgs {
+    /// The socket to listen on, defaults to `$XDG_RUNTIME_DIR/dipc.sock`
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+
+    /// Convert at a lower priority and leave one CPU core free, like `dipc --nice`. Requests
+    /// can't change the priority of a running daemon.
+    #[arg(long)]
+    pub nice: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ClientArgs {
+    /// The socket of the daemon, defaults to `$XDG_RUNTIME_DIR/dipc.sock`
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+
+    /// The arguments of the conversion, as they would be given to dipc, e.g.
+    /// `nord --styles Frost icon.png`
+    #[arg(
+        value_name = "ARGS",
+        required = true,
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    pub arguments: Vec<String>,
+}
+
+/// Color vision deficiencies that can be simulated
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Deficiency {
+    /// No red cones
+    Protanopia,
+    /// No green cones
+    Deuteranopia,
+    /// No blue cones
+    Tritanopia,
+}
+
+impl std::fmt::Display for Deficiency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Deficiency::Protanopia => "protanopia",
+            Deficiency::Deuteranopia => "deuteranopia",
+            Deficiency::Tritanopia => "tritanopia",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct InfoArgs {
+    /// The image to inspect
+    #[arg(value_name = "FILE")]
+    pub image: PathBuf,
+
+    /// Number of dominant colors to show
+    #[arg(short = 'n', long, value_name = "N", default_value = "8")]
+    pub colors: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct SuggestArgs {
+    /// The image to find a palette for
+    #[arg(value_name = "FILE")]
+    pub image: PathBuf,
+
+    /// CIELAB DeltaE method to use
+    #[arg(short, long, value_enum, default_value = "de2000")]
+    pub method: CLIDEMethod,
+
+    /// Only list the N best fitting palettes
+    #[arg(short = 'n', long, value_name = "N")]
+    pub top: Option<usize>,
+}
+
+#[derive(Args, Debug)]
+pub struct ThemeArgs {
+    /// The image to take the colors from
+    #[arg(value_name = "FILE")]
+    pub image: PathBuf,
+
+    /// Color scheme format
+    #[arg(short, long, value_enum, default_value = "pywal")]
+    pub format: ThemeFormat,
+
+    /// Output file name/path, the scheme is printed to stdout otherwise
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ThemeFormat {
+    /// pywal colors.json with the special colors and 16 ANSI colors
+    Pywal,
+    /// Base16 scheme YAML (base00 to base0F)
+    Base16,
+}
+
+#[derive(Args, Debug)]
+pub struct LutArgs {
+    /// The color palette to use, accepts the same values as the main PALETTE argument
+    #[arg(value_name = "PALETTE")]
+    pub palette: String,
+
+    /// The color palette variation(s) to use, defaults to all of them
+    #[arg(short, long, value_name = "VARIATIONS")]
+    pub styles: Option<ColorPaletteStyles>,
+
+    /// LUT file format
+    #[arg(short, long, value_enum, default_value = "cube")]
+    pub format: LutFormat,
+
+    /// Number of entries per channel for cube (2-256, default 33), or the level for HALD
+    /// (2-16, default 8, giving a 512x512 image)
+    #[arg(long, value_name = "N")]
+    pub size: Option<u32>,
+
+    /// CIELAB DeltaE method to use
+    #[arg(short, long, value_enum, default_value = "de2000")]
+    pub method: CLIDEMethod,
+
+    /// Space the LUT entries evenly in linear light instead of sRGB, for linear light input. Apply
+    /// such a LUT with `--apply-lut --linear-light`
+    #[arg(long)]
+    pub linear_light: bool,
+
+    /// Output file name/path, defaults to `<palette>.cube` or `<palette>.png`
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LutFormat {
+    /// HALD CLUT image (PNG)
+    Hald,
+    /// Adobe/Resolve .cube file
+    Cube,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PalettesCommand {
+    /// Print every variation of a palette as color swatches with their names and hex values
+    Show {
+        /// The color palette to show, accepts the same values as the main PALETTE argument
+        #[arg(value_name = "PALETTE")]
+        palette: String,
+    },
+    /// Check every color of every variation of a palette, reporting all problems instead of
+    /// stopping at the first one
+    Validate {
+        /// The color palette to check, accepts the same values as the main PALETTE argument
+        #[arg(value_name = "PALETTE")]
+        palette: String,
+    },
+    /// Merge palettes into one. Variations of the same name are combined, and a color defined by
+    /// several palettes is taken from the last one
+    Merge {
+        /// The color palettes to merge, accepts the same values as the main PALETTE argument
+        #[arg(value_name = "PALETTE", num_args = 2.., required = true)]
+        palettes: Vec<String>,
+
+        /// Output file name/path, the merged palette is printed to stdout otherwise
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Convert a palette to another format: dipc JSON, GIMP .gpl, Adobe .ase, CSS custom
+    /// properties or a Base16 scheme
+    Convert {
+        /// The color palette to convert, accepts the same values as the main PALETTE argument,
+        /// including .gpl and .ase files
+        #[arg(value_name = "PALETTE")]
+        palette: String,
+
+        /// The format to convert to
+        #[arg(long, value_enum)]
+        to: PaletteFormat,
+
+        /// The color palette variation(s) to convert, defaults to all of them
+        #[arg(short, long, value_name = "VARIATIONS")]
+        styles: Option<ColorPaletteStyles>,
+
+        /// Output file name/path, the palette is printed to stdout otherwise
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Print the WCAG contrast ratio of every color of a palette against its background
+    /// color(s), flagging the pairs too low for body text
+    Contrast {
+        /// The color palette to check, accepts the same values as the main PALETTE argument
+        #[arg(value_name = "PALETTE")]
+        palette: String,
+
+        /// The color palette variation(s) to check, defaults to all of them
+        #[arg(short, long, value_name = "VARIATIONS")]
+        styles: Option<ColorPaletteStyles>,
+
+        /// The background color(s) by name, comma separated. Defaults to the colors named like a
+        /// background (`bg`, `background`, `base`, ...), or the darkest or lightest color
+        #[arg(short, long, value_name = "NAMES", value_delimiter = ',')]
+        background: Vec<String>,
+
+        /// The contrast ratio below which a pair is flagged, 4.5 is the WCAG AA level for body
+        /// text
+        #[arg(long, value_name = "RATIO", default_value = "4.5")]
+        min_ratio: f32,
+    },
+    /// List the colors added, removed and changed between two palettes, with the DeltaE of the
+    /// changed ones
+    Diff {
+        /// The original palette, accepts the same values as the main PALETTE argument
+        #[arg(value_name = "OLD")]
+        old: String,
+
+        /// The changed palette
+        #[arg(value_name = "NEW")]
+        new: String,
+    },
+}
+
+/// Formats `palettes convert` can write
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum PaletteFormat {
+    /// dipc palette JSON
+    Json,
+    /// GIMP palette (.gpl)
+    Gpl,
+    /// Adobe Swatch Exchange (.ase)
+    Ase,
+    /// CSS custom properties
+    Css,
+    /// Base16 scheme YAML, from the `base00` to `base0F` colors or the first 16 colors
+    Base16,
+}
+
+impl std::fmt::Display for PaletteFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            PaletteFormat::Json => "JSON",
+            PaletteFormat::Gpl => "A GIMP palette",
+            PaletteFormat::Ase => "An Adobe Swatch Exchange file",
+            PaletteFormat::Css => "CSS",
+            PaletteFormat::Base16 => "A Base16 scheme",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatsFormat {
+    Text,
+    Json,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorWhen {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProgressFormat {
+    Bar,
+    Json,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum PreviewProtocol {
+    Auto,
+    Kitty,
+    Sixel,
+    Halfblocks,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum WallpaperBackend {
+    Auto,
+    Swww,
+    Hyprpaper,
+    Feh,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Png,
+    /// PNG, which stores animations as APNG
+    Apng,
+    Jpeg,
+    Webp,
+    Bmp,
+    Tiff,
+    Gif,
+    /// Windows icon with one image for every size of --sizes, for favicons and app icons
+    Ico,
+    /// Needs dipc to be built with the `avif` feature
+    Avif,
+}
+
+impl From<OutputFormat> for image::ImageFormat {
+    fn from(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Png | OutputFormat::Apng => Self::Png,
+            OutputFormat::Jpeg => Self::Jpeg,
+            OutputFormat::Webp => Self::WebP,
+            OutputFormat::Bmp => Self::Bmp,
+            OutputFormat::Tiff => Self::Tiff,
+            OutputFormat::Gif => Self::Gif,
+            OutputFormat::Ico => Self::Ico,
+            OutputFormat::Avif => Self::Avif,
+        }
+    }
+}
+
+/// A range of hues in degrees, wrapping around 360 when `start` is larger than `end`
+#[derive(Copy, Clone, Debug)]
+pub struct HueRange {
+    start: f32,
+    end: f32,
+}
+
+impl HueRange {
+    /// Whether the hue of `rgb` is within the range. Grays have no hue and never are
+    pub fn contains(&self, rgb: [u8; 3]) -> bool {
+        let [r, g, b] = rgb.map(f32::from);
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        if delta == 0.0 {
+            return false;
+        }
+        let hue = if max == r {
+            (g - b) / delta
+        } else if max == g {
+            (b - r) / delta + 2.0
+        } else {
+            (r - g) / delta + 4.0
+        };
+        let hue = (hue * 60.0).rem_euclid(360.0);
+        match self.start <= self.end {
+            true => (self.start..=self.end).contains(&hue),
+            false => hue >= self.start || hue <= self.end,
+        }
+    }
+}
+
+impl FromStr for HueRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |degrees: &str| {
+            degrees
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|degrees| (0.0..=360.0).contains(degrees))
+                .ok_or_else(|| format!("`{degrees}` is not a hue between 0 and 360"))
+        };
+        let Some((start, end)) = s.split_once('-') else {
+            return Err(String::from("Expected a range of hues like `180-260`"));
+        };
+        Ok(HueRange {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+/// A palette to blend with and how far to move towards it
+#[derive(Clone, Debug)]
+pub struct BlendWith {
+    pub palette: String,
+    pub ratio: f32,
+}
+
+impl FromStr for BlendWith {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Palettes can be paths and URLs, so only a trailing number is taken as the ratio
+        let (palette, ratio) = match s.rsplit_once(':') {
+            Some((palette, ratio)) => match ratio.parse::<f32>() {
+                Ok(ratio) => (palette, ratio),
+                Err(_) => (s, 0.5),
+            },
+            None => (s, 0.5),
+        };
+        if !(0.0..=1.0).contains(&ratio) {
+            return Err(format!("The blend ratio {ratio} is not between 0 and 1"));
+        }
+        Ok(BlendWith {
+            palette: palette.to_string(),
+            ratio,
+        })
+    }
+}
+
+/// Offsets added to the LCh lightness, chroma and hue of palette colors
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PaletteAdjust {
+    lightness: f32,
+    chroma: f32,
+    hue: f32,
+}
+
+impl PaletteAdjust {
+    pub fn apply(&self, color: [u8; 3]) -> [u8; 3] {
+        let [l, c, h] = crate::delta::Lab::from(color).to_lch();
+        crate::delta::Lab::from_lch([
+            (l + self.lightness).clamp(0.0, 100.0),
+            (c + self.chroma).max(0.0),
+            h + self.hue,
+        ])
+        .to_rgb()
+    }
+}
+
+impl FromStr for PaletteAdjust {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut adjust = PaletteAdjust::default();
+        for adjustment in s.split(',') {
+            let Some((key, value)) = adjustment.split_once('=') else {
+                return Err(format!(
+                    "Expected `key=value` like `lightness=+10`, found `{adjustment}`"
+                ));
+            };
+            let value = value
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .ok_or_else(|| format!("`{value}` is not a number"))?;
+            match key.trim() {
+                "lightness" | "l" => adjust.lightness = value,
+                "chroma" | "c" => adjust.chroma = value,
+                "hue" | "h" => adjust.hue = value,
+                key => {
+                    return Err(format!(
+                        "Unknown adjustment `{key}`, expected lightness, chroma or hue"
+                    ))
+                }
+            }
+        }
+        Ok(adjust)
+    }
+}
+
+/// A rectangle to crop images to
+#[derive(Copy, Clone, Debug)]
+pub struct Crop {
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+}
+
+impl Crop {
+    /// The `(x, y, width, height)` of the crop within an image of `width` by `height`, cut off
+    /// at its edges, or `None` when it starts outside of the image
+    pub fn within(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        (self.x < width && self.y < height).then(|| {
+            (
+                self.x,
+                self.y,
+                self.width.min(width - self.x),
+                self.height.min(height - self.y),
+            )
+        })
+    }
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("`{s}` is not a crop like `1920x1080+0+120`");
+        let (size, offset) = s.split_once('+').unwrap_or((s, "0+0"));
+        let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let (x, y) = offset.split_once('+').ok_or_else(invalid)?;
+        let parse = |number: &str| number.trim().parse::<u32>().map_err(|_| invalid());
+        let crop = Crop {
+            width: parse(width)?,
+            height: parse(height)?,
+            x: parse(x)?,
+            y: parse(y)?,
+        };
+        if crop.width == 0 || crop.height == 0 {
+            return Err(format!("`{s}` crops the image to nothing"));
+        }
+        Ok(crop)
+    }
+}
+
+/// An aspect ratio to center crop images to
+#[derive(Copy, Clone, Debug)]
+pub struct Aspect {
+    width: u32,
+    height: u32,
+}
+
+impl Aspect {
+    /// The `(x, y, width, height)` of the largest centered crop with this aspect ratio
+    pub fn within(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let (aspect_width, aspect_height) = (u64::from(self.width), u64::from(self.height));
+        let (crop_width, crop_height) =
+            match u64::from(width) * aspect_height > u64::from(height) * aspect_width {
+                // Too wide, keep the height
+                true => (
+                    (u64::from(height) * aspect_width / aspect_height).max(1) as u32,
+                    height,
+                ),
+                false => (
+                    width,
+                    (u64::from(width) * aspect_height / aspect_width).max(1) as u32,
+                ),
+            };
+        (
+            (width - crop_width) / 2,
+            (height - crop_height) / 2,
+            crop_width,
+            crop_height,
+        )
+    }
+}
+
+impl FromStr for Aspect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("`{s}` is not an aspect ratio like `16:9`");
+        let (width, height) = s.split_once(':').ok_or_else(invalid)?;
+        let parse = |number: &str| {
+            number
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|&number| number > 0)
+                .ok_or_else(invalid)
+        };
+        Ok(Aspect {
+            width: parse(width)?,
+            height: parse(height)?,
+        })
+    }
+}
+
+/// Output dimensions, either of which can be left out to keep the aspect ratio
+#[derive(Copy, Clone, Debug)]
+pub struct Size {
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+impl Size {
+    /// The size an image of `width` by `height` is resized to
+    pub fn of(&self, width: u32, height: u32) -> (u32, u32) {
+        let scale = |length: u32, from: u32, to: u32| {
+            ((u64::from(length) * u64::from(to) + u64::from(from) / 2) / u64::from(from)).max(1)
+                as u32
+        };
+        match (self.width, self.height) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, scale(height, width, w)),
+            (None, Some(h)) => (scale(width, height, h), h),
+            (None, None) => (width, height),
+        }
+    }
+}
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |length: &str| match length.trim() {
+            "" => Ok(None),
+            length => length
+                .parse::<u32>()
+                .ok()
+                .filter(|&length| length > 0)
+                .map(Some)
+                .ok_or_else(|| format!("`{length}` is not a positive number of pixels")),
+        };
+        let Some((width, height)) = s.split_once(['x', 'X']) else {
+            return Err(String::from(
+                "Expected a size like `1920x1080`, `1920x` or `x1080`",
+            ));
+        };
+        let size = Size {
+            width: parse(width)?,
+            height: parse(height)?,
+        };
+        if size.width.is_none() && size.height.is_none() {
+            return Err(String::from(
+                "Expected at least one of the width and height",
+            ));
+        }
+        Ok(size)
+    }
+}
+
+fn parse_scale(scale: &str) -> Result<f32, String> {
+    scale
+        .parse::<f32>()
+        .ok()
+        .filter(|scale| scale.is_finite() && *scale > 0.0)
+        .ok_or_else(|| format!("`{scale}` is not a positive scale factor"))
+}
+
+/// A number of bytes with an optional binary K, M, G or T suffix, e.g. `1.5G`
+fn parse_memory(size: &str) -> Result<u64, String> {
+    let upper = size.trim().to_ascii_uppercase();
+    let number = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (number, unit) = match number.char_indices().last() {
+        Some((idx, 'K')) => (&number[..idx], 1u64 << 10),
+        Some((idx, 'M')) => (&number[..idx], 1 << 20),
+        Some((idx, 'G')) => (&number[..idx], 1 << 30),
+        Some((idx, 'T')) => (&number[..idx], 1 << 40),
+        _ => (number, 1),
+    };
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite() && *number > 0.0)
+        .map(|number| (number * unit as f64) as u64)
+        .ok_or_else(|| format!("`{size}` is not a size like `512M` or `2G`"))
+}
+
+fn parse_dimensions(dimensions: &str) -> Result<(u32, u32), String> {
+    match Size::from_str(dimensions)? {
+        Size {
+            width: Some(width),
+            height: Some(height),
+        } => Ok((width, height)),
+        _ => Err(String::from("Expected a size like `1920x1080`")),
+    }
+}
+
+fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    crate::config::parse_hex(color)
 }
 
 #[derive(Clone, Debug)]
@@ -73,6 +1239,7 @@ pub enum ColorPaletteStyles {
     All,
     Some { styles: Vec<String> },
     None,
+    Auto,
 }
 
 impl FromStr for ColorPaletteStyles {
@@ -82,6 +1249,7 @@ impl FromStr for ColorPaletteStyles {
         let style = match s {
             "all" | "ALL" => Self::All,
             "none" | "NONE" | "no" | "NO" => Self::None,
+            "auto" | "AUTO" => Self::Auto,
             some => Self::Some {
                 styles: {
                     let mut vars = Vec::new();
@@ -105,36 +1273,75 @@ impl FromStr for ColorPaletteStyles {
 #[derive(Clone, Debug)]
 pub enum ColorPalette {
     RawJSON { map: serde_json::Map<String, Value> },
+    Ayu,
     Catppuccin,
     Dracula,
     Edge,
     Everforest,
+    Github,
     Gruvbox,
     GruvboxMaterial,
+    Horizon,
+    Kanagawa,
+    Material,
+    Monokai,
+    NightOwl,
     Nord,
     OneDark,
     RosePine,
     Solarized,
     TokyoNight,
+    Zenburn,
 }
 
+/// Names of the builtin palettes, as they are displayed
+pub const BUILTIN_PALETTES: [&str; 19] = [
+    "ayu",
+    "catppuccin",
+    "dracula",
+    "edge",
+    "everforest",
+    "github",
+    "gruvbox",
+    "gruvbox-material",
+    "horizon",
+    "kanagawa",
+    "material",
+    "monokai",
+    "night-owl",
+    "nord",
+    "onedark",
+    "rose-pine",
+    "solarized",
+    "tokyo-night",
+    "zenburn",
+];
+
 impl std::fmt::Display for ColorPalette {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ColorPalette::RawJSON { map } => {
                 write!(f, "JSON: {}", serde_json::to_string(map).unwrap())
             }
+            ColorPalette::Ayu => write!(f, "ayu"),
             ColorPalette::Catppuccin => write!(f, "catppuccin"),
             ColorPalette::Dracula => write!(f, "dracula"),
             ColorPalette::Edge => write!(f, "edge"),
             ColorPalette::Everforest => write!(f, "everforest"),
+            ColorPalette::Github => write!(f, "github"),
             ColorPalette::Gruvbox => write!(f, "gruvbox"),
             ColorPalette::GruvboxMaterial => write!(f, "gruvbox-material"),
+            ColorPalette::Horizon => write!(f, "horizon"),
+            ColorPalette::Kanagawa => write!(f, "kanagawa"),
+            ColorPalette::Material => write!(f, "material"),
+            ColorPalette::Monokai => write!(f, "monokai"),
+            ColorPalette::NightOwl => write!(f, "night-owl"),
             ColorPalette::Nord => write!(f, "nord"),
             ColorPalette::OneDark => write!(f, "onedark"),
             ColorPalette::RosePine => write!(f, "rose-pine"),
             ColorPalette::Solarized => write!(f, "solarized"),
             ColorPalette::TokyoNight => write!(f, "tokyo-night"),
+            ColorPalette::Zenburn => write!(f, "zenburn"),
         }
     }
 }
@@ -151,38 +1358,138 @@ impl FromStr for ColorPalette {
             };
             return Ok(ColorPalette::RawJSON { map });
         };
+        if crate::http::is_url(s) {
+            return read_palette_url(s);
+        }
 
         let palette = match s {
+            "ayu" => ColorPalette::Ayu,
             "catppuccin" => ColorPalette::Catppuccin,
             "dracula" => ColorPalette::Dracula,
             "edge" => ColorPalette::Edge,
             "everforest" => ColorPalette::Everforest,
+            "github" | "git-hub" => ColorPalette::Github,
             "gruvbox" => ColorPalette::Gruvbox,
             "gruvbox-material" | "gruvbox_material" | "gruvboxmaterial" => {
                 ColorPalette::GruvboxMaterial
             }
+            "horizon" => ColorPalette::Horizon,
+            "kanagawa" => ColorPalette::Kanagawa,
+            "material" => ColorPalette::Material,
+            "monokai" => ColorPalette::Monokai,
+            "night-owl" | "night_owl" | "nightowl" => ColorPalette::NightOwl,
             "nord" => ColorPalette::Nord,
             "onedark" | "one_dark" | "one-dark" => ColorPalette::OneDark,
             "rose-pine" | "rose_pine" | "rosepine" => ColorPalette::RosePine,
             "solarized" => ColorPalette::Solarized,
             "tokyo-night" | "tokyo_night" | "tokyonight" => ColorPalette::TokyoNight,
+            "zenburn" => ColorPalette::Zenburn,
 
             // The color palette seems to be the path to an external file
             external => {
-                let external: PathBuf = external.into();
-                if !external.is_file() {
-                    return Err(format!("Theme source file `{s}` appears to not be a file."));
+                let path: PathBuf = external.into();
+                if path.is_file() {
+                    return read_palette_file(&path);
                 };
-                let file = File::open(external).map_err(|err| err.to_string())?;
-                let file = BufReader::new(file);
-                let json = serde_json::from_reader(file)
-                    .map_err(|err| format!("Error while parsing JSON content of {s}: {err}"))?;
-                let Value::Object(map) = json else {
-                return Err("Encountered error while parsing JSON theme file: the contents of the file are valid JSON but do not appear to be a JSON object".to_string());
-            };
-                ColorPalette::RawJSON { map }
+                // Or the name of a palette in the user palette directory
+                if let Some(path) = user_palette_file(external) {
+                    return read_palette_file(&path);
+                }
+                let user_palettes = user_palette_names();
+                let candidates = BUILTIN_PALETTES
+                    .into_iter()
+                    .chain(user_palettes.iter().map(String::as_str));
+                return Err(format!(
+                    "Theme source file `{s}` appears to not be a file, and there is no `{s}.json` or `{s}.yaml` in the user palette directory{}.{}",
+                    crate::settings::user_palette_dir()
+                        .map(|dir| format!(" ({})", dir.display()))
+                        .unwrap_or_default(),
+                    crate::fuzzy::did_you_mean(s, candidates)
+                ));
             }
         };
         Ok(palette)
     }
 }
+
+/// Palettes larger than this are not downloaded
+const MAX_PALETTE_DOWNLOAD: u64 = 1024 * 1024;
+
+/// Palette files and URLs that were already parsed
+static PALETTE_CACHE: FileCache<ColorPalette> = FileCache::new();
+
+fn read_palette_url(url: &str) -> Result<ColorPalette, String> {
+    PALETTE_CACHE.get(Path::new(url), || download_palette(url))
+}
+
+/// Download a palette, parsed by the extension of the URL like a local file
+fn download_palette(url: &str) -> Result<ColorPalette, String> {
+    let body = crate::http::get(url, MAX_PALETTE_DOWNLOAD)?;
+    let file_name = crate::http::file_name(url);
+    if let Some(format) = crate::palette_formats::Format::from_file_name(file_name) {
+        let name = crate::palette_formats::palette_name(Path::new(file_name));
+        return crate::palette_formats::parse(format, &body, name)
+            .map(|map| ColorPalette::RawJSON { map })
+            .map_err(|err| format!("Failed to parse {url}: {err}"));
+    }
+    let text = String::from_utf8(body).map_err(|_| format!("{url} is not a text file"))?;
+    let json = serde_json::from_str(&text)
+        .map_err(|err| format!("Error while parsing JSON content of {url}: {err}"))?;
+    let Value::Object(map) = json else {
+        return Err(format!(
+            "The contents of {url} are valid JSON but not a JSON object"
+        ));
+    };
+    Ok(ColorPalette::RawJSON { map })
+}
+
+/// Look up `<name>.json` (or `.yaml`/`.yml`/`.toml`) in the user palette directory
+fn user_palette_file(name: &str) -> Option<PathBuf> {
+    // Only plain names, `dipc ../theme` should not resolve relative to the palette directory
+    if name.contains(std::path::is_separator) {
+        return None;
+    }
+    let dir = crate::settings::user_palette_dir()?;
+    ["json", "yaml", "yml", "toml"]
+        .into_iter()
+        .map(|extension| dir.join(format!("{name}.{extension}")))
+        .find(|path| path.is_file())
+}
+
+/// Names of the palettes in the user palette directory
+fn user_palette_names() -> Vec<String> {
+    let Some(entries) = crate::settings::user_palette_dir().and_then(|dir| fs::read_dir(dir).ok())
+    else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let extension = path.extension()?.to_str()?;
+            matches!(extension, "json" | "yaml" | "yml" | "toml")
+                .then(|| path.file_stem()?.to_str().map(str::to_string))?
+        })
+        .collect()
+}
+
+fn read_palette_file(path: &Path) -> Result<ColorPalette, String> {
+    PALETTE_CACHE.get(path, || parse_palette_file(path))
+}
+
+fn parse_palette_file(path: &Path) -> Result<ColorPalette, String> {
+    if let Some(map) = crate::palette_formats::read(path) {
+        return map.map(|map| ColorPalette::RawJSON { map });
+    }
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let file = BufReader::new(file);
+    let json = serde_json::from_reader(file).map_err(|err| {
+        format!(
+            "Error while parsing JSON content of {}: {err}",
+            path.display()
+        )
+    })?;
+    let Value::Object(map) = json else {
+        return Err("Encountered error while parsing JSON theme file: the contents of the file are valid JSON but do not appear to be a JSON object".to_string());
+    };
+    Ok(ColorPalette::RawJSON { map })
+}
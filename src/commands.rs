@@ -0,0 +1,79 @@
+use std::str::FromStr;
+
+use crate::{
+    cli::{ColorPalette, ColorPaletteStyles, Command, PalettesCommand},
+    config::{is_variation, parse_palette, Palette},
+    error::{DipcError, Result},
+};
+
+mod batch;
+mod convert_format;
+#[cfg(unix)]
+mod daemon;
+mod info;
+mod lut;
+mod palettes;
+mod simulate;
+mod suggest;
+mod theme;
+mod video;
+
+pub fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Palettes(PalettesCommand::Show { palette }) => palettes::show(&palette),
+        Command::Palettes(PalettesCommand::Validate { palette }) => palettes::validate(&palette),
+        Command::Palettes(PalettesCommand::Merge { palettes, output }) => {
+            palettes::merge(&palettes, output.as_deref())
+        }
+        Command::Palettes(PalettesCommand::Diff { old, new }) => palettes::diff(&old, &new),
+        Command::Palettes(PalettesCommand::Convert {
+            palette,
+            to,
+            styles,
+            output,
+        }) => palettes::convert(&palette, to, styles.as_ref(), output.as_deref()),
+        Command::Palettes(PalettesCommand::Contrast {
+            palette,
+            styles,
+            background,
+            min_ratio,
+        }) => palettes::contrast(&palette, styles.as_ref(), &background, min_ratio),
+        Command::Lut(args) => lut::run(&args),
+        Command::Theme(args) => theme::run(&args),
+        Command::Suggest(args) => suggest::run(&args),
+        Command::Info(args) => info::run(&args),
+        Command::Simulate(args) => simulate::run(&args),
+        Command::Batch(args) => batch::run(&args),
+        Command::Video(args) => video::run(&args),
+        Command::ConvertFormat(args) => convert_format::run(&args),
+        #[cfg(unix)]
+        Command::Daemon(args) => daemon::serve(&args),
+        #[cfg(unix)]
+        Command::Client(args) => daemon::send(&args),
+        #[cfg(not(unix))]
+        Command::Daemon(_) | Command::Client(_) => Err(DipcError::Usage(String::from(
+            "The daemon uses unix sockets, which are not available on this platform",
+        ))),
+    }
+}
+
+/// Parse the selected `styles` of a palette. Without `styles` every variation is used, or the
+/// palette itself if it is a flat theme.
+pub fn parse_palettes(palette: &str, styles: Option<&ColorPaletteStyles>) -> Result<Vec<Palette>> {
+    let palette = ColorPalette::from_str(palette).map_err(DipcError::Palette)?;
+    let aliases = palette.style_aliases();
+    let json = palette.get_json();
+    let styles = match styles {
+        Some(styles) => styles.clone(),
+        None if json.values().all(is_variation) => ColorPaletteStyles::All,
+        None => ColorPaletteStyles::None,
+    };
+    parse_palette(json, &styles, aliases).map_err(DipcError::Palette)
+}
+
+/// Only the message of a clap error, without the usage that refers to the command line
+pub fn clap_error(err: clap::Error) -> DipcError {
+    let message = err.to_string();
+    let message = message.lines().next().unwrap_or_default();
+    DipcError::Usage(message.trim_start_matches("error: ").to_string())
+}
@@ -0,0 +1,164 @@
+use std::{fs, path::Path};
+
+use clap::{CommandFactory, FromArgMatches};
+use toml::{Table, Value};
+use tracing::{error, info};
+
+use super::clap_error;
+use crate::{
+    cli::{BatchArgs, Cli},
+    error::{DipcError, Result},
+    settings::Settings,
+};
+
+/// Keys of a job that are arguments instead of options
+const POSITIONAL: [&str; 2] = ["palette", "input"];
+
+/// A batch file: options shared by every job and the jobs
+///
+/// ```toml
+/// [defaults]
+/// method = "de1976"
+/// dir_output = "converted"
+///
+/// [[job]]
+/// palette = "nord"
+/// input = ["a.png", "b.png"]
+/// styles = "Frost,Aurora"
+///
+/// [[job]]
+/// palette = "gruvbox"
+/// input = "c.jpg"
+/// output = "c-gruvbox.png"
+/// ```
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    defaults: Table,
+    #[serde(default, rename = "job")]
+    jobs: Vec<Table>,
+}
+
+pub fn run(args: &BatchArgs) -> Result<()> {
+    let manifest = read_manifest(&args.manifest)?;
+    let settings = Settings::load()?;
+    let total = manifest.jobs.len();
+
+    let mut failures = Vec::new();
+    for (idx, job) in manifest.jobs.iter().enumerate() {
+        let mut options = manifest.defaults.clone();
+        options.extend(job.clone());
+        let result = job_arguments(&options).and_then(|arguments| {
+            info!(
+                "[job {}/{total}] dipc {}",
+                idx + 1,
+                arguments[1..].join(" ")
+            );
+            let matches = Cli::command()
+                .try_get_matches_from(&arguments)
+                .map_err(clap_error)?;
+            let mut cli = Cli::from_arg_matches(&matches).map_err(clap_error)?;
+            settings.clone().apply(&mut cli, &matches)?;
+            crate::process(cli)
+        });
+        match result {
+            Ok(()) => {}
+            Err(DipcError::Cancelled) => return Err(DipcError::Cancelled),
+            Err(err) => {
+                let err = match err {
+                    DipcError::Usage(message) => {
+                        DipcError::Usage(format!("Job {}: {message}", idx + 1))
+                    }
+                    err => err,
+                };
+                if args.fail_fast {
+                    return Err(err);
+                }
+                error!("{err}");
+                failures.push((idx, err));
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+    error!("Failed jobs:");
+    for (idx, err) in &failures {
+        error!("  {}: {err}", idx + 1);
+    }
+    Err(DipcError::Batch {
+        failed: failures.len(),
+        total,
+        unit: "jobs",
+        exit_code: failures[0].1.exit_code(),
+    })
+}
+
+fn read_manifest(path: &Path) -> Result<Manifest> {
+    let content = fs::read_to_string(path).map_err(|err| {
+        DipcError::io(format!("Failed to read batch file {}", path.display()), err)
+    })?;
+    let manifest: Manifest = toml::from_str(&content).map_err(|err| {
+        DipcError::Usage(format!(
+            "Failed to parse batch file {}: {err}",
+            path.display()
+        ))
+    })?;
+    if manifest.jobs.is_empty() {
+        return Err(DipcError::Usage(format!(
+            "{} contains no [[job]]",
+            path.display()
+        )));
+    }
+    Ok(manifest)
+}
+
+/// The command line equivalent to a job: options first, then the palette and the images
+fn job_arguments(options: &Table) -> Result<Vec<String>> {
+    let mut arguments = vec![String::from("dipc")];
+    for (key, value) in options {
+        if POSITIONAL.contains(&key.as_str()) {
+            continue;
+        }
+        let option = format!("--{}", key.replace('_', "-"));
+        match value {
+            Value::Boolean(true) => arguments.push(option),
+            Value::Boolean(false) => {}
+            value => arguments.push(format!("{option}={}", option_value(key, value)?)),
+        }
+    }
+
+    let Some(palette) = options.get("palette") else {
+        return Err(DipcError::Usage(String::from("`palette` is missing")));
+    };
+    let inputs = match options.get("input") {
+        Some(Value::Array(inputs)) => inputs.iter().collect(),
+        Some(input) => vec![input],
+        None => return Err(DipcError::Usage(String::from("`input` is missing"))),
+    };
+    arguments.push(String::from("--"));
+    arguments.push(option_value("palette", palette)?);
+    for input in inputs {
+        arguments.push(option_value("input", input)?);
+    }
+    Ok(arguments)
+}
+
+/// A value as it would be given on the command line, lists are comma separated
+fn option_value(key: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(string) => Ok(string.clone()),
+        Value::Integer(integer) => Ok(integer.to_string()),
+        Value::Float(float) => Ok(float.to_string()),
+        Value::Array(values) => Ok(values
+            .iter()
+            .map(|value| option_value(key, value))
+            .collect::<Result<Vec<_>>>()?
+            .join(",")),
+        _ => Err(DipcError::Usage(format!(
+            "`{key}` must be a string, number, boolean or list, found {value}"
+        ))),
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::Path;
+
+use image::ImageFormat;
+use tracing::{error, info, warn};
+
+use crate::{
+    cli::ConvertFormatArgs,
+    convert_image_format::{self, OpenOptions, SaveOptions},
+    error::{DipcError, Result},
+};
+
+pub fn run(args: &ConvertFormatArgs) -> Result<()> {
+    if let Some(outputs) = &args.output {
+        if outputs.len() != args.images.len() {
+            return Err(DipcError::Usage(String::from(
+                "You need to provide the same amount of output image names/paths as input images",
+            )));
+        }
+    }
+    if let Some(dir) = &args.dir_output {
+        std::fs::create_dir_all(dir).map_err(|err| {
+            DipcError::io("Creating provided output directory failed with error", err)
+        })?;
+    }
+    let save_options = SaveOptions {
+        ico_sizes: args.sizes.clone(),
+        ..SaveOptions::default()
+    };
+
+    let mut failures = Vec::new();
+    for (idx, image) in args.images.iter().enumerate() {
+        let output = args.output.as_ref().map(|outputs| outputs[idx].as_path());
+        match convert(args, image, output, &save_options) {
+            Ok(()) => {}
+            Err(DipcError::Cancelled) => return Err(DipcError::Cancelled),
+            Err(err) if args.images.len() > 1 => {
+                error!("{err}");
+                failures.push((image, err));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+    error!("Failed images:");
+    for (path, err) in &failures {
+        error!("  {}: {err}", path.display());
+    }
+    Err(DipcError::Batch {
+        failed: failures.len(),
+        total: args.images.len(),
+        unit: "images",
+        exit_code: failures[0].1.exit_code(),
+    })
+}
+
+/// Save `image` as the format picked by the extension of `output` or `--to`
+fn convert(
+    args: &ConvertFormatArgs,
+    image: &Path,
+    output: Option<&Path>,
+    options: &SaveOptions,
+) -> Result<()> {
+    let format = output
+        .and_then(|output| ImageFormat::from_path(output).ok())
+        .or(args.to.map(ImageFormat::from))
+        .ok_or_else(|| {
+            DipcError::Usage(String::from(
+                "Pick the format with --to or the extension of the --output name",
+            ))
+        })?;
+    let extension = convert_image_format::extension(format);
+    let name = match output {
+        Some(output) if ImageFormat::from_path(output).is_ok() => output.to_path_buf(),
+        Some(output) => output.with_extension(extension),
+        None => Path::new(image.file_name().unwrap_or_default()).with_extension(extension),
+    };
+    let output = match &args.dir_output {
+        Some(dir) => dir.join(name),
+        None if args.output.is_some() => name,
+        None => image.with_file_name(name),
+    };
+    if output == image {
+        return Err(DipcError::Usage(format!(
+            "{} is already {extension}, give --output or --dir-output to write a copy",
+            image.display()
+        )));
+    }
+
+    let decoded = convert_image_format::open(image, &OpenOptions::default())?;
+    if decoded.frames.len() > 1 && !convert_image_format::supports_animation(format) {
+        warn!("{extension} can not store animations, only the first frame will be saved");
+    }
+    crate::save_image(&decoded, &output, format, options, crate::cancel_token())?;
+    info!("Saved image: {:?}", output.display());
+    Ok(())
+}
@@ -0,0 +1,276 @@
+use std::{
+    fs::{self, DirBuilder, Permissions},
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    thread,
+    time::Duration,
+};
+
+use clap::{CommandFactory, FromArgMatches};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use super::clap_error;
+use crate::{
+    cli::{Cli, ClientArgs, DaemonArgs},
+    error::{DipcError, Result},
+    exit_code, priority,
+    settings::Settings,
+};
+
+/// A conversion sent by `dipc client`, one JSON object per line
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    /// The working directory of the client, relative paths are resolved from it
+    cwd: PathBuf,
+    /// The command line arguments, without `dipc`
+    arguments: Vec<String>,
+}
+
+/// The result of a request, one JSON object per line
+#[derive(Debug, Serialize, Deserialize)]
+struct Response {
+    exit_code: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// What the conversion printed, e.g. `--stats` and `--metrics`, for the client's stdout
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    output: String,
+}
+
+/// Removes the socket when the daemon stops
+struct SocketFile<'a>(&'a Path);
+
+impl Drop for SocketFile<'_> {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(self.0);
+    }
+}
+
+pub fn serve(args: &DaemonArgs) -> Result<()> {
+    let path = args.socket.clone().unwrap_or_else(default_socket);
+    let settings = Settings::load()?;
+    // Like `dipc --nice`, before rayon starts its threads
+    let nice = args.nice || settings.nice == Some(true);
+    if nice {
+        priority::lower();
+    }
+    let listener = bind(&path)?;
+    let _socket = SocketFile(&path);
+
+    // Waiting for a connection can't be interrupted, so Ctrl-C wakes the daemon up by connecting
+    let cancel = crate::cancel_token();
+    thread::spawn({
+        let path = path.clone();
+        move || {
+            while !cancel.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(100));
+            }
+            let _ = UnixStream::connect(path);
+        }
+    });
+
+    info!("Listening on {}", path.display());
+    for stream in listener.incoming() {
+        if cancel.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Failed to accept a connection: {err}");
+                continue;
+            }
+        };
+        // Connections without a request only check whether the daemon is running
+        let request = match read_request(&stream) {
+            Ok(None) => continue,
+            Ok(Some(request)) => Ok(request),
+            Err(err) => Err(err),
+        };
+        let mut output = Vec::new();
+        let result = request.and_then(|request| handle(request, &settings, nice, &mut output));
+        let response = match result {
+            Ok(()) => Response {
+                exit_code: exit_code::SUCCESS,
+                error: None,
+                output: String::from_utf8_lossy(&output).into_owned(),
+            },
+            Err(err) => {
+                error!("{err}");
+                Response {
+                    exit_code: err.exit_code(),
+                    error: Some(err.to_string()),
+                    output: String::from_utf8_lossy(&output).into_owned(),
+                }
+            }
+        };
+        // The client may be gone already, e.g. after Ctrl-C
+        let _ = write_line(&stream, &response);
+        if cancel.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+    info!("Stopped listening on {}", path.display());
+    Ok(())
+}
+
+/// Send the arguments to the daemon and wait until it converted the images
+pub fn send(args: &ClientArgs) -> Result<()> {
+    let path = args.socket.clone().unwrap_or_else(default_socket);
+    let stream = UnixStream::connect(&path).map_err(|err| {
+        DipcError::io(
+            format!(
+                "Failed to connect to {}, is `dipc daemon` running?",
+                path.display()
+            ),
+            err,
+        )
+    })?;
+    let request = Request {
+        cwd: std::env::current_dir()
+            .map_err(|err| DipcError::io("Failed to read the current directory", err))?,
+        arguments: args.arguments.clone(),
+    };
+    write_line(&stream, &request)
+        .map_err(|err| DipcError::io("Failed to send the request to the daemon", err))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut line)
+        .map_err(|err| DipcError::io("Failed to read the response of the daemon", err))?;
+    let response: Response = serde_json::from_str(&line).map_err(|_| {
+        DipcError::io(
+            "The daemon closed the connection without a response",
+            std::io::ErrorKind::UnexpectedEof.into(),
+        )
+    })?;
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(response.output.as_bytes())
+        .and_then(|()| stdout.flush())?;
+    match response.error {
+        None => Ok(()),
+        Some(message) => Err(DipcError::Remote {
+            message,
+            exit_code: response.exit_code,
+        }),
+    }
+}
+
+fn read_request(stream: &UnixStream) -> Result<Option<Request>> {
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .map_err(|err| DipcError::io("Failed to read a request", err))?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_str(&line)
+        .map(Some)
+        .map_err(|err| DipcError::Usage(format!("Invalid request: {err}")))
+}
+
+/// Run the conversion of one request, like dipc would with its arguments. What it prints is
+/// collected in `output` and sent back to the client. `nice` tells whether the daemon runs at a
+/// lower priority.
+fn handle(request: Request, settings: &Settings, nice: bool, output: &mut Vec<u8>) -> Result<()> {
+    std::env::set_current_dir(&request.cwd).map_err(|err| {
+        DipcError::io(
+            format!("Failed to change directory to {}", request.cwd.display()),
+            err,
+        )
+    })?;
+    info!("dipc {}", request.arguments.join(" "));
+
+    let matches = Cli::command()
+        .try_get_matches_from(std::iter::once(String::from("dipc")).chain(request.arguments))
+        .map_err(clap_error)?;
+    let mut cli = Cli::from_arg_matches(&matches).map_err(clap_error)?;
+    // The daemon's own stdin and stdout are not the client's
+    if cli.command.is_some() || cli.stream || cli.raw_frames.is_some() {
+        return Err(DipcError::Usage(String::from(
+            "Only conversions of image files can be sent to the daemon",
+        )));
+    }
+    settings.clone().apply(&mut cli, &matches)?;
+    // The priority and the threads are the daemon's, a request can't lower them
+    if cli.nice && !nice {
+        return Err(DipcError::Usage(String::from(
+            "--nice has no effect on a running daemon, start it with `dipc daemon --nice`",
+        )));
+    }
+    crate::with_threads(cli.deterministic, || crate::process_to(cli, output))
+}
+
+fn bind(path: &Path) -> Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(DipcError::Usage(format!(
+                "{} already exists and is not a socket",
+                path.display()
+            )));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(DipcError::Usage(format!(
+                "A daemon is already listening on {}",
+                path.display()
+            )));
+        }
+        // Left behind by a daemon that did not stop cleanly
+        fs::remove_file(path).map_err(|err| {
+            DipcError::io(
+                format!("Failed to remove stale socket {}", path.display()),
+                err,
+            )
+        })?;
+    }
+
+    // Requests read and write files as the user running the daemon, nobody else may send them.
+    // The socket is created in a directory only the user can enter and moved into place once it
+    // is restricted, so nobody can connect before.
+    let private = path.with_file_name(format!(".dipc-{}", std::process::id()));
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&private)
+        .map_err(|err| DipcError::io(format!("Failed to create {}", private.display()), err))?;
+    let listener = bind_private(&private.join("dipc.sock"), path);
+    let _ = fs::remove_dir_all(&private);
+    listener
+}
+
+/// Listen on `private`, restrict it to the user and move it to `path`
+fn bind_private(private: &Path, path: &Path) -> Result<UnixListener> {
+    let listen_err = |err| DipcError::io(format!("Failed to listen on {}", path.display()), err);
+    let listener = UnixListener::bind(private).map_err(listen_err)?;
+    fs::set_permissions(private, Permissions::from_mode(0o600)).map_err(|err| {
+        DipcError::io(
+            format!("Failed to restrict access to {}", path.display()),
+            err,
+        )
+    })?;
+    fs::rename(private, path).map_err(listen_err)?;
+    Ok(listener)
+}
+
+fn write_line(mut stream: &UnixStream, message: &impl Serialize) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(message).expect("requests and responses are valid JSON");
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+/// `$XDG_RUNTIME_DIR/dipc.sock`, or a socket per user in the temporary directory
+fn default_socket() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("dipc.sock"),
+        _ => std::env::temp_dir().join(format!(
+            "dipc-{}.sock",
+            std::env::var("USER").unwrap_or_default()
+        )),
+    }
+}
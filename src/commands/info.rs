@@ -0,0 +1,93 @@
+use std::io::{stdout, Write};
+
+use owo_colors::OwoColorize;
+
+use crate::{
+    cli::InfoArgs,
+    convert_image_format::{self, Container, OpenOptions},
+    display,
+    error::Result,
+    extract,
+};
+
+pub fn run(args: &InfoArgs) -> Result<()> {
+    let input = convert_image_format::open(&args.image, &OpenOptions::default())?;
+    let (width, height) = input.dimensions();
+    let mut stdout = stdout().lock();
+
+    writeln!(stdout, "File:       {}", args.image.display())?;
+    writeln!(
+        stdout,
+        "Format:     {}",
+        convert_image_format::extension(input.format)
+    )?;
+    writeln!(stdout, "Dimensions: {width}x{height}")?;
+    writeln!(
+        stdout,
+        "Bit depth:  {}",
+        if input.deep.is_some() { 16 } else { 8 }
+    )?;
+    match input.container {
+        Container::Still => {}
+        Container::Gif { .. } | Container::Apng { .. } => {
+            writeln!(stdout, "Frames:     {}", input.frames.len())?
+        }
+    }
+    writeln!(stdout, "Alpha:      {}", alpha(&input.frames))?;
+    if input.metadata.icc.is_some() || input.metadata.exif.is_some() {
+        let metadata: Vec<&str> = [
+            input.metadata.icc.as_ref().map(|_| "ICC profile"),
+            input.metadata.exif.as_ref().map(|_| "EXIF"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        writeln!(stdout, "Metadata:   {}", metadata.join(", "))?;
+    }
+
+    let swatches = extract::dominant_colors(input.frames[0].buffer(), args.colors);
+    if swatches.is_empty() {
+        return Ok(());
+    }
+    writeln!(stdout, "Dominant colors:")?;
+    let truecolor = display::supports_truecolor();
+    for swatch in swatches {
+        let [r, g, b] = swatch.color.to_rgb();
+        if truecolor {
+            write!(stdout, "  {} ", "    ".on_truecolor(r, g, b))?;
+        } else {
+            write!(stdout, "  ")?;
+        }
+        writeln!(
+            stdout,
+            "#{r:02x}{g:02x}{b:02x}  {:5.1}%",
+            swatch.share * 100.0
+        )?;
+    }
+    Ok(())
+}
+
+/// How transparency is used by `frames`: not at all, fully transparent pixels only, or
+/// translucent pixels too
+fn alpha(frames: &[image::Frame]) -> String {
+    let pixels = frames.iter().flat_map(|frame| frame.buffer().pixels());
+    let (mut transparent, mut translucent, mut total) = (0usize, 0usize, 0usize);
+    for pixel in pixels {
+        match pixel[3] {
+            0 => transparent += 1,
+            u8::MAX => {}
+            _ => translucent += 1,
+        }
+        total += 1;
+    }
+    let share = |count: usize| count as f32 / total.max(1) as f32 * 100.0;
+    match (transparent, translucent) {
+        (0, 0) => String::from("none (fully opaque)"),
+        (_, 0) => format!("{:.1}% transparent", share(transparent)),
+        _ => format!(
+            "{:.1}% transparent, {:.1}% translucent",
+            share(transparent),
+            share(translucent)
+        ),
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use image::{ImageFormat, RgbImage};
+use indicatif::{ProgressBar, ProgressStyle};
+use tracing::info;
+
+use super::parse_palettes;
+use crate::{
+    cli::{ColorPalette, LutArgs, LutFormat},
+    convert,
+    error::{DipcError, Result},
+    lut,
+};
+
+pub fn run(args: &LutArgs) -> Result<()> {
+    let (sizes, default_size) = match args.format {
+        LutFormat::Cube => (2..=256, 33),
+        LutFormat::Hald => (2..=16, 8),
+    };
+    let size = args.size.unwrap_or(default_size);
+    if !sizes.contains(&size) {
+        return Err(DipcError::Usage(format!(
+            "--size must be between {} and {} for {:?} LUTs",
+            sizes.start(),
+            sizes.end(),
+            args.format
+        )));
+    }
+
+    let palettes = parse_palettes(&args.palette, args.styles.as_ref())?;
+    let palette_lab = convert::palette_lab(&palettes);
+    let weights = convert::palette_weights(&palettes);
+    let name = match ColorPalette::from_str(&args.palette) {
+        Ok(ColorPalette::RawJSON { .. }) | Err(_) => String::from("custom"),
+        Ok(palette) => palette.to_string(),
+    };
+    let output = args.output.clone().unwrap_or_else(|| {
+        PathBuf::from(name.clone()).with_extension(match args.format {
+            LutFormat::Cube => "cube",
+            LutFormat::Hald => "png",
+        })
+    });
+
+    // A HALD CLUT of level L holds L^2 entries per channel
+    let entries = match args.format {
+        LutFormat::Cube => size,
+        LutFormat::Hald => size * size,
+    };
+    info!("Rendering a {entries}x{entries}x{entries} LUT...");
+    let progress_bar = ProgressBar::new(u64::from(entries).pow(3));
+    progress_bar.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] [{wide_bar}] {pos}/{len} ({eta_precise})",
+        )
+        .expect("Failed to set progress bar style"),
+    );
+    let matcher = convert::Matcher {
+        palette: &palette_lab,
+        weights: &weights,
+        method: args.method.into(),
+        remap: None,
+        gradient: None,
+        regions: &[],
+    };
+    let table = convert::lut(entries, &matcher, args.linear_light, progress_bar.clone());
+    progress_bar.finish();
+
+    let write_err = |err| DipcError::io(format!("Failed to write LUT {:?}", output), err);
+    match args.format {
+        LutFormat::Cube => {
+            let mut writer = BufWriter::new(File::create(&output).map_err(write_err)?);
+            lut::write_cube(&mut writer, &name, size, &table)
+                .and_then(|_| writer.flush())
+                .map_err(write_err)?;
+        }
+        LutFormat::Hald => {
+            let side = size.pow(3);
+            RgbImage::from_raw(side, side, table.into_iter().flatten().collect())
+                .expect("A HALD CLUT has as many pixels as LUT entries")
+                .save_with_format(&output, ImageFormat::Png)
+                .map_err(|source| DipcError::Encode {
+                    path: output.clone(),
+                    source,
+                })?;
+        }
+    }
+    info!("Saved LUT: {:?}", output.display());
+    Ok(())
+}
@@ -0,0 +1,394 @@
+use std::{
+    fs,
+    io::{stdout, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use serde_json::{Map, Value};
+
+use super::parse_palettes;
+use crate::{
+    cli::{ColorPalette, ColorPaletteStyles, PaletteFormat},
+    config::{is_variation, Palette},
+    delta::{srgb_to_linear, Lab},
+    display,
+    error::{DipcError, Result},
+    palette_formats,
+};
+
+pub fn show(palette: &str) -> Result<()> {
+    let palettes = parse_palettes(palette, None)?;
+    let mut writer = BufWriter::new(stdout().lock());
+    display::ansi_paint_palette_details(&mut writer, &palettes, display::supports_truecolor())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// A problem found by `validate`, at a JSON pointer into the palette document
+struct Problem {
+    path: String,
+    message: String,
+}
+
+pub fn validate(palette: &str) -> Result<()> {
+    let json = ColorPalette::from_str(palette)
+        .map_err(DipcError::Palette)?
+        .get_json();
+
+    let mut problems = Vec::new();
+    let mut variations = 0;
+    let mut colors = 0;
+    // A palette is either a map of variations or a flat map of colors, decided by the majority
+    let objects = json.values().filter(|value| is_variation(value)).count();
+    if objects * 2 > json.len() {
+        for (name, value) in &json {
+            let path = format!("/{}", escape_pointer(name));
+            match value {
+                Value::Object(variation) if is_variation(value) => {
+                    variations += 1;
+                    colors += validate_colors(variation, &path, &mut problems);
+                }
+                _ => problems.push(Problem {
+                    path,
+                    message: String::from("Expected a variation (a JSON object of colors)"),
+                }),
+            }
+        }
+    } else {
+        variations = 1;
+        colors = validate_colors(&json, "", &mut problems);
+    }
+
+    let mut writer = BufWriter::new(stdout().lock());
+    for problem in &problems {
+        writeln!(writer, "{}: {}", problem.path, problem.message)?;
+    }
+    let result = if problems.is_empty() {
+        writeln!(
+            writer,
+            "{variations} variation(s), {colors} colors, no problems found"
+        )?;
+        Ok(())
+    } else {
+        Err(DipcError::Palette(format!(
+            "{} problem(s) found in {variations} variation(s) with {colors} colors",
+            problems.len()
+        )))
+    };
+    writer.flush()?;
+    result
+}
+
+/// Check every color of `variation` on its own, returning the number of valid colors
+fn validate_colors(
+    variation: &Map<String, Value>,
+    path: &str,
+    problems: &mut Vec<Problem>,
+) -> usize {
+    if variation.is_empty() {
+        problems.push(Problem {
+            path: path.to_string(),
+            message: String::from("Contains no colors"),
+        });
+    }
+    let mut valid = 0;
+    for (name, value) in variation {
+        let path = format!("{path}/{}", escape_pointer(name));
+        let mut problem = |message: String| {
+            problems.push(Problem {
+                path: path.clone(),
+                message,
+            })
+        };
+        match value {
+            Value::String(_) | Value::Array(_) | Value::Object(_) => {}
+            other => {
+                problem(format!(
+                    "Expected a color as `#HEX`, `[r, g, b]`, `{{\"r\": 255, \"g\": 128, \"b\": 0}}` or `{{\"hex\": \"#HEX\", \"weight\": 2.0}}`, found {other}"
+                ));
+                continue;
+            }
+        }
+        let unknown_keys: Vec<&String> = value
+            .as_object()
+            .map(|channels| {
+                channels
+                    .keys()
+                    .filter(|key| !matches!(key.as_str(), "r" | "g" | "b" | "hex" | "weight"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        if !unknown_keys.is_empty() {
+            problem(format!("Unknown keys in color object: {unknown_keys:?}"));
+        }
+        // The exact parsing used for conversions, one color at a time
+        match Palette::try_from(Map::from_iter([(name.clone(), value.clone())])) {
+            Ok(_) if unknown_keys.is_empty() => valid += 1,
+            Ok(_) => {}
+            Err(err) => problem(err),
+        }
+    }
+    valid
+}
+
+pub fn merge(sources: &[String], output: Option<&Path>) -> Result<()> {
+    let parsed = sources
+        .iter()
+        .map(|source| Ok((source, parse_palettes(source, None)?)))
+        .collect::<Result<Vec<_>>>()?;
+    // Flat palettes are kept flat when merged with each other, and become a variation named after
+    // their source otherwise
+    let flat = parsed
+        .iter()
+        .flat_map(|(_, palettes)| palettes)
+        .all(|palette| palette.name.is_none());
+
+    let mut merged: Vec<Palette> = Vec::new();
+    for (source, palettes) in parsed {
+        for mut palette in palettes {
+            if palette.name.is_none() && !flat {
+                palette.name = Some(palette_formats::palette_name(Path::new(source)));
+            }
+            match merged.iter_mut().find(|merged| merged.name == palette.name) {
+                Some(merged) => merge_colors(merged, palette),
+                None => merged.push(palette),
+            }
+        }
+    }
+
+    let json = serde_json::to_string_pretty(&palette_formats::to_json(&merged))
+        .expect("A palette can always be serialized");
+    match output {
+        Some(path) => fs::write(path, json + "\n")
+            .map_err(|err| DipcError::io(format!("Failed to write palette {:?}", path), err)),
+        None => Ok(writeln!(stdout().lock(), "{json}")?),
+    }
+}
+
+/// Add the colors of `other` to `palette`, replacing the colors of the same name
+fn merge_colors(palette: &mut Palette, other: Palette) {
+    for (name, color) in other.colors {
+        palette.weights.remove(&name);
+        match palette
+            .colors
+            .iter_mut()
+            .find(|(existing, _)| *existing == name)
+        {
+            Some((_, existing)) => *existing = color,
+            None => palette.colors.push((name, color)),
+        }
+    }
+    palette.weights.extend(other.weights);
+}
+
+pub fn convert(
+    palette: &str,
+    format: PaletteFormat,
+    styles: Option<&ColorPaletteStyles>,
+    output: Option<&Path>,
+) -> Result<()> {
+    let palettes = parse_palettes(palette, styles)?;
+    let name = palette_formats::palette_name(Path::new(palette));
+    let bytes = palette_formats::write(format, &palettes, &name).map_err(DipcError::Palette)?;
+    match output {
+        Some(path) => fs::write(path, bytes)
+            .map_err(|err| DipcError::io(format!("Failed to write palette {:?}", path), err)),
+        None => Ok(stdout().lock().write_all(&bytes)?),
+    }
+}
+
+pub fn diff(old: &str, new: &str) -> Result<()> {
+    let old = parse_palettes(old, None)?;
+    let new = parse_palettes(new, None)?;
+    let hex = |[r, g, b]: [u8; 3]| format!("#{r:02x}{g:02x}{b:02x}");
+    let variation_name = |palette: &Palette| palette.name.clone().unwrap_or_default();
+
+    let mut writer = BufWriter::new(stdout().lock());
+    let mut differences = 0;
+    for palette in &old {
+        if !new.iter().any(|other| other.name == palette.name) {
+            writeln!(writer, "- variation {}", variation_name(palette))?;
+            differences += 1;
+        }
+    }
+    for palette in &new {
+        let Some(old) = old.iter().find(|other| other.name == palette.name) else {
+            writeln!(writer, "+ variation {}", variation_name(palette))?;
+            differences += 1;
+            continue;
+        };
+        let mut lines = Vec::new();
+        for (name, color) in &old.colors {
+            if !palette.colors.iter().any(|(other, _)| other == name) {
+                lines.push(format!("- {name}  {}", hex(color.0)));
+            }
+        }
+        for (name, color) in &palette.colors {
+            match old.colors.iter().find(|(other, _)| other == name) {
+                None => lines.push(format!("+ {name}  {}", hex(color.0))),
+                Some((_, old_color)) if old_color != color => {
+                    let delta_e = deltae::DeltaE::new(
+                        Lab::from(old_color.0),
+                        Lab::from(color.0),
+                        deltae::DEMethod::DE2000,
+                    );
+                    lines.push(format!(
+                        "~ {name}  {} -> {}  (DeltaE {:.2})",
+                        hex(old_color.0),
+                        hex(color.0),
+                        delta_e.value()
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+        if lines.is_empty() {
+            continue;
+        }
+        differences += lines.len();
+        if let Some(name) = &palette.name {
+            writeln!(writer, "{name}")?;
+        }
+        for line in lines {
+            writeln!(writer, "  {line}")?;
+        }
+    }
+    if differences == 0 {
+        writeln!(writer, "The palettes have the same colors")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Normalized names of the main background color in the builtin palettes, including both
+/// backgrounds of Solarized
+const BACKGROUND_NAMES: [&str; 7] = [
+    "background",
+    "bg",
+    "bg0",
+    "base",
+    "canvas",
+    "base03",
+    "base3",
+];
+
+/// Parts of the names of colors that are only used behind text, never compared as foregrounds
+const BACKGROUND_PARTS: [&str; 7] = [
+    "background",
+    "canvas",
+    "crust",
+    "highlight",
+    "mantle",
+    "selection",
+    "surface",
+];
+
+pub fn contrast(
+    palette: &str,
+    styles: Option<&ColorPaletteStyles>,
+    backgrounds: &[String],
+    min_ratio: f32,
+) -> Result<()> {
+    let palettes = parse_palettes(palette, styles)?;
+    let hex = |[r, g, b]: [u8; 3]| format!("#{r:02x}{g:02x}{b:02x}");
+    let normalize = |name: &str| name.to_lowercase().replace(['_', '-', ' '], "");
+
+    let mut writer = BufWriter::new(stdout().lock());
+    let mut pairs = 0;
+    let mut failing = 0;
+    for palette in &palettes {
+        let background_colors: Vec<&(String, image::Rgb<u8>)> = if backgrounds.is_empty() {
+            let named: Vec<_> = palette
+                .colors
+                .iter()
+                .filter(|(name, _)| BACKGROUND_NAMES.contains(&normalize(name).as_str()))
+                .collect();
+            match named.is_empty() {
+                true => fallback_background(&palette.colors).into_iter().collect(),
+                false => named,
+            }
+        } else {
+            let mut colors = Vec::new();
+            for background in backgrounds {
+                match palette.colors.iter().find(|(name, _)| name == background) {
+                    Some(color) => colors.push(color),
+                    None => {
+                        return Err(DipcError::Palette(format!(
+                            "No color named {background:?} in {}",
+                            palette.name.as_deref().unwrap_or("the palette")
+                        )))
+                    }
+                }
+            }
+            colors
+        };
+
+        if let Some(name) = &palette.name {
+            writeln!(writer, "{name}")?;
+        }
+        for (background_name, background) in &background_colors {
+            writeln!(writer, "  on {background_name}  {}", hex(background.0))?;
+            let background_luminance = relative_luminance(background.0);
+            for (name, color) in &palette.colors {
+                let normalized = normalize(name);
+                if background_colors.iter().any(|(other, _)| other == name)
+                    || normalized.starts_with("bg")
+                    || BACKGROUND_PARTS
+                        .iter()
+                        .any(|part| normalized.contains(part))
+                {
+                    continue;
+                }
+                let luminance = relative_luminance(color.0);
+                let ratio = (luminance.max(background_luminance) + 0.05)
+                    / (luminance.min(background_luminance) + 0.05);
+                pairs += 1;
+                if ratio < min_ratio {
+                    failing += 1;
+                    writeln!(
+                        writer,
+                        "    {name}  {}  {ratio:.2}:1  below {min_ratio}:1",
+                        hex(color.0)
+                    )?;
+                } else {
+                    writeln!(writer, "    {name}  {}  {ratio:.2}:1", hex(color.0))?;
+                }
+            }
+        }
+    }
+    writeln!(
+        writer,
+        "{failing} of {pairs} pair(s) below a contrast ratio of {min_ratio}:1"
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// The darkest color of a mostly dark palette or the lightest color of a mostly light one
+fn fallback_background(colors: &[(String, image::Rgb<u8>)]) -> Option<&(String, image::Rgb<u8>)> {
+    let mean = colors
+        .iter()
+        .map(|(_, color)| relative_luminance(color.0))
+        .sum::<f32>()
+        / colors.len() as f32;
+    let by_luminance = |(_, a): &&(String, image::Rgb<u8>), (_, b): &&(String, image::Rgb<u8>)| {
+        relative_luminance(a.0).total_cmp(&relative_luminance(b.0))
+    };
+    // The luminance of a mid gray, L* 50
+    match mean < 0.18 {
+        true => colors.iter().min_by(by_luminance),
+        false => colors.iter().max_by(by_luminance),
+    }
+}
+
+/// The relative luminance of an sRGB color as defined by WCAG 2
+fn relative_luminance([r, g, b]: [u8; 3]) -> f32 {
+    let [r, g, b] = [r, g, b].map(|c| srgb_to_linear(c as f32 / 255.0));
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// Escape a key for use in a JSON pointer (RFC 6901)
+fn escape_pointer(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
@@ -0,0 +1,41 @@
+use tracing::info;
+
+use crate::{
+    cli::SimulateArgs,
+    convert_image_format::{self, OpenOptions, SaveOptions},
+    error::{DipcError, Result},
+    postprocess,
+};
+
+pub fn run(args: &SimulateArgs) -> Result<()> {
+    let mut image = convert_image_format::open(&args.image, &OpenOptions::default())?;
+    for frame in &mut image.frames {
+        postprocess::simulate(frame.buffer_mut(), args.deficiency);
+    }
+    if let Some(deep) = &mut image.deep {
+        postprocess::simulate(deep, args.deficiency);
+    }
+
+    let format = image.output_format(None);
+    let output = args.output.clone().unwrap_or_else(|| {
+        let stem = args
+            .image
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        args.image.with_file_name(format!(
+            "{stem}_{}.{}",
+            args.deficiency,
+            convert_image_format::extension(format)
+        ))
+    });
+    let format = image::ImageFormat::from_path(&output).unwrap_or(format);
+    convert_image_format::save(&image, &output, format, &SaveOptions::default()).map_err(
+        |source| DipcError::Encode {
+            path: output.clone(),
+            source,
+        },
+    )?;
+    info!("Saved image: {:?}", output.display());
+    Ok(())
+}
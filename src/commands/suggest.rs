@@ -0,0 +1,82 @@
+use std::io::{stdout, Write};
+
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    cli::{SuggestArgs, BUILTIN_PALETTES},
+    commands::parse_palettes,
+    convert_image_format::{self, OpenOptions},
+    delta::Lab,
+    error::Result,
+    extract,
+};
+
+/// Images are scaled down to at most this many pixels on their longest side before matching
+const SAMPLE_SIZE: u32 = 128;
+
+/// A palette variation and the mean DeltaE of the image mapped to it
+struct Fit {
+    palette: &'static str,
+    variation: Option<String>,
+    delta_e: f32,
+}
+
+pub fn run(args: &SuggestArgs) -> Result<()> {
+    let input = convert_image_format::open(&args.image, &OpenOptions::default())?;
+    let pixels = extract::sample(input.frames[0].buffer(), SAMPLE_SIZE);
+    let method = deltae::DEMethod::from(args.method);
+
+    let mut fits = Vec::new();
+    for palette in BUILTIN_PALETTES {
+        for variation in parse_palettes(palette, None)? {
+            let colors: Vec<Lab> = variation
+                .colors
+                .iter()
+                .map(|(_, color)| Lab::from(color.0))
+                .collect();
+            fits.push(Fit {
+                palette,
+                variation: variation.name,
+                delta_e: mean_delta_e(&pixels, &colors, method),
+            });
+        }
+    }
+    fits.sort_by(|a, b| a.delta_e.total_cmp(&b.delta_e));
+
+    let mut stdout = stdout().lock();
+    let names: Vec<String> = fits
+        .iter()
+        .map(|fit| match &fit.variation {
+            Some(variation) => format!("{} --styles {variation:?}", fit.palette),
+            None => fit.palette.to_string(),
+        })
+        .collect();
+    let max_name = names.iter().map(String::len).max().unwrap_or_default();
+    let top = args.top.unwrap_or(fits.len());
+    for (rank, (fit, name)) in fits.iter().zip(&names).take(top).enumerate() {
+        writeln!(
+            stdout,
+            "{:>3}. {name:<max_name$}  {:6.2}",
+            rank + 1,
+            fit.delta_e
+        )?;
+    }
+    Ok(())
+}
+
+/// Mean DeltaE between `pixels` and their nearest color of `palette`
+fn mean_delta_e(pixels: &[Lab], palette: &[Lab], method: deltae::DEMethod) -> f32 {
+    if pixels.is_empty() || palette.is_empty() {
+        return f32::MAX;
+    }
+    let sum: f64 = pixels
+        .par_iter()
+        .map(|&pixel| {
+            palette
+                .iter()
+                .map(|&color| *deltae::DeltaE::new(pixel, color, method).value())
+                .fold(f32::MAX, f32::min) as f64
+        })
+        .sum();
+    (sum / pixels.len() as f64) as f32
+}
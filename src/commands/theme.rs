@@ -0,0 +1,198 @@
+use std::{
+    fs,
+    io::{stdout, Write},
+    path::Path,
+};
+
+use serde_json::json;
+
+use crate::{
+    cli::{ThemeArgs, ThemeFormat},
+    convert_image_format::{self, OpenOptions},
+    delta::Lab,
+    error::{DipcError, Result},
+    extract::{self, Swatch},
+};
+
+/// Hues (LCh, degrees) the accent colors are pulled towards
+const RED: f32 = 30.0;
+const ORANGE: f32 = 60.0;
+const YELLOW: f32 = 90.0;
+const GREEN: f32 = 140.0;
+const CYAN: f32 = 200.0;
+const BLUE: f32 = 270.0;
+const MAGENTA: f32 = 330.0;
+/// How far an accent may stray from its hue to stay closer to the colors of the image
+const HUE_TOLERANCE: f32 = 12.0;
+
+/// Background, foreground and accents derived from an image
+struct Theme {
+    dark: bool,
+    background: Lab,
+    foreground: Lab,
+}
+
+impl Theme {
+    fn from_swatches(swatches: &[Swatch]) -> Theme {
+        let lightness = |swatch: &&Swatch| swatch.color.to_lch()[0];
+        let average: f32 = swatches
+            .iter()
+            .map(|swatch| lightness(&swatch) * swatch.share)
+            .sum();
+        let dark = average < 55.0;
+        let darkest = swatches
+            .iter()
+            .min_by(|a, b| lightness(a).total_cmp(&lightness(b)));
+        let lightest = swatches
+            .iter()
+            .max_by(|a, b| lightness(a).total_cmp(&lightness(b)));
+        let (background, foreground) = if dark {
+            (darkest, lightest)
+        } else {
+            (lightest, darkest)
+        };
+        // Tone the colors down until text is readable on the background
+        let (background_range, foreground_range) = if dark {
+            ((4.0, 14.0), (82.0, 94.0))
+        } else {
+            ((90.0, 98.0), (16.0, 30.0))
+        };
+        let adjust = |swatch: Option<&Swatch>, (min, max): (f32, f32), chroma: f32| {
+            let [l, c, h] =
+                swatch
+                    .map(|swatch| swatch.color.to_lch())
+                    .unwrap_or([(min + max) / 2.0, 0.0, 0.0]);
+            Lab::from_lch([l.clamp(min, max), c.min(chroma), h])
+        };
+        Theme {
+            dark,
+            background: adjust(background, background_range, 12.0),
+            foreground: adjust(foreground, foreground_range, 10.0),
+        }
+    }
+
+    /// An accent of `hue`, based on the most colorful swatch close to it
+    fn accent(&self, swatches: &[Swatch], hue: f32, bright: bool) -> Lab {
+        let distance = |h: f32| {
+            let diff = (h - hue).rem_euclid(360.0);
+            diff.min(360.0 - diff)
+        };
+        let closest = swatches
+            .iter()
+            .map(|swatch| swatch.color.to_lch())
+            .filter(|[_, c, _]| *c > 8.0)
+            .min_by(|a, b| (distance(a[2]) - a[1] / 4.0).total_cmp(&(distance(b[2]) - b[1] / 4.0)));
+        let (chroma, h) = match closest {
+            Some([_, c, h]) => {
+                let offset = ((h - hue + 180.0).rem_euclid(360.0) - 180.0)
+                    .clamp(-HUE_TOLERANCE, HUE_TOLERANCE);
+                (c.clamp(35.0, 75.0), hue + offset)
+            }
+            None => (45.0, hue),
+        };
+        let l = match (self.dark, bright) {
+            (true, false) => 60.0,
+            (true, true) => 72.0,
+            (false, false) => 45.0,
+            (false, true) => 35.0,
+        };
+        Lab::from_lch([l, chroma, h.rem_euclid(360.0)])
+    }
+
+    /// A shade between the background (0.0) and the foreground (1.0), extrapolated past them
+    fn shade(&self, t: f32) -> Lab {
+        let [bl, ba, bb] = self.background.to_array();
+        let [fl, fa, fb] = self.foreground.to_array();
+        Lab::new(
+            (bl + (fl - bl) * t).clamp(0.0, 100.0),
+            ba + (fa - ba) * t,
+            bb + (fb - bb) * t,
+        )
+    }
+
+    /// The 16 ANSI colors: black, red, green, yellow, blue, magenta, cyan, white, then their
+    /// bright variants
+    fn ansi(&self, swatches: &[Swatch]) -> Vec<Lab> {
+        let hues = [RED, GREEN, YELLOW, BLUE, MAGENTA, CYAN];
+        let mut colors = Vec::with_capacity(16);
+        for bright in [false, true] {
+            colors.push(self.shade(if bright { 0.3 } else { 0.0 }));
+            colors.extend(hues.map(|hue| self.accent(swatches, hue, bright)));
+            colors.push(self.shade(if bright { 1.08 } else { 0.85 }));
+        }
+        colors
+    }
+
+    /// base00 to base0F: eight shades from background to foreground, then the accents
+    fn base16(&self, swatches: &[Swatch]) -> Vec<Lab> {
+        let mut colors: Vec<Lab> = [0.0, 0.08, 0.18, 0.4, 0.7, 0.9, 1.0, 1.08]
+            .map(|t| self.shade(t))
+            .to_vec();
+        colors.extend(
+            [RED, ORANGE, YELLOW, GREEN, CYAN, BLUE, MAGENTA]
+                .map(|hue| self.accent(swatches, hue, false)),
+        );
+        // base0F, deprecated or embedded content, is usually a brown
+        let [l, c, h] = self.accent(swatches, ORANGE, false).to_lch();
+        colors.push(Lab::from_lch([l * 0.75, c * 0.6, h]));
+        colors
+    }
+}
+
+fn hex(color: Lab) -> String {
+    let [r, g, b] = color.to_rgb();
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+pub fn run(args: &ThemeArgs) -> Result<()> {
+    let input = convert_image_format::open(&args.image, &OpenOptions::default())?;
+    let swatches = extract::dominant_colors(input.frames[0].buffer(), 16);
+    let theme = Theme::from_swatches(&swatches);
+
+    let output = match args.format {
+        ThemeFormat::Pywal => pywal(&theme, &swatches, &args.image),
+        ThemeFormat::Base16 => base16(&theme, &swatches, &args.image),
+    };
+    match &args.output {
+        Some(path) => fs::write(path, output)
+            .map_err(|err| DipcError::io(format!("Failed to write theme {:?}", path), err)),
+        None => Ok(stdout().lock().write_all(output.as_bytes())?),
+    }
+}
+
+/// The `colors.json` format written by pywal
+fn pywal(theme: &Theme, swatches: &[Swatch], image: &Path) -> String {
+    let colors: serde_json::Map<String, serde_json::Value> = theme
+        .ansi(swatches)
+        .into_iter()
+        .enumerate()
+        .map(|(idx, color)| (format!("color{idx}"), hex(color).into()))
+        .collect();
+    let wallpaper = image.canonicalize().unwrap_or_else(|_| image.to_path_buf());
+    let json = json!({
+        "wallpaper": wallpaper,
+        "alpha": "100",
+        "special": {
+            "background": hex(theme.background),
+            "foreground": hex(theme.foreground),
+            "cursor": hex(theme.foreground),
+        },
+        "colors": colors,
+    });
+    let mut output = serde_json::to_string_pretty(&json).expect("Theme JSON is serializable");
+    output.push('\n');
+    output
+}
+
+/// A Base16 scheme in YAML
+fn base16(theme: &Theme, swatches: &[Swatch], image: &Path) -> String {
+    let name = image
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let mut output = format!("scheme: \"{}\"\nauthor: \"dipc\"\n", name.replace('"', "'"));
+    for (idx, color) in theme.base16(swatches).into_iter().enumerate() {
+        output.push_str(&format!("base{idx:02X}: \"{}\"\n", &hex(color)[1..]));
+    }
+    output
+}
@@ -0,0 +1,244 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread::{self, JoinHandle},
+};
+
+use tracing::info;
+
+use crate::{
+    cli::VideoArgs,
+    error::{DipcError, Result},
+    palette_formats,
+};
+
+/// Options that would make the converted frames a different size than ffmpeg expects
+const RESIZING: [&str; 5] = ["--crop", "--fit", "--resize", "--scale", "--fast-preview"];
+
+/// The video stream of a file as reported by ffprobe
+struct VideoStream {
+    width: u32,
+    height: u32,
+    /// Frames per second as a fraction, e.g. `30000/1001`
+    frame_rate: String,
+}
+
+/// Convert a video by piping its frames from ffmpeg through `dipc --raw-frames` and back into
+/// ffmpeg, which copies the audio from the original
+pub fn run(args: &VideoArgs) -> Result<()> {
+    if let Some(option) = args
+        .options
+        .iter()
+        .find(|option| RESIZING.contains(&option.split('=').next().unwrap_or_default()))
+    {
+        return Err(DipcError::Usage(format!(
+            "{option} changes the size of the frames and can't be used for videos"
+        )));
+    }
+    let stream = probe(&args.input)?;
+    let output = args.output.clone().unwrap_or_else(|| {
+        let stem = args.input.file_stem().unwrap_or_default().to_string_lossy();
+        let palette = palette_formats::palette_name(Path::new(&args.palette));
+        let extension = args.input.extension().unwrap_or_default().to_string_lossy();
+        args.input
+            .with_file_name(format!("{stem}_{palette}.{extension}"))
+    });
+    let size = format!("{}x{}", stream.width, stream.height);
+    info!(
+        "Converting {} ({size}, {} fps)...",
+        args.input.display(),
+        stream.frame_rate
+    );
+
+    let mut decoder = Ffmpeg::spawn(
+        Command::new("ffmpeg")
+            .args(["-v", "error", "-i"])
+            .arg(&args.input)
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba", "-"])
+            .stdout(Stdio::piped()),
+        "decoding",
+    )?;
+    let exe = std::env::current_exe()
+        .map_err(|err| DipcError::io("Failed to find the dipc executable", err))?;
+    let mut converter = spawn(
+        Command::new(exe)
+            .args(&args.options)
+            .args(["--raw-frames", &size, "--", &args.palette])
+            .stdin(decoder.child.stdout.take().expect("Piped stdout"))
+            .stdout(Stdio::piped()),
+    )?;
+    let mut encoder = Command::new("ffmpeg");
+    encoder
+        .args(["-v", "error", "-y", "-f", "rawvideo", "-pix_fmt", "rgba"])
+        .args(["-s", &size, "-r", &stream.frame_rate, "-i", "-", "-i"])
+        .arg(&args.input)
+        .args(["-map", "0:v", "-map", "1:a?", "-c:a", "copy"]);
+    // The most widely playable pixel format needs even dimensions
+    if stream.width % 2 == 0 && stream.height % 2 == 0 {
+        encoder.args(["-pix_fmt", "yuv420p"]);
+    }
+    // The video is encoded to a temporary file first so a failed conversion never leaves a
+    // truncated video behind under the final name
+    let partial_path = partial_path(&output);
+    let encoder = Ffmpeg::spawn(
+        encoder
+            .arg(&partial_path)
+            .stdin(converter.stdout.take().expect("Piped stdout")),
+        "encoding",
+    );
+    let encoder = match encoder {
+        Ok(encoder) => encoder,
+        Err(err) => {
+            let _ = converter.kill();
+            let _ = decoder.child.kill();
+            return Err(err);
+        }
+    };
+
+    // When the converter fails, the decoder and encoder fail because of it
+    let saved = [wait(&mut converter), decoder.wait(), encoder.wait()]
+        .into_iter()
+        .collect::<Result<()>>()
+        .and_then(|()| {
+            fs::rename(&partial_path, &output).map_err(|err| {
+                DipcError::io(
+                    format!("Failed to move video into place at {:?}", output.display()),
+                    err,
+                )
+            })
+        });
+    if saved.is_err() {
+        let _ = fs::remove_file(&partial_path);
+    }
+    saved?;
+    info!("Saved video: {:?}", output.display());
+    Ok(())
+}
+
+/// `name.part.ext` next to `output`, the extension is kept because ffmpeg picks the container
+/// format by it
+fn partial_path(output: &Path) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    match output.extension() {
+        Some(extension) => {
+            output.with_file_name(format!("{stem}.part.{}", extension.to_string_lossy()))
+        }
+        None => output.with_file_name(format!("{stem}.part")),
+    }
+}
+
+/// A running ffmpeg, its error output is read in the background so a full pipe never blocks it
+struct Ffmpeg {
+    child: Child,
+    /// `decoding` or `encoding`
+    step: &'static str,
+    stderr: JoinHandle<String>,
+}
+
+impl Ffmpeg {
+    fn spawn(command: &mut Command, step: &'static str) -> Result<Self> {
+        let mut child = spawn(command.stderr(Stdio::piped()))?;
+        let mut stderr = child.stderr.take().expect("Piped stderr");
+        let stderr = thread::spawn(move || {
+            let mut text = String::new();
+            let _ = stderr.read_to_string(&mut text);
+            text
+        });
+        Ok(Ffmpeg {
+            child,
+            step,
+            stderr,
+        })
+    }
+
+    fn wait(mut self) -> Result<()> {
+        let status = self.child.wait().map_err(|err| {
+            DipcError::io(format!("Failed to wait for ffmpeg ({})", self.step), err)
+        })?;
+        let stderr = self.stderr.join().unwrap_or_default();
+        if status.success() {
+            return Ok(());
+        }
+        Err(DipcError::Ffmpeg {
+            step: self.step,
+            status,
+            stderr: stderr.trim().to_string(),
+        })
+    }
+}
+
+/// Read the size and frame rate of the first video stream of `input` with ffprobe
+fn probe(input: &Path) -> Result<VideoStream> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0", "-show_entries"])
+        .args(["stream=width,height,r_frame_rate", "-of", "csv=p=0"])
+        .arg(input)
+        .output()
+        .map_err(missing_ffmpeg)?;
+    if !output.status.success() {
+        return Err(DipcError::io(
+            format!(
+                "ffprobe could not read {}: {}",
+                input.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            io::ErrorKind::InvalidData.into(),
+        ));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fields: Vec<&str> = stdout.trim().split(',').collect();
+    match fields.as_slice() {
+        [width, height, frame_rate] => match (width.parse(), height.parse()) {
+            (Ok(width), Ok(height)) => Ok(VideoStream {
+                width,
+                height,
+                frame_rate: frame_rate.to_string(),
+            }),
+            _ => Err(no_video(input)),
+        },
+        _ => Err(no_video(input)),
+    }
+}
+
+fn spawn(command: &mut Command) -> Result<Child> {
+    command.spawn().map_err(missing_ffmpeg)
+}
+
+/// Wait for the dipc converting the frames
+fn wait(converter: &mut Child) -> Result<()> {
+    let status = converter
+        .wait()
+        .map_err(|err| DipcError::io("Failed to wait for dipc", err))?;
+    match status.code() {
+        Some(0) => Ok(()),
+        // dipc reported its error already, only its exit code is kept
+        Some(code) => Err(DipcError::Batch {
+            failed: 1,
+            total: 1,
+            unit: "videos",
+            exit_code: code,
+        }),
+        None => Err(DipcError::io(
+            format!("dipc failed with {status}"),
+            io::ErrorKind::Other.into(),
+        )),
+    }
+}
+
+fn missing_ffmpeg(err: io::Error) -> DipcError {
+    match err.kind() {
+        io::ErrorKind::NotFound => DipcError::Usage(String::from(
+            "Converting videos needs ffmpeg and ffprobe, which were not found on the PATH",
+        )),
+        _ => DipcError::io("Failed to run ffmpeg", err),
+    }
+}
+
+fn no_video(input: &Path) -> DipcError {
+    DipcError::io(
+        format!("{} has no video stream", input.display()),
+        io::ErrorKind::InvalidData.into(),
+    )
+}
@@ -1,14 +1,18 @@
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::path::PathBuf;
 
 use image::Rgb;
 use serde_json::Value;
+use tracing::warn;
 
 use crate::cli::{ColorPalette, ColorPaletteStyles};
+use crate::convert::Mode;
 
 pub fn parse_palette(
     json: serde_json::Map<String, Value>,
     styles: &ColorPaletteStyles,
+    aliases: &[(&str, &str)],
 ) -> Result<Vec<Palette>, String> {
     match styles {
         ColorPaletteStyles::None => {
@@ -29,16 +33,26 @@ pub fn parse_palette(
             }
             Ok(out)
         }
+        ColorPaletteStyles::Auto => Err(String::from(
+            "`auto` picks a style per image and can only be used when converting images",
+        )),
         ColorPaletteStyles::Some { styles } => {
-            let mut json = json;
             let mut out = Vec::with_capacity(styles.len());
             for style in styles {
-                let Some(Value::Object(map)) = json.remove(style) else {
-                    return Err(format!("Failed to parse palette style `{style}`: It does not exist in the theme JSON source"))
+                let Some((name, Value::Object(map))) = resolve_style(&json, style, aliases) else {
+                    let candidates = json
+                        .keys()
+                        .map(String::as_str)
+                        .chain(aliases.iter().map(|(alias, _)| *alias));
+                    return Err(format!(
+                        "Failed to parse palette style `{style}`: It does not exist in the theme JSON source.{}",
+                        crate::fuzzy::did_you_mean(style, candidates)
+                    ))
                 };
-                let mut palette = Palette::try_from(map)
+                let mut palette = Palette::try_from(map.clone())
                     .map_err(|err| format!("Failed to parse palette style `{style}`: {err}"))?;
-                palette.name = Some(style.to_string());
+                // The canonical name, as it is used in output file names
+                palette.name = Some(name.clone());
                 out.push(palette);
             }
             Ok(out)
@@ -46,10 +60,162 @@ pub fn parse_palette(
     }
 }
 
+/// Find the variation selected by `style`: an exact match, then a match ignoring case, spaces,
+/// underscores and dashes, then one of the palette's `aliases` (alias, canonical name)
+fn resolve_style<'a>(
+    json: &'a serde_json::Map<String, Value>,
+    style: &str,
+    aliases: &[(&str, &str)],
+) -> Option<(&'a String, &'a Value)> {
+    if let Some(entry) = json.get_key_value(style) {
+        return Some(entry);
+    }
+    let normalized = normalize_style(style);
+    json.iter()
+        .find(|(name, _)| normalize_style(name) == normalized)
+        .or_else(|| {
+            let (_, canonical) = aliases
+                .iter()
+                .find(|(alias, _)| normalize_style(alias) == normalized)?;
+            json.get_key_value(*canonical)
+        })
+}
+
+/// Lowercase a variation name and drop the spaces, underscores and dashes separating its words
+pub fn normalize_style(style: &str) -> String {
+    style
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Keep only the colors named in `only` and drop the ones named in `exclude`, matching names
+/// like variations. Variations left without colors are dropped.
+pub fn filter_colors(
+    palettes: &mut Vec<Palette>,
+    only: Option<&[String]>,
+    exclude: &[String],
+) -> Result<(), String> {
+    for name in only.unwrap_or_default().iter().chain(exclude) {
+        let normalized = normalize_style(name);
+        let mut names = palettes
+            .iter()
+            .flat_map(|palette| palette.colors.iter().map(|(name, _)| name.as_str()));
+        if !names.any(|color| normalize_style(color) == normalized) {
+            let candidates = palettes
+                .iter()
+                .flat_map(|palette| palette.colors.iter().map(|(name, _)| name.as_str()));
+            return Err(format!(
+                "There is no color named `{name}` in the palette.{}",
+                crate::fuzzy::did_you_mean(name, candidates)
+            ));
+        }
+    }
+
+    let only: Option<Vec<String>> =
+        only.map(|names| names.iter().map(|name| normalize_style(name)).collect());
+    let exclude: Vec<String> = exclude.iter().map(|name| normalize_style(name)).collect();
+    for palette in palettes.iter_mut() {
+        palette.colors.retain(|(name, _)| {
+            let name = normalize_style(name);
+            only.as_ref().is_none_or(|only| only.contains(&name)) && !exclude.contains(&name)
+        });
+    }
+    palettes.retain(|palette| !palette.colors.is_empty());
+    if palettes.is_empty() {
+        return Err("No palette colors are left after applying --only-colors and --exclude-colors".to_string());
+    }
+    Ok(())
+}
+
+/// Drop the colors of `palette` closer than `delta` to an earlier one
+pub fn dedup_colors(palette: &mut Palette, delta: f32, method: deltae::DEMethod) {
+    let mut kept: Vec<crate::delta::Lab> = Vec::with_capacity(palette.colors.len());
+    palette.colors.retain(|(_name, color)| {
+        let lab = crate::delta::Lab::from(color.0);
+        let duplicate = kept
+            .iter()
+            .any(|&other| *deltae::DeltaE::new(lab, other, method).value() < delta);
+        if !duplicate {
+            kept.push(lab);
+        }
+        !duplicate
+    });
+}
+
+/// Remove the colors of `palette` that are identical to an earlier one, keeping the palette order
+/// so ties between equally close colors still go to the one listed first
+pub fn dedup_identical(palette: &mut Palette) {
+    let mut seen = HashSet::new();
+    palette.colors.retain(|(_name, color)| seen.insert(color.0));
+}
+
+/// Move every color of `palette` towards the color of the same name in `other`, or the nearest
+/// one if there is none, by `ratio` in Lab space
+pub fn blend_colors(
+    palette: &mut Palette,
+    other: &[(String, Rgb<u8>)],
+    ratio: f32,
+    method: deltae::DEMethod,
+) {
+    use crate::delta::Lab;
+
+    let other: Vec<(&str, Lab)> = other
+        .iter()
+        .map(|(name, color)| (name.as_str(), Lab::from(color.0)))
+        .collect();
+    let others: Vec<Lab> = other.iter().map(|&(_, color)| color).collect();
+    let weights = vec![1.0; others.len()];
+    for (name, color) in &mut palette.colors {
+        let lab = Lab::from(color.0);
+        let target = match other.iter().find(|(other, _)| other == name) {
+            Some(&(_, target)) => target,
+            None => lab.to_nearest_palette(&others, &weights, method),
+        };
+        color.0 = lab.lerp(target, ratio).to_rgb();
+    }
+}
+
+/// Add `steps` lighter and `steps` darker shades of every color of `palette`, evenly spaced in
+/// lightness towards white and black. The shades are named `<color>+1`, `<color>-1` and so on,
+/// and share the weight of their color.
+pub fn expand_shades(palette: &mut Palette, steps: u32) {
+    let mut shades = Vec::with_capacity(palette.colors.len() * 2 * steps as usize);
+    for (name, color) in &palette.colors {
+        let [l, c, h] = crate::delta::Lab::from(color.0).to_lch();
+        for step in 1..=steps {
+            let t = step as f32 / (steps + 1) as f32;
+            for (sign, lightness) in [('+', l + (100.0 - l) * t), ('-', l - l * t)] {
+                let shade = format!("{name}{sign}{step}");
+                if let Some(&weight) = palette.weights.get(name) {
+                    palette.weights.insert(shade.clone(), weight);
+                }
+                let rgb = crate::delta::Lab::from_lch([lightness, c, h]).to_rgb();
+                shades.push((shade, Rgb(rgb)));
+            }
+        }
+    }
+    palette.colors.extend(shades);
+}
+
+/// Whether `value` is a variation rather than a color, as colors can be JSON objects too
+pub fn is_variation(value: &Value) -> bool {
+    let Value::Object(map) = value else {
+        return false;
+    };
+    map.is_empty()
+        || !map
+            .keys()
+            .all(|key| matches!(key.as_str(), "r" | "g" | "b" | "hex" | "weight"))
+}
+
 #[derive(Debug, Clone)]
 pub struct Palette {
     pub name: Option<String>,
     pub colors: Vec<(String, Rgb<u8>)>,
+    /// Matching weights of the colors that have one, by color name
+    pub weights: HashMap<String, f32>,
 }
 
 impl TryFrom<serde_json::Map<String, Value>> for Palette {
@@ -57,42 +223,13 @@ impl TryFrom<serde_json::Map<String, Value>> for Palette {
 
     fn try_from(json: serde_json::Map<String, Value>) -> Result<Self, Self::Error> {
         let mut colors = Vec::with_capacity(json.len());
+        let mut weights = HashMap::new();
         for (name, value) in json {
             let mut colorarr: [u8; 3] = [0_u8; 3];
             match value {
                 Value::String(hex) => {
                     // For representing a color as a hex string `#FF8800` in JSON
-                    if !hex.starts_with('#') {
-                        return Err(format!(
-                            "Encountered a color string not in the `#HEX` format: `{hex}`"
-                        ));
-                    };
-                    let color = &hex[1..];
-                    if !matches!(color.len(), 3 | 6) {
-                        return Err(format!(
-                            "Encountered a HEX color string of an invalid length: `{hex}`"
-                        ));
-                    }
-                    let channel_length = color.len() / 3;
-                    let multiplier = match channel_length {
-                        1 => 16,
-                        2 => 1,
-                        _ => unreachable!(),
-                    };
-                    for (channel, c) in colorarr.iter_mut().enumerate() {
-                        let start = channel * channel_length;
-                        let Some(channelstr) = color.get(start..start + channel_length) else {
-                            return Err(format!(
-                                "Failed to parse HEX color string `{hex}`. Does it contain a multi-byte sequence? Only hexadecimal digits are allowed."
-                            ));
-                        };
-                        let Ok(val) = u8::from_str_radix(channelstr, 16).map(|x| x * multiplier) else {
-                            return Err(format!(
-                                "Failed to parse HEX color string `{hex}`. Only hexadecimal digits are allowed."
-                            ));
-                        };
-                        *c = val;
-                    }
+                    colorarr = parse_hex(&hex)?;
                 }
                 Value::Array(arr) => {
                     // For representing a color as `[128, 255, 0]` in JSON
@@ -113,26 +250,83 @@ impl TryFrom<serde_json::Map<String, Value>> for Palette {
                     }
                 }
                 Value::Object(mut map) => {
-                    // For representing a color as a JSON object: `{"r": 255, "g": 128, "b": 0}`
-                    for (channel, name) in ["r", "g", "b"].into_iter().enumerate() {
-                        let Some(obj)=map.remove(name) else {
-                            return Err(format!(r#"Key `{name}` not found in JSON object {map:?}. The format is `{{"r": 255, "g": 128, "b": 0\}}"#))
-                        };
-                        let Value::Number(num) = obj else {
-                            return Err(format!(r#"Key `{name}` has a non-number value in JSON object {map:?}. The format is `{{"r": 255, "g": 128, "b": 0}}"#))
+                    // A weight divides the DeltaE distance to this color while matching:
+                    // `{"hex": "#88c0d0", "weight": 2.0}`
+                    if let Some(weight) = map.remove("weight") {
+                        let positive = weight.as_f64().filter(|weight| *weight > 0.0 && weight.is_finite());
+                        let Some(weight) = positive else {
+                            return Err(format!("Encountered a color weight that is not a positive number: {weight}"))
                         };
-                        let Some(Ok(brightness)): Option<Result<u8, _>> = num.as_u64().map(|num| num.try_into()) else {
-                            return Err(format!("Encountered a number not representable by an 8-bit-integer in a color object: at key {name}: {num}"))
+                        weights.insert(name.clone(), weight as f32);
+                    }
+                    if let Some(hex) = map.remove("hex") {
+                        let Value::String(hex) = hex else {
+                            return Err(format!("Key `hex` has a non-string value: {hex}"))
                         };
-                        colorarr[channel] = brightness;
+                        colorarr = parse_hex(&hex)?;
+                    } else {
+                        // For representing a color as a JSON object: `{"r": 255, "g": 128, "b": 0}`
+                        for (channel, name) in ["r", "g", "b"].into_iter().enumerate() {
+                            let Some(obj)=map.remove(name) else {
+                                return Err(format!(r#"Key `{name}` not found in JSON object {map:?}. The format is `{{"r": 255, "g": 128, "b": 0\}}"#))
+                            };
+                            let Value::Number(num) = obj else {
+                                return Err(format!(r#"Key `{name}` has a non-number value in JSON object {map:?}. The format is `{{"r": 255, "g": 128, "b": 0}}"#))
+                            };
+                            let Some(Ok(brightness)): Option<Result<u8, _>> = num.as_u64().map(|num| num.try_into()) else {
+                                return Err(format!("Encountered a number not representable by an 8-bit-integer in a color object: at key {name}: {num}"))
+                            };
+                            colorarr[channel] = brightness;
+                        }
                     }
                 }
                 _ => {}
             };
             colors.push((name, Rgb(colorarr)))
         }
-        Ok(Palette { colors, name: None })
+        Ok(Palette {
+            colors,
+            weights,
+            name: None,
+        })
+    }
+}
+
+/// Parse a `#RGB` or `#RRGGBB` color
+pub fn parse_hex(hex: &str) -> Result<[u8; 3], String> {
+    let mut colorarr: [u8; 3] = [0_u8; 3];
+    if !hex.starts_with('#') {
+        return Err(format!(
+            "Encountered a color string not in the `#HEX` format: `{hex}`"
+        ));
+    };
+    let color = &hex[1..];
+    if !matches!(color.len(), 3 | 6) {
+        return Err(format!(
+            "Encountered a HEX color string of an invalid length: `{hex}`"
+        ));
+    }
+    let channel_length = color.len() / 3;
+    let multiplier = match channel_length {
+        1 => 16,
+        2 => 1,
+        _ => unreachable!(),
+    };
+    for (channel, c) in colorarr.iter_mut().enumerate() {
+        let start = channel * channel_length;
+        let Some(channelstr) = color.get(start..start + channel_length) else {
+            return Err(format!(
+                "Failed to parse HEX color string `{hex}`. Does it contain a multi-byte sequence? Only hexadecimal digits are allowed."
+            ));
+        };
+        let Ok(val) = u8::from_str_radix(channelstr, 16).map(|x| x * multiplier) else {
+            return Err(format!(
+                "Failed to parse HEX color string `{hex}`. Only hexadecimal digits are allowed."
+            ));
+        };
+        *c = val;
     }
+    Ok(colorarr)
 }
 
 pub fn output_file_name(
@@ -141,32 +335,26 @@ pub fn output_file_name(
     color_palette: &ColorPalette,
     color_palette_variations: &[Palette],
     method: deltae::DEMethod,
+    mode: Mode,
+    extension: &str,
 ) -> PathBuf {
     let mut output = PathBuf::new();
     let mut output_file_name = String::new();
 
     if let Some(dir) = dir_path {
-        match dir.to_str() {
-            Some(dir) => output.push(dir),
-            None => {
-                eprintln!("Failed to convert directory path to string");
-                std::process::exit(1);
-            }
-        };
+        output.push(dir);
     }
 
     let file_stem = match input_path.file_stem() {
         Some(stem) => match stem.to_str() {
             Some(stem) => stem,
             None => {
-                eprintln!("Failed to convert file stem to string");
-                eprintln!("Defaulting to \"image\"");
+                warn!("Failed to convert file stem to string, defaulting to \"image\"");
                 "image"
             }
         },
         None => {
-            eprintln!("Failed to get file stem");
-            eprintln!("Defaulting to \"image\"");
+            warn!("Failed to get file stem, defaulting to \"image\"");
             "image"
         }
     };
@@ -187,8 +375,12 @@ pub fn output_file_name(
     if method != deltae::DEMethod::DE2000 {
         output_file_name.push_str(format!("_{}", method).as_str());
     }
+    if mode != Mode::Nearest {
+        output_file_name.push_str(format!("_{}", mode).as_str());
+    }
 
+    // Not `set_extension`, which would cut off everything after a dot in the file stem
+    output_file_name.push_str(format!(".{}", extension).as_str());
     output.push(output_file_name);
-    output.set_extension("png");
     output
 }
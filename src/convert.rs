@@ -0,0 +1,510 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use image::RgbaImage;
+use indicatif::{ParallelProgressIterator, ProgressBar};
+use rayon::{
+    prelude::{
+        IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+    },
+    slice::{ParallelSlice, ParallelSliceMut},
+};
+
+use crate::{
+    config::Palette,
+    convert_image_format::Rgba16Image,
+    delta::{linear_to_srgb, Lab},
+    lut::Lut,
+    remap::Remap,
+};
+
+const CHUNK: usize = 4;
+/// Images with more distinct colors are matched pixel by pixel, see `unique_colors`
+pub const MAX_UNIQUE_COLORS: usize = 1 << 14;
+
+/// Returned when a conversion was aborted through its cancellation token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Conversion was cancelled")
+    }
+}
+
+/// Number of visible pixels mapped to each color
+pub type Usage = HashMap<[u8; 3], u64>;
+
+fn count(mut usage: Usage, pixel: &[u8], rgb: [u8; 3]) -> Result<Usage, Cancelled> {
+    if pixel[3] > 0 {
+        *usage.entry(rgb).or_default() += 1;
+    }
+    Ok(usage)
+}
+
+/// Add the counts of `other` to `usage`
+pub fn merge_usage(mut usage: Usage, other: Usage) -> Usage {
+    for (rgb, pixels) in other {
+        *usage.entry(rgb).or_default() += pixels;
+    }
+    usage
+}
+
+/// Map every pixel of `image` to its nearest palette color with `matcher`, returning how many
+/// pixels were mapped to each color. Every rayon job counts on its own, the counts are merged
+/// at the end.
+///
+/// `cancel` is polled for every pixel, so setting it from another thread (e.g. a Ctrl-C handler)
+/// stops the conversion shortly after. The image is left half converted in that case and should
+/// be discarded by the caller.
+pub fn convert_image(
+    image: &mut RgbaImage,
+    matcher: &Matcher,
+    labels: Option<&[u8]>,
+    progress_bar: ProgressBar,
+    cancel: &AtomicBool,
+) -> Result<Usage, Cancelled> {
+    image
+        .par_chunks_exact_mut(CHUNK)
+        .enumerate()
+        .progress_with(progress_bar)
+        .try_fold(Usage::new, |usage, (idx, bytes)| {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(Cancelled);
+            }
+            let pixel: [u8; CHUNK] = bytes.try_into().unwrap();
+            let new_rgb = matcher.at(labels, idx).map(Lab::from(pixel));
+            bytes[..3].copy_from_slice(&new_rgb);
+            count(usage, bytes, new_rgb)
+        })
+        .try_reduce(Usage::new, |usage, other| Ok(merge_usage(usage, other)))
+}
+
+/// The distinct colors of `frames`, or `None` if there are so many that matching each of them
+/// once would not save much over matching every pixel, like in photos. Counting stops as soon
+/// as there are too many, so photos only pay for a few rows
+pub fn unique_colors<'a>(
+    frames: impl IntoIterator<Item = &'a RgbaImage> + Clone,
+) -> Option<Vec<[u8; 3]>> {
+    let pixels: usize = frames
+        .clone()
+        .into_iter()
+        .map(|frame| frame.len() / CHUNK)
+        .sum();
+    let max = MAX_UNIQUE_COLORS.min(pixels / 4);
+    let mut colors = HashSet::new();
+    for frame in frames {
+        for pixel in frame.pixels() {
+            let [r, g, b, _] = pixel.0;
+            if colors.insert([r, g, b]) && colors.len() > max {
+                return None;
+            }
+        }
+    }
+    Some(colors.into_iter().collect())
+}
+
+/// The palette color `matcher` maps each of `colors` to
+pub fn match_colors(colors: &[[u8; 3]], matcher: &Matcher) -> HashMap<[u8; 3], [u8; 3]> {
+    colors
+        .par_iter()
+        .map(|&rgb| (rgb, matcher.map(Lab::from(rgb))))
+        .collect()
+}
+
+/// Like `convert_image`, with every color of `image` already matched by `match_colors`
+pub fn convert_mapped(
+    image: &mut RgbaImage,
+    colors: &HashMap<[u8; 3], [u8; 3]>,
+    progress_bar: ProgressBar,
+    cancel: &AtomicBool,
+) -> Result<Usage, Cancelled> {
+    image
+        .par_chunks_exact_mut(CHUNK)
+        .progress_with(progress_bar)
+        .try_fold(Usage::new, |usage, bytes| {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(Cancelled);
+            }
+            let new_rgb = colors[&[bytes[0], bytes[1], bytes[2]]];
+            bytes[..3].copy_from_slice(&new_rgb);
+            count(usage, bytes, new_rgb)
+        })
+        .try_reduce(Usage::new, |usage, other| Ok(merge_usage(usage, other)))
+}
+
+/// Whether every pixel of `image` is a shade of gray, e.g. a grayscale input
+pub fn is_grayscale(image: &RgbaImage) -> bool {
+    image
+        .par_chunks_exact(CHUNK)
+        .all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2])
+}
+
+/// Like `convert_image` for images where `is_grayscale` holds. There are only 256 shades of gray,
+/// so each is matched once and the pixels are looked up instead of matched one by one
+pub fn convert_grayscale(
+    image: &mut RgbaImage,
+    matcher: &Matcher,
+    progress_bar: ProgressBar,
+    cancel: &AtomicBool,
+) -> Result<Usage, Cancelled> {
+    let shades: Vec<[u8; 3]> = (0..=u8::MAX)
+        .into_par_iter()
+        .map(|value| matcher.map(Lab::from([value; 3])))
+        .collect();
+    image
+        .par_chunks_exact_mut(CHUNK)
+        .progress_with(progress_bar)
+        .try_fold(Usage::new, |usage, bytes| {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(Cancelled);
+            }
+            let new_rgb = shades[usize::from(bytes[0])];
+            bytes[..3].copy_from_slice(&new_rgb);
+            count(usage, bytes, new_rgb)
+        })
+        .try_reduce(Usage::new, |usage, other| Ok(merge_usage(usage, other)))
+}
+
+/// Like `convert_image`, but starting from the Lab representation of `image` computed by
+/// `image_to_lab`, so the conversion can be shared when mapping one image to several palettes
+pub fn convert_image_lab(
+    image: &mut RgbaImage,
+    lab: &[Lab],
+    matcher: &Matcher,
+    labels: Option<&[u8]>,
+    progress_bar: ProgressBar,
+    cancel: &AtomicBool,
+) -> Result<Usage, Cancelled> {
+    image
+        .par_chunks_exact_mut(CHUNK)
+        .zip(lab.par_iter())
+        .enumerate()
+        .progress_with(progress_bar)
+        .try_fold(Usage::new, |usage, (idx, (bytes, &lab))| {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(Cancelled);
+            }
+            let new_rgb = matcher.at(labels, idx).map(lab);
+            bytes[..3].copy_from_slice(&new_rgb);
+            count(usage, bytes, new_rgb)
+        })
+        .try_reduce(Usage::new, |usage, other| Ok(merge_usage(usage, other)))
+}
+
+/// Like `convert_image_lab`, but converting the pixels of `deep`, the 16 bit copy of `image`,
+/// to Lab as they are mapped instead of taking the Lab representation of the whole image
+pub fn convert_image16(
+    image: &mut RgbaImage,
+    deep: &Rgba16Image,
+    matcher: &Matcher,
+    labels: Option<&[u8]>,
+    progress_bar: ProgressBar,
+    cancel: &AtomicBool,
+) -> Result<Usage, Cancelled> {
+    image
+        .par_chunks_exact_mut(CHUNK)
+        .zip(deep.par_chunks_exact(CHUNK))
+        .enumerate()
+        .progress_with(progress_bar)
+        .try_fold(Usage::new, |usage, (idx, (bytes, pixel))| {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(Cancelled);
+            }
+            let rgb = [pixel[0], pixel[1], pixel[2]].map(|c| c as f32 / u16::MAX as f32);
+            let new_rgb = matcher.at(labels, idx).map(Lab::from_rgb_normalized(rgb));
+            bytes[..3].copy_from_slice(&new_rgb);
+            count(usage, bytes, new_rgb)
+        })
+        .try_reduce(Usage::new, |usage, other| Ok(merge_usage(usage, other)))
+}
+
+/// Map every pixel of `image` through `lut` instead of a palette, see `convert_image`
+pub fn apply_lut(
+    image: &mut RgbaImage,
+    lut: &Lut,
+    linear: bool,
+    progress_bar: ProgressBar,
+    cancel: &AtomicBool,
+) -> Result<(), Cancelled> {
+    image
+        .par_chunks_exact_mut(CHUNK)
+        .progress_with(progress_bar)
+        .try_for_each(|bytes| {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(Cancelled);
+            }
+            let new_rgb = lut.map([bytes[0], bytes[1], bytes[2]], linear);
+            bytes[..3].copy_from_slice(&new_rgb);
+            Ok(())
+        })
+}
+
+/// Lab representation of every pixel of `image`
+pub fn image_to_lab(image: &RgbaImage) -> Vec<Lab> {
+    let mut lab = Vec::with_capacity(image.len() / CHUNK);
+    image
+        .par_chunks_exact(CHUNK)
+        .map(|pixel| {
+            let pixel: [u8; CHUNK] = pixel.try_into().unwrap();
+            Lab::from(pixel)
+        })
+        .collect_into_vec(&mut lab);
+    lab
+}
+
+/// Lab representation of every pixel of a 16 bit `image`, at full precision
+pub fn image16_to_lab(image: &Rgba16Image) -> Vec<Lab> {
+    let mut lab = Vec::with_capacity(image.len() / CHUNK);
+    image
+        .par_chunks_exact(CHUNK)
+        .map(|pixel| {
+            let rgb = [pixel[0], pixel[1], pixel[2]].map(|c| c as f32 / u16::MAX as f32);
+            Lab::from_rgb_normalized(rgb)
+        })
+        .collect_into_vec(&mut lab);
+    lab
+}
+
+/// Copy the converted colors of `image` into its 16 bit copy `deep`, keeping the full precision
+/// alpha channel of `deep`
+pub fn copy_rgb_to_deep(image: &RgbaImage, deep: &mut Rgba16Image) {
+    deep.par_chunks_exact_mut(CHUNK)
+        .zip(image.par_chunks_exact(CHUNK))
+        .for_each(|(deep, pixel)| {
+            for (deep, &channel) in deep[..3].iter_mut().zip(&pixel[..3]) {
+                *deep = u16::from(channel) * 257;
+            }
+        });
+}
+
+/// Nearest palette color of every entry of a `size`x`size`x`size` RGB lattice, with red changing
+/// fastest and blue slowest, as used by both .cube files and HALD CLUTs
+pub fn lut(size: u32, matcher: &Matcher, linear: bool, progress_bar: ProgressBar) -> Vec<[u8; 3]> {
+    let max = (size - 1) as f32;
+    (0..size.pow(3))
+        .into_par_iter()
+        .progress_with(progress_bar)
+        .map(|idx| {
+            let rgb = [idx % size, idx / size % size, idx / (size * size)].map(|c| {
+                // A LUT for linear light input has its entries evenly spaced in linear light
+                match linear {
+                    true => linear_to_srgb(c as f32 / max),
+                    false => c as f32 / max,
+                }
+            });
+            matcher.map(Lab::from_rgb_normalized(rgb))
+        })
+        .collect()
+}
+
+/// How pixels are matched to a palette
+pub struct Matcher<'a> {
+    /// Lab representation of the palette colors, see `palette_lab`
+    pub palette: &'a [Lab],
+    /// Weight of every palette color, see `palette_weights`
+    pub weights: &'a [f32],
+    pub method: deltae::DEMethod,
+    /// Explicit source → target colors from `--map`, used before the palette
+    pub remap: Option<&'a Remap>,
+    /// Lightness ramp used instead of the nearest palette color with `--mode gradient-map`
+    pub gradient: Option<&'a Gradient>,
+    /// Matchers of the regions of `--regions`, selected by the labels of the pixels
+    pub regions: &'a [Matcher<'a>],
+}
+
+impl Matcher<'_> {
+    /// The matcher for the pixel at `idx`, given the region labels of the image
+    fn at(&self, labels: Option<&[u8]>, idx: usize) -> &Matcher<'_> {
+        match labels.map_or(0, |labels| labels[idx]) {
+            0 => self,
+            label => &self.regions[usize::from(label) - 1],
+        }
+    }
+
+    pub fn map(&self, lab: Lab) -> [u8; 3] {
+        if let Some(target) = self.remap.and_then(|remap| remap.target(lab, self.method)) {
+            return target;
+        }
+        if let Some(gradient) = self.gradient {
+            return gradient.map(lab).to_rgb();
+        }
+        lab.to_nearest_palette(self.palette, self.weights, self.method)
+            .to_rgb()
+    }
+}
+
+/// How pixels are mapped to the palette colors
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Mode {
+    /// The nearest palette color by DeltaE
+    #[default]
+    Nearest,
+    /// The palette colors sorted by lightness form a ramp, and every pixel gets the color at its
+    /// lightness, for duotone and posterized styles
+    GradientMap,
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mode::Nearest => write!(f, "nearest"),
+            Mode::GradientMap => write!(f, "gradient-map"),
+        }
+    }
+}
+
+/// The palette colors sorted by lightness, see `Mode::GradientMap`
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    stops: Vec<Lab>,
+    /// Blend between neighboring stops instead of using distinct bands
+    interpolate: bool,
+}
+
+impl Gradient {
+    pub fn new(palette: &[Lab], interpolate: bool) -> Self {
+        let mut stops = palette.to_vec();
+        stops.sort_by(|a, b| a.lightness().total_cmp(&b.lightness()));
+        Gradient { stops, interpolate }
+    }
+
+    pub fn map(&self, lab: Lab) -> Lab {
+        let position = (lab.lightness() / 100.0).clamp(0.0, 1.0);
+        let last = self.stops.len() - 1;
+        if !self.interpolate {
+            // Equally wide bands, one per stop
+            let band = (position * self.stops.len() as f32) as usize;
+            return self.stops[band.min(last)];
+        }
+        let position = position * last as f32;
+        let low = (position.floor() as usize).min(last.saturating_sub(1));
+        let high = (low + 1).min(last);
+        self.stops[low].lerp(self.stops[high], position - low as f32)
+    }
+}
+
+/// Lab representation of all colors of `palettes`, merged into a single palette
+pub fn palette_lab(palettes: &[Palette]) -> Vec<Lab> {
+    palettes
+        .par_iter()
+        .flat_map_iter(|palette| {
+            palette
+                .colors
+                .iter()
+                .map(|(_name, color)| Lab::from(color.0))
+        })
+        .collect()
+}
+
+/// Matching weight of every color of `palettes`, in the order of `palette_lab`
+pub fn palette_weights(palettes: &[Palette]) -> Vec<f32> {
+    palettes
+        .iter()
+        .flat_map(|palette| {
+            palette
+                .colors
+                .iter()
+                .map(|(name, _color)| palette.weights.get(name).copied().unwrap_or(1.0))
+        })
+        .collect()
+}
+
+/// Number of pixels `convert_image` will process, used to size progress bars
+pub fn pixel_count(image: &RgbaImage) -> u64 {
+    (image.len() / CHUNK)
+        .try_into()
+        .expect("Failed to convert usize to u64")
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::{
+        cli::{ColorPalette, ColorPaletteStyles},
+        config::parse_palette,
+    };
+
+    /// Convert a copy of `image` to the Nord palette with `convert`
+    fn to_nord(
+        image: &RgbaImage,
+        convert: impl FnOnce(&mut RgbaImage, &Matcher) -> Result<Usage, Cancelled>,
+    ) -> (RgbaImage, Usage) {
+        let palettes =
+            parse_palette(ColorPalette::Nord.get_json(), &ColorPaletteStyles::All, &[]).unwrap();
+        let palette = palette_lab(&palettes);
+        let weights = palette_weights(&palettes);
+        let matcher = Matcher {
+            palette: &palette,
+            weights: &weights,
+            method: deltae::DEMethod::DE2000,
+            remap: None,
+            gradient: None,
+            regions: &[],
+        };
+        let mut image = image.clone();
+        let usage = convert(&mut image, &matcher).unwrap();
+        (image, usage)
+    }
+
+    fn every_pixel(image: &mut RgbaImage, matcher: &Matcher) -> Result<Usage, Cancelled> {
+        convert_image(
+            image,
+            matcher,
+            None,
+            ProgressBar::hidden(),
+            &AtomicBool::new(false),
+        )
+    }
+
+    #[test]
+    fn grayscale_table_matches_every_pixel() {
+        let image = RgbaImage::from_fn(64, 16, |x, y| {
+            let shade = (x * 4 + y) as u8;
+            // Transparent pixels are converted but not counted
+            Rgba([shade, shade, shade, if y == 0 { 0 } else { 255 }])
+        });
+        assert!(is_grayscale(&image));
+        assert_eq!(
+            to_nord(&image, |image, matcher| convert_grayscale(
+                image,
+                matcher,
+                ProgressBar::hidden(),
+                &AtomicBool::new(false)
+            )),
+            to_nord(&image, every_pixel)
+        );
+
+        let mut tinted = image;
+        tinted.put_pixel(3, 3, Rgba([1, 2, 3, 255]));
+        assert!(!is_grayscale(&tinted));
+    }
+
+    #[test]
+    fn unique_colors_match_every_pixel() {
+        let flat = RgbaImage::from_fn(40, 30, |x, y| {
+            Rgba([(x / 10 * 60) as u8, (y / 10 * 80) as u8, 200, 255])
+        });
+        let colors = unique_colors([&flat]).unwrap();
+        assert_eq!(colors.len(), 12);
+        assert_eq!(
+            to_nord(&flat, |image, matcher| convert_mapped(
+                image,
+                &match_colors(&colors, matcher),
+                ProgressBar::hidden(),
+                &AtomicBool::new(false)
+            )),
+            to_nord(&flat, every_pixel)
+        );
+
+        // Photos have about as many colors as pixels, matching them once saves nothing
+        let noisy = RgbaImage::from_fn(40, 30, |x, y| {
+            Rgba([(x * 6) as u8, (y * 8) as u8, (x * y) as u8, 255])
+        });
+        assert_eq!(unique_colors([&noisy]), None);
+    }
+}
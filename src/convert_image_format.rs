@@ -0,0 +1,830 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use image::{
+    codecs::{
+        gif::{GifDecoder, GifEncoder, Repeat},
+        hdr::HdrDecoder,
+        ico::{IcoEncoder, IcoFrame},
+        png::PngDecoder,
+        webp::WebPEncoder,
+    },
+    imageops::{self, FilterType},
+    AnimationDecoder, ColorType, Delay, DynamicImage, Frame, ImageBuffer, ImageFormat, Pixel,
+    Primitive, Rgba, Rgba32FImage, RgbaImage,
+};
+use num_traits::NumCast;
+use rayon::{
+    iter::{IndexedParallelIterator, ParallelIterator},
+    slice::{ParallelSlice, ParallelSliceMut},
+};
+
+use crate::{
+    delta::{linear_to_srgb, srgb_to_linear},
+    error::{DipcError, Result},
+    http,
+    metadata::{self, Metadata},
+};
+
+/// Images larger than this are not downloaded
+const MAX_IMAGE_DOWNLOAD: u64 = 256 * 1024 * 1024;
+
+/// How a decoded input has to be written back
+#[derive(Debug, Clone, Copy)]
+pub enum Container {
+    /// A single still image
+    Still,
+    /// An animated GIF with its looping behavior
+    Gif { repeat: Repeat },
+    /// An animated PNG with its number of plays (0 loops forever)
+    Apng { plays: u32 },
+}
+
+impl Container {
+    pub fn is_animated(&self) -> bool {
+        !matches!(self, Container::Still)
+    }
+
+    /// Number of times an animation is played, 0 meaning forever
+    fn plays(&self) -> u32 {
+        match self {
+            Container::Still
+            | Container::Gif {
+                repeat: Repeat::Infinite,
+            } => 0,
+            Container::Gif {
+                repeat: Repeat::Finite(count),
+            } => (*count).into(),
+            Container::Apng { plays } => *plays,
+        }
+    }
+
+    fn repeat(&self) -> Repeat {
+        match self.plays() {
+            0 => Repeat::Infinite,
+            plays => Repeat::Finite(plays.try_into().unwrap_or(u16::MAX)),
+        }
+    }
+}
+
+/// A decoded input image: a single frame for still images, or every frame of an animation
+#[derive(Clone)]
+pub struct Decoded {
+    pub frames: Vec<Frame>,
+    pub container: Container,
+    /// The format the image was read from
+    pub format: ImageFormat,
+    /// Full precision copy of still images with 16 bits per channel. Kept in sync with the 8 bit
+    /// frame, which is what most of the pipeline works with.
+    pub deep: Option<Rgba16Image>,
+    /// EXIF data and ICC profile to write into the outputs
+    pub metadata: Metadata,
+}
+
+/// An RGBA image with 16 bits per channel
+pub type Rgba16Image = ImageBuffer<Rgba<u16>, Vec<u16>>;
+
+impl Decoded {
+    /// The format to save the image as: `requested`, or the input format if it can be written
+    pub fn output_format(&self, requested: Option<ImageFormat>) -> ImageFormat {
+        output_format(self.format, requested)
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.frames[0].buffer().dimensions()
+    }
+
+    /// Crop every frame, and the 16 bit copy, to the `width`x`height` rectangle at `x`,`y`
+    pub fn crop(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        for frame in &mut self.frames {
+            let buffer = imageops::crop_imm(frame.buffer(), x, y, width, height).to_image();
+            *frame = Frame::from_parts(buffer, frame.left(), frame.top(), frame.delay());
+        }
+        if let Some(deep) = &mut self.deep {
+            *deep = imageops::crop_imm(deep, x, y, width, height).to_image();
+        }
+    }
+
+    /// Gaussian blur every frame, and the 16 bit copy, in linear light if `linear`
+    pub fn blur(&mut self, sigma: f32, linear: bool) {
+        self.resample(linear, Blur(sigma));
+    }
+
+    /// Resize every frame, and the 16 bit copy, to `width`x`height`, in linear light if `linear`
+    pub fn resize(&mut self, width: u32, height: u32, filter: FilterType, linear: bool) {
+        self.resample(linear, Resize(width, height, filter));
+    }
+
+    /// Drop frames of an animation to show at most `fps` frames per second. The delays of the
+    /// dropped frames are added to the frame shown instead, so the animation keeps its length
+    pub fn reduce_frame_rate(&mut self, fps: u32) {
+        let delay_ms = |frame: &Frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            numer as f64 / denom.max(1) as f64
+        };
+        // Without delays there is no frame rate to reduce
+        if self.frames.iter().all(|frame| delay_ms(frame) == 0.0) {
+            return;
+        }
+        let interval = 1000.0 / fps as f64;
+        let mut kept: Vec<(Frame, f64)> = Vec::with_capacity(self.frames.len());
+        let mut time = 0.0;
+        let mut due = 0.0;
+        for frame in std::mem::take(&mut self.frames) {
+            let delay = delay_ms(&frame);
+            match kept.last_mut() {
+                Some((_, length)) if time < due => *length += delay,
+                _ => {
+                    kept.push((frame, delay));
+                    while due <= time {
+                        due += interval;
+                    }
+                }
+            }
+            time += delay;
+        }
+        self.frames = kept
+            .into_iter()
+            .map(|(frame, length)| {
+                let delay = Delay::from_numer_denom_ms((length * 100.0).round() as u32, 100);
+                let (left, top) = (frame.left(), frame.top());
+                Frame::from_parts(frame.into_buffer(), left, top, delay)
+            })
+            .collect();
+    }
+
+    /// Replace every frame and the 16 bit copy by the result of `resample`. Only linear light
+    /// needs the detour through floating point values, sRGB is resampled as stored
+    fn resample(&mut self, linear: bool, resample: impl Resample) {
+        for frame in &mut self.frames {
+            let buffer = match linear {
+                true => from_f32(&resample.apply(&to_f32(frame.buffer()))),
+                false => resample.apply(frame.buffer()),
+            };
+            *frame = Frame::from_parts(buffer, frame.left(), frame.top(), frame.delay());
+        }
+        if let Some(deep) = &mut self.deep {
+            *deep = match linear {
+                true => from_f32(&resample.apply(&to_f32(deep))),
+                false => resample.apply(deep),
+            };
+        }
+    }
+}
+
+/// An `imageops` resampling operation, for any pixel type
+trait Resample {
+    fn apply<P: Pixel + 'static>(
+        &self,
+        image: &ImageBuffer<P, Vec<P::Subpixel>>,
+    ) -> ImageBuffer<P, Vec<P::Subpixel>>;
+}
+
+/// Gaussian blur with this sigma
+struct Blur(f32);
+
+impl Resample for Blur {
+    fn apply<P: Pixel + 'static>(
+        &self,
+        image: &ImageBuffer<P, Vec<P::Subpixel>>,
+    ) -> ImageBuffer<P, Vec<P::Subpixel>> {
+        imageops::blur(image, self.0)
+    }
+}
+
+/// Resize to width x height with the filter
+struct Resize(u32, u32, FilterType);
+
+impl Resample for Resize {
+    fn apply<P: Pixel + 'static>(
+        &self,
+        image: &ImageBuffer<P, Vec<P::Subpixel>>,
+    ) -> ImageBuffer<P, Vec<P::Subpixel>> {
+        imageops::resize(image, self.0, self.1, self.2)
+    }
+}
+
+/// An RGBA image as floating point values in `0.0..=1.0`, with the color channels decoded to
+/// linear light
+fn to_f32<T>(image: &ImageBuffer<Rgba<T>, Vec<T>>) -> Rgba32FImage
+where
+    T: Primitive + Send + Sync,
+    Rgba<T>: Pixel<Subpixel = T>,
+{
+    let max = T::DEFAULT_MAX_VALUE.to_f32().unwrap();
+    let mut values = vec![0.0; image.len()];
+    values
+        .par_chunks_exact_mut(4)
+        .zip(image.par_chunks_exact(4))
+        .for_each(|(value, pixel)| {
+            for (value, &channel) in value[..3].iter_mut().zip(&pixel[..3]) {
+                *value = srgb_to_linear(channel.to_f32().unwrap() / max);
+            }
+            value[3] = pixel[3].to_f32().unwrap() / max;
+        });
+    let (width, height) = image.dimensions();
+    Rgba32FImage::from_raw(width, height, values).unwrap()
+}
+
+/// The inverse of `to_f32`
+fn from_f32<T>(image: &Rgba32FImage) -> ImageBuffer<Rgba<T>, Vec<T>>
+where
+    T: Primitive + Send + Sync,
+    Rgba<T>: Pixel<Subpixel = T>,
+{
+    let max = T::DEFAULT_MAX_VALUE.to_f32().unwrap();
+    let encode = |value: f32| NumCast::from((value.clamp(0.0, 1.0) * max).round()).unwrap();
+    let mut channels = vec![T::DEFAULT_MIN_VALUE; image.len()];
+    channels
+        .par_chunks_exact_mut(4)
+        .zip(image.par_chunks_exact(4))
+        .for_each(|(channel, value)| {
+            for (channel, &value) in channel[..3].iter_mut().zip(&value[..3]) {
+                *channel = encode(linear_to_srgb(value));
+            }
+            channel[3] = encode(value[3]);
+        });
+    let (width, height) = image.dimensions();
+    ImageBuffer::from_raw(width, height, channels).unwrap()
+}
+
+/// Operator used to bring HDR (Radiance `.hdr` and OpenEXR) inputs into displayable range
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ToneMap {
+    /// Simple global operator, keeps the overall look of the image
+    #[default]
+    Reinhard,
+    /// Filmic curve fitted to the ACES reference transform, more contrast and saturation
+    Aces,
+}
+
+impl ToneMap {
+    /// Map a linear scene value to a linear display value in `0.0..=1.0`
+    fn apply(self, value: f32) -> f32 {
+        let value = value.max(0.0);
+        match self {
+            ToneMap::Reinhard => value / (1.0 + value),
+            // Krzysztof Narkowicz's fit of the ACES filmic curve
+            ToneMap::Aces => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                (value * (a * value + b)) / (value * (c * value + d) + e)
+            }
+        }
+        .clamp(0.0, 1.0)
+    }
+}
+
+/// Options for `open` that only apply to some input formats
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OpenOptions {
+    /// Tone mapping operator for HDR inputs
+    pub tone_map: ToneMap,
+}
+
+/// Whether an animation keeps all of its frames when saved as `format`
+pub fn supports_animation(format: ImageFormat) -> bool {
+    matches!(
+        format,
+        ImageFormat::Gif | ImageFormat::Png | ImageFormat::WebP
+    )
+}
+
+/// The format to save an image read as `input` as, see `Decoded::output_format`
+pub fn output_format(input: ImageFormat, requested: Option<ImageFormat>) -> ImageFormat {
+    requested.unwrap_or(match input {
+        format @ (ImageFormat::Png
+        | ImageFormat::Jpeg
+        | ImageFormat::WebP
+        | ImageFormat::Bmp
+        | ImageFormat::Tiff
+        | ImageFormat::Gif) => format,
+        #[cfg(feature = "avif")]
+        ImageFormat::Avif => ImageFormat::Avif,
+        _ => ImageFormat::Png,
+    })
+}
+
+/// The file extension used for `format`
+pub fn extension(format: ImageFormat) -> &'static str {
+    format.extensions_str().first().copied().unwrap_or("png")
+}
+
+/// Open an image, decoding every frame of animated GIFs and PNGs and tone mapping HDR images
+pub fn open(path: &Path, options: &OpenOptions) -> Result<Decoded> {
+    let decode_err = |source| DipcError::Decode {
+        path: path.to_path_buf(),
+        source,
+    };
+    let reader = image::io::Reader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|err| decode_err(image::ImageError::IoError(err)))?;
+
+    let metadata = reader
+        .format()
+        .map(|format| Metadata::read(path, format))
+        .unwrap_or_default();
+
+    match reader.format() {
+        Some(ImageFormat::Gif) => {
+            let repeat = gif_repeat(path).map_err(decode_err)?;
+            let file = File::open(path).map_err(|err| decode_err(err.into()))?;
+            let frames = GifDecoder::new(BufReader::new(file))
+                .and_then(|decoder| decoder.into_frames().collect_frames())
+                .map_err(decode_err)?;
+            Ok(Decoded {
+                frames,
+                container: Container::Gif { repeat },
+                format: ImageFormat::Gif,
+                deep: None,
+                metadata,
+            })
+        }
+        Some(ImageFormat::Png) => {
+            let file = File::open(path).map_err(|err| decode_err(err.into()))?;
+            let decoder = PngDecoder::new(BufReader::new(file)).map_err(decode_err)?;
+            if !decoder.is_apng() {
+                let image = DynamicImage::from_decoder(decoder).map_err(decode_err)?;
+                return Ok(still(image, ImageFormat::Png, metadata));
+            }
+            let plays = apng_plays(path).map_err(decode_err)?;
+            let frames = decoder
+                .apng()
+                .into_frames()
+                .collect_frames()
+                .map_err(decode_err)?;
+            Ok(Decoded {
+                frames,
+                container: Container::Apng { plays },
+                format: ImageFormat::Png,
+                deep: None,
+                metadata,
+            })
+        }
+        Some(ImageFormat::Hdr) => {
+            // Going through `DynamicImage` would clip the image to 8 bits, losing the highlights
+            let file = File::open(path).map_err(|err| decode_err(err.into()))?;
+            let decoder = HdrDecoder::new(BufReader::new(file)).map_err(decode_err)?;
+            let dimensions = decoder.metadata();
+            let pixels = decoder.read_image_hdr().map_err(decode_err)?;
+            let image = Rgba32FImage::from_vec(
+                dimensions.width,
+                dimensions.height,
+                pixels
+                    .into_iter()
+                    .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 1.0])
+                    .collect(),
+            )
+            .expect("HDR decoder returned the wrong number of pixels");
+            Ok(still(
+                tone_map(&image, options.tone_map),
+                ImageFormat::Hdr,
+                metadata,
+            ))
+        }
+        Some(ImageFormat::OpenExr) => {
+            let image = reader.decode().map_err(decode_err)?.into_rgba32f();
+            Ok(still(
+                tone_map(&image, options.tone_map),
+                ImageFormat::OpenExr,
+                metadata,
+            ))
+        }
+        // image has no JPEG XL or HEIF decoder, and no format to write them back as, so the
+        // outputs are PNGs
+        #[cfg(feature = "jxl")]
+        None if crate::jxl::is_jxl(path) => {
+            let image = crate::jxl::decode(path).map_err(decode_err)?;
+            Ok(still(image, ImageFormat::Png, metadata))
+        }
+        #[cfg(feature = "heif")]
+        None if crate::heif::is_heif(path) => {
+            let image = crate::heif::decode(path).map_err(decode_err)?;
+            Ok(still(image, ImageFormat::Png, metadata))
+        }
+        format => {
+            let image = reader.decode().map_err(decode_err)?;
+            Ok(still(image, format.unwrap_or(ImageFormat::Png), metadata))
+        }
+    }
+}
+
+/// Download and decode the image at `url`. The decoders read files, so the download is written
+/// to a temporary file that is removed once it is decoded. Returns the file name of the URL as
+/// well, which the outputs are named after.
+pub fn open_url(url: &str, options: &OpenOptions) -> Result<(PathBuf, Decoded)> {
+    let decode_err = |err| DipcError::Decode {
+        path: PathBuf::from(url),
+        source: image::ImageError::IoError(err),
+    };
+    let body =
+        http::get(url, MAX_IMAGE_DOWNLOAD).map_err(|err| decode_err(io::Error::other(err)))?;
+    let name = match http::file_name(url) {
+        "" => "download",
+        name => name,
+    };
+    let file = std::env::temp_dir().join(format!("dipc-{}-{name}", std::process::id()));
+    fs::write(&file, body).map_err(|err| DipcError::io("Failed to write a temporary file", err))?;
+    let decoded = open(&file, options);
+    let _ = fs::remove_file(&file);
+    decoded
+        .map(|decoded| (PathBuf::from(name), decoded))
+        .map_err(|err| match err {
+            DipcError::Decode { source, .. } => DipcError::Decode {
+                path: PathBuf::from(url),
+                source,
+            },
+            err => err,
+        })
+}
+
+/// A still image, rotated upright according to its EXIF orientation
+fn still(image: DynamicImage, format: ImageFormat, mut metadata: Metadata) -> Decoded {
+    let image = metadata.apply_orientation(image);
+    let deep = matches!(
+        image.color(),
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16
+    )
+    .then(|| image.to_rgba16());
+    Decoded {
+        frames: vec![Frame::new(image.into_rgba8())],
+        container: Container::Still,
+        format,
+        deep,
+        metadata,
+    }
+}
+
+/// Tone map a linear HDR image and encode it as sRGB. The result is kept at 16 bits per channel
+/// so the conversion to Lab does not lose the detail the tone mapping brought back.
+fn tone_map(image: &Rgba32FImage, operator: ToneMap) -> DynamicImage {
+    let encode = |value: f32| {
+        let srgb = if value <= 0.003_130_8 {
+            value * 12.92
+        } else {
+            1.055 * value.powf(1.0 / 2.4) - 0.055
+        };
+        (srgb * u16::MAX as f32).round() as u16
+    };
+    let pixels = image
+        .pixels()
+        .flat_map(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            [
+                encode(operator.apply(r)),
+                encode(operator.apply(g)),
+                encode(operator.apply(b)),
+                (a.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16,
+            ]
+        })
+        .collect();
+    let (width, height) = image.dimensions();
+    DynamicImage::ImageRgba16(
+        Rgba16Image::from_vec(width, height, pixels).expect("Buffer sized from the image"),
+    )
+}
+
+/// The image crate does not expose the NETSCAPE looping extension, so it is read separately
+fn gif_repeat(path: &Path) -> image::ImageResult<Repeat> {
+    let file = File::open(path)?;
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let decoder = options
+        .read_info(BufReader::new(file))
+        .map_err(gif_decoding_error)?;
+    Ok(match decoder.repeat() {
+        gif::Repeat::Infinite => Repeat::Infinite,
+        gif::Repeat::Finite(count) => Repeat::Finite(count),
+    })
+}
+
+/// The image crate does not expose the number of plays of an APNG, so it is read separately
+fn apng_plays(path: &Path) -> image::ImageResult<u32> {
+    let file = File::open(path)?;
+    let reader = png::Decoder::new(BufReader::new(file))
+        .read_info()
+        .map_err(|err| {
+            image::ImageError::Decoding(image::error::DecodingError::new(
+                ImageFormat::Png.into(),
+                err,
+            ))
+        })?;
+    Ok(reader
+        .info()
+        .animation_control
+        .map(|control| control.num_plays)
+        .unwrap_or_default())
+}
+
+/// Options for `save` that only apply to some output formats
+#[derive(Debug, Clone)]
+pub struct SaveOptions {
+    /// Write PNGs as indexed color with these colors at the start of the color table
+    pub indexed: Option<Vec<[u8; 3]>>,
+    /// The sizes of the images in an .ico
+    pub ico_sizes: Vec<u32>,
+}
+
+impl Default for SaveOptions {
+    fn default() -> Self {
+        SaveOptions {
+            indexed: None,
+            ico_sizes: vec![16, 32, 48, 256],
+        }
+    }
+}
+
+/// Save `decoded` to `path` as `format`.
+///
+/// Animations keep all of their frames when saved as GIF or PNG (APNG), otherwise only the first
+/// frame is written. 16 bit images stay 16 bit when saved as PNG or TIFF. The EXIF data and ICC
+/// profile of the input are kept for PNG, JPEG and WebP.
+pub fn save(
+    decoded: &Decoded,
+    path: &Path,
+    format: ImageFormat,
+    options: &SaveOptions,
+) -> image::ImageResult<()> {
+    let container = decoded.container;
+    let saved = match (format, &options.indexed) {
+        (ImageFormat::Png | ImageFormat::Tiff, None) if decoded.deep.is_some() => decoded
+            .deep
+            .as_ref()
+            .unwrap()
+            .save_with_format(path, format),
+        (ImageFormat::Png, Some(palette)) => convert_indexed_png(
+            &decoded.frames,
+            container.is_animated().then(|| container.plays()),
+            palette,
+            path,
+        ),
+        (ImageFormat::Gif, _) if container.is_animated() => {
+            convert_gif(&decoded.frames, container.repeat(), path)
+        }
+        (ImageFormat::Png, _) if container.is_animated() => {
+            convert_apng(&decoded.frames, container.plays(), path)
+        }
+        (ImageFormat::WebP, _) if container.is_animated() => {
+            convert_animated_webp(&decoded.frames, container.plays(), path)
+        }
+        (ImageFormat::Ico, _) => convert_ico(decoded.frames[0].buffer(), &options.ico_sizes, path),
+        (format, _) => convert_default(decoded.frames[0].buffer(), format, path),
+    };
+    saved?;
+    Ok(metadata::embed(path, format, &decoded.metadata)?)
+}
+
+/// Save a still image as `format`, dropping the alpha channel for formats without one
+pub fn convert_default(
+    image: &RgbaImage,
+    format: ImageFormat,
+    path: &Path,
+) -> image::ImageResult<()> {
+    match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgba8(image.clone())
+            .into_rgb8()
+            .save_with_format(path, format),
+        format => image.save_with_format(path, format),
+    }
+}
+
+/// Encode converted frames as an animated GIF, keeping their delays and the original looping
+pub fn convert_gif(frames: &[Frame], repeat: Repeat, path: &Path) -> image::ImageResult<()> {
+    let file = BufWriter::new(File::create(path)?);
+    // The frames only contain palette colors, so the slower but better quantizer has little to do
+    let mut encoder = GifEncoder::new_with_speed(file, 10);
+    encoder.set_repeat(repeat)?;
+    encoder.encode_frames(frames.iter().cloned())
+}
+
+/// Write an .ico with the image scaled to each of `sizes`, centered on a transparent square
+pub fn convert_ico(image: &RgbaImage, sizes: &[u32], path: &Path) -> image::ImageResult<()> {
+    let (width, height) = image.dimensions();
+    let icons: Vec<RgbaImage> = sizes
+        .iter()
+        .map(|&size| {
+            let scale = size as f32 / width.max(height) as f32;
+            let scaled_width = ((width as f32 * scale).round() as u32).clamp(1, size);
+            let scaled_height = ((height as f32 * scale).round() as u32).clamp(1, size);
+            let scaled = imageops::resize(image, scaled_width, scaled_height, FilterType::Lanczos3);
+            let mut icon = RgbaImage::new(size, size);
+            imageops::overlay(
+                &mut icon,
+                &scaled,
+                ((size - scaled_width) / 2).into(),
+                ((size - scaled_height) / 2).into(),
+            );
+            icon
+        })
+        .collect();
+    let frames = icons
+        .iter()
+        .map(|icon| IcoFrame::as_png(icon.as_raw(), icon.width(), icon.height(), ColorType::Rgba8))
+        .collect::<image::ImageResult<Vec<_>>>()?;
+    IcoEncoder::new(BufWriter::new(File::create(path)?)).encode_images(&frames)
+}
+
+/// Write an animated WebP. Every frame is encoded as lossless VP8L, which suits the few colors
+/// of a palette, and stored in its own ANMF chunk.
+pub fn convert_animated_webp(frames: &[Frame], plays: u32, path: &Path) -> image::ImageResult<()> {
+    const ALPHA_FLAG: u8 = 0x10;
+    const ANIMATION_FLAG: u8 = 0x02;
+    // Frames replace the canvas instead of being alpha blended onto the previous one
+    const NO_BLEND: u8 = 0x02;
+    let u24 = |value: u32| value.min(0xFF_FFFF).to_le_bytes()[..3].to_vec();
+
+    let (width, height) = frames[0].buffer().dimensions();
+    let mut vp8x = vec![ALPHA_FLAG | ANIMATION_FLAG, 0, 0, 0];
+    vp8x.extend(u24(width - 1));
+    vp8x.extend(u24(height - 1));
+    // Transparent background, then the loop count with 0 for infinite like APNG
+    let mut anim = vec![0; 4];
+    anim.extend_from_slice(&(plays.min(u16::MAX.into()) as u16).to_le_bytes());
+
+    let mut body = b"WEBP".to_vec();
+    body.extend(metadata::webp_chunk(b"VP8X", &vp8x));
+    body.extend(metadata::webp_chunk(b"ANIM", &anim));
+    for frame in frames {
+        let buffer = frame.buffer();
+        let mut encoded = Vec::new();
+        WebPEncoder::new_lossless(&mut encoded).encode(
+            buffer.as_raw(),
+            buffer.width(),
+            buffer.height(),
+            ColorType::Rgba8,
+        )?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let mut anmf = u24(frame.left() / 2);
+        anmf.extend(u24(frame.top() / 2));
+        anmf.extend(u24(buffer.width() - 1));
+        anmf.extend(u24(buffer.height() - 1));
+        anmf.extend(u24(numer / denom.max(1)));
+        anmf.push(NO_BLEND);
+        // The VP8L chunk of the still image, without its RIFF header
+        anmf.extend_from_slice(&encoded[12..]);
+        body.extend(metadata::webp_chunk(b"ANMF", &anmf));
+    }
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"RIFF")?;
+    file.write_all(&(body.len() as u32).to_le_bytes())?;
+    file.write_all(&body)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Encode converted frames as an animated PNG, keeping their delays and number of plays
+pub fn convert_apng(frames: &[Frame], plays: u32, path: &Path) -> image::ImageResult<()> {
+    let encoding_err = png_encoding_error;
+    let (width, height) = frames[0].buffer().dimensions();
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, plays)
+        .map_err(encoding_err)?;
+    let mut writer = encoder.write_header().map_err(encoding_err)?;
+    for frame in frames {
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let delay_ms = (numer / denom.max(1)).min(u16::MAX as u32) as u16;
+        writer
+            .set_frame_delay(delay_ms, 1000)
+            .map_err(encoding_err)?;
+        writer
+            .write_image_data(frame.buffer())
+            .map_err(encoding_err)?;
+    }
+    writer.finish().map_err(encoding_err)
+}
+
+/// Encode frames as an indexed color PNG (APNG if `plays` is set).
+///
+/// The color table starts with `palette`, so the PLTE chunk holds the theme colors, followed by
+/// any partially transparent colors found in the frames. The smallest bit depth fitting the
+/// table is used. Fails if the frames use more than 256 distinct colors.
+pub fn convert_indexed_png(
+    frames: &[Frame],
+    plays: Option<u32>,
+    palette: &[[u8; 3]],
+    path: &Path,
+) -> image::ImageResult<()> {
+    let encoding_err = png_encoding_error;
+
+    let mut table: Vec<[u8; 4]> = Vec::with_capacity(palette.len());
+    let mut lookup: HashMap<[u8; 4], u8> = HashMap::with_capacity(palette.len());
+    let mut index_of = |color: [u8; 4]| -> image::ImageResult<u8> {
+        // All fully transparent pixels share one entry
+        let color = if color[3] == 0 { [0; 4] } else { color };
+        if let Some(&index) = lookup.get(&color) {
+            return Ok(index);
+        }
+        let Ok(index) = u8::try_from(table.len()) else {
+            return Err(image::ImageError::Encoding(
+                image::error::EncodingError::new(
+                    ImageFormat::Png.into(),
+                    "The image uses more than 256 distinct colors (including transparency) and can not be saved as indexed PNG",
+                ),
+            ));
+        };
+        table.push(color);
+        lookup.insert(color, index);
+        Ok(index)
+    };
+    for &[r, g, b] in palette {
+        index_of([r, g, b, 255])?;
+    }
+    let indices = frames
+        .iter()
+        .map(|frame| {
+            frame
+                .buffer()
+                .pixels()
+                .map(|pixel| index_of(pixel.0))
+                .collect::<image::ImageResult<Vec<u8>>>()
+        })
+        .collect::<image::ImageResult<Vec<_>>>()?;
+
+    let (depth, bits) = match table.len() {
+        0..=2 => (png::BitDepth::One, 1),
+        3..=4 => (png::BitDepth::Two, 2),
+        5..=16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8),
+    };
+    let (width, height) = frames[0].buffer().dimensions();
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_palette(
+        table
+            .iter()
+            .flat_map(|[r, g, b, _]| [*r, *g, *b])
+            .collect::<Vec<_>>(),
+    );
+    // tRNS only needs to cover entries up to the last one that is not opaque
+    if let Some(last) = table.iter().rposition(|color| color[3] != 255) {
+        encoder.set_trns(
+            table[..=last]
+                .iter()
+                .map(|color| color[3])
+                .collect::<Vec<_>>(),
+        );
+    }
+    if let Some(plays) = plays {
+        encoder
+            .set_animated(frames.len() as u32, plays)
+            .map_err(encoding_err)?;
+    }
+    let mut writer = encoder.write_header().map_err(encoding_err)?;
+    for (frame, indices) in frames.iter().zip(indices) {
+        if plays.is_some() {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            let delay_ms = (numer / denom.max(1)).min(u16::MAX as u32) as u16;
+            writer
+                .set_frame_delay(delay_ms, 1000)
+                .map_err(encoding_err)?;
+        }
+        writer
+            .write_image_data(&pack_indices(&indices, width as usize, bits))
+            .map_err(encoding_err)?;
+    }
+    writer.finish().map_err(encoding_err)
+}
+
+/// Pack one byte per pixel into rows of `bits` per pixel, most significant bits first
+fn pack_indices(indices: &[u8], width: usize, bits: usize) -> Vec<u8> {
+    if bits == 8 {
+        return indices.to_vec();
+    }
+    let per_byte = 8 / bits;
+    let row_len = width.div_ceil(per_byte);
+    let mut packed = Vec::with_capacity(row_len * indices.len() / width.max(1));
+    for row in indices.chunks(width) {
+        for pixels in row.chunks(per_byte) {
+            let mut byte = 0;
+            for (i, index) in pixels.iter().enumerate() {
+                byte |= index << (8 - bits * (i + 1));
+            }
+            packed.push(byte);
+        }
+    }
+    packed
+}
+
+fn png_encoding_error(err: png::EncodingError) -> image::ImageError {
+    image::ImageError::Encoding(image::error::EncodingError::new(
+        ImageFormat::Png.into(),
+        err,
+    ))
+}
+
+fn gif_decoding_error(err: gif::DecodingError) -> image::ImageError {
+    image::ImageError::Decoding(image::error::DecodingError::new(
+        ImageFormat::Gif.into(),
+        err,
+    ))
+}
@@ -1,6 +1,6 @@
 use deltae::LabValue;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Lab {
     l: f32,
     a: f32,
@@ -21,6 +21,57 @@ impl From<[u8; 4]> for Lab {
     }
 }
 
+impl Lab {
+    pub fn new(l: f32, a: f32, b: f32) -> Self {
+        Lab { l, a, b }
+    }
+
+    /// From RGB with channels in the range 0.0..=1.0, for inputs with more than 8 bits per channel
+    pub fn from_rgb_normalized(rgb: [f32; 3]) -> Self {
+        let lab::Lab { l, a, b } = lab::Lab::from_rgb_normalized(&rgb);
+        Lab { l, a, b }
+    }
+
+    /// From lightness, chroma and hue in degrees
+    pub fn from_lch([l, c, h]: [f32; 3]) -> Self {
+        let (sin, cos) = h.to_radians().sin_cos();
+        Lab {
+            l,
+            a: c * cos,
+            b: c * sin,
+        }
+    }
+
+    /// Lightness, chroma and hue in degrees (0.0..360.0)
+    pub fn to_lch(self) -> [f32; 3] {
+        let c = self.a.hypot(self.b);
+        let h = self.b.atan2(self.a).to_degrees().rem_euclid(360.0);
+        [self.l, c, h]
+    }
+
+    pub fn to_array(self) -> [f32; 3] {
+        [self.l, self.a, self.b]
+    }
+
+    pub fn lightness(self) -> f32 {
+        self.l
+    }
+
+    /// Linear interpolation towards `other`, `t` going from 0.0 (`self`) to 1.0 (`other`)
+    pub fn lerp(self, other: Lab, t: f32) -> Self {
+        Lab {
+            l: self.l + (other.l - self.l) * t,
+            a: self.a + (other.a - self.a) * t,
+            b: self.b + (other.b - self.b) * t,
+        }
+    }
+
+    /// Squared euclidean distance, the cheap DeltaE 1976 used for clustering
+    pub fn distance_squared(self, other: Lab) -> f32 {
+        (self.l - other.l).powi(2) + (self.a - other.a).powi(2) + (self.b - other.b).powi(2)
+    }
+}
+
 impl From<Lab> for LabValue {
     fn from(lab: Lab) -> Self {
         LabValue {
@@ -32,14 +83,23 @@ impl From<Lab> for LabValue {
 }
 
 impl Lab {
-    pub fn to_nearest_palette(self, palette: &[Lab], method: deltae::DEMethod) -> Self {
-        let mut min_distance = std::f32::MAX;
+    /// The color of `palette` with the smallest DeltaE divided by its entry in `weights`
+    pub fn to_nearest_palette(
+        self,
+        palette: &[Lab],
+        weights: &[f32],
+        method: deltae::DEMethod,
+    ) -> Self {
+        let mut min_distance = f32::MAX;
         let mut new_color = self;
 
-        for &color in palette {
+        for (&color, &weight) in palette.iter().zip(weights) {
             // let delta = *deltae::DeltaE::new(self, color, deltae::DEMethod::DE2000).value();
-            let delta = *deltae::DeltaE::new(self, color, method).value();
+            let delta = *deltae::DeltaE::new(self, color, method).value() / weight;
 
+            // Only a strictly smaller distance wins, so ties go to the color listed first in the
+            // palette. Identical colors are removed keeping the palette order, see
+            // `config::dedup_identical`
             if delta < min_distance {
                 min_distance = delta;
                 new_color = color;
@@ -62,9 +122,29 @@ impl Lab {
 // Implement DeltaEq for Lab
 impl<D: deltae::Delta + Copy> deltae::DeltaEq<D> for Lab {}
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+/// Decode an sRGB channel in `0.0..=1.0` to linear light. The Lab conversions already do this
+/// internally, it is needed where colors are averaged or interpolated as RGB.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode a linear light channel in `0.0..=1.0` with the sRGB transfer function
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
 pub enum CLIDEMethod {
     /// The default DeltaE method
+    #[default]
     DE2000,
     // /// An implementation of DeltaE with separate tolerances for Lightness and Chroma
     // DECMC(
@@ -81,12 +161,6 @@ pub enum CLIDEMethod {
     DE1976,
 }
 
-impl Default for CLIDEMethod {
-    fn default() -> Self {
-        Self::DE2000
-    }
-}
-
 impl std::fmt::Display for CLIDEMethod {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -110,3 +184,30 @@ impl From<CLIDEMethod> for deltae::DEMethod {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ties_go_to_the_color_listed_first() {
+        let gray = Lab {
+            l: 50.0,
+            a: 0.0,
+            b: 0.0,
+        };
+        let red = Lab { a: 10.0, ..gray };
+        let green = Lab { a: -10.0, ..gray };
+        let weights = [1.0; 2];
+        let method = deltae::DEMethod::DE1976;
+
+        assert_eq!(
+            gray.to_nearest_palette(&[red, green], &weights, method),
+            red
+        );
+        assert_eq!(
+            gray.to_nearest_palette(&[green, red], &weights, method),
+            green
+        );
+    }
+}
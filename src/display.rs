@@ -0,0 +1,138 @@
+use std::{
+    io::{self, Write},
+    sync::OnceLock,
+};
+
+use owo_colors::{OwoColorize, Style};
+use supports_color::Stream;
+
+use crate::{cli::ColorWhen, config::Palette, report::Stats};
+
+/// `--color`, set once at startup
+static COLOR: OnceLock<ColorWhen> = OnceLock::new();
+
+/// Apply `--color` to all colored output
+pub fn set_color(when: ColorWhen) {
+    let _ = COLOR.set(when);
+    owo_colors::set_override(color_enabled(Stream::Stdout));
+}
+
+/// Whether to use colors on `stream`
+pub fn color_enabled(stream: Stream) -> bool {
+    match COLOR.get().copied().unwrap_or(ColorWhen::Auto) {
+        ColorWhen::Always => true,
+        ColorWhen::Never => false,
+        ColorWhen::Auto => supports_color::on_cached(stream).is_some(),
+    }
+}
+
+/// Whether stdout can display 24-bit colors. With `--color always` they are assumed to be
+/// supported unless the terminal is known not to.
+pub fn supports_truecolor() -> bool {
+    let level = supports_color::on_cached(Stream::Stdout);
+    match COLOR.get().copied().unwrap_or(ColorWhen::Auto) {
+        ColorWhen::Always => level.is_none_or(|level| level.has_16m),
+        ColorWhen::Never => false,
+        ColorWhen::Auto => level.is_some_and(|level| level.has_16m),
+    }
+}
+
+fn bold_name(name: &String) -> impl std::fmt::Display + '_ {
+    name.if_supports_color(owo_colors::Stream::Stdout, |text| {
+        let style = Style::new().bold().bright_white();
+        text.style(style)
+    })
+}
+
+/// Print the name and color count of every palette, followed by a grid of swatches when
+/// `truecolor` is set
+pub fn ansi_paint_palette(
+    writer: &mut impl Write,
+    palettes: &[Palette],
+    truecolor: bool,
+) -> io::Result<()> {
+    let max_name = palettes
+        .iter()
+        .map(|p| p.name.as_ref().map(|n| n.len()).unwrap_or_default())
+        .max()
+        .unwrap_or_default();
+    for palette in palettes {
+        if let Some(name) = &palette.name {
+            writeln!(
+                writer,
+                "{:<max_name$} - {} colors{}",
+                bold_name(name),
+                palette.colors.len(),
+                if truecolor { ":" } else { "" }
+            )?;
+        }
+        const WIDTH: usize = 8;
+        if truecolor {
+            for (idx, (_, color)) in palette.colors.iter().enumerate() {
+                let [r, g, b] = color.0;
+                write!(writer, "{}", "  ".on_truecolor(r, g, b))?;
+                if idx % WIDTH == WIDTH - 1 {
+                    writeln!(writer)?;
+                }
+            }
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+/// Print every color of every palette on its own line with a swatch, its name and hex value
+pub fn ansi_paint_palette_details(
+    writer: &mut impl Write,
+    palettes: &[Palette],
+    truecolor: bool,
+) -> io::Result<()> {
+    for (idx, palette) in palettes.iter().enumerate() {
+        if idx > 0 {
+            writeln!(writer)?;
+        }
+        if let Some(name) = &palette.name {
+            writeln!(writer, "{}", bold_name(name))?;
+        }
+        let max_name = palette
+            .colors
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or_default();
+        for (name, color) in &palette.colors {
+            let [r, g, b] = color.0;
+            if truecolor {
+                write!(writer, "{} ", "    ".on_truecolor(r, g, b))?;
+            }
+            writeln!(writer, "{name:<max_name$}  #{r:02x}{g:02x}{b:02x}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Print the palette colors used by a conversion, most used first, followed by the unused ones
+pub fn print_stats(writer: &mut impl Write, stats: &Stats, truecolor: bool) -> io::Result<()> {
+    let max_name = stats
+        .colors
+        .iter()
+        .map(|color| color.name.len())
+        .max()
+        .unwrap_or_default();
+    for color in &stats.colors {
+        if truecolor {
+            let [r, g, b] = crate::config::parse_hex(&color.hex).unwrap_or_default();
+            write!(writer, "{} ", "    ".on_truecolor(r, g, b))?;
+        }
+        let share = color.pixels as f64 / stats.pixels.max(1) as f64 * 100.0;
+        writeln!(
+            writer,
+            "{:<max_name$}  {}  {:>10}  {share:5.1}%",
+            color.name, color.hex, color.pixels
+        )?;
+    }
+    if !stats.unused.is_empty() {
+        writeln!(writer, "Unused: {}", stats.unused.join(", "))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use std::{io, path::PathBuf, process::ExitStatus};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DipcError {
+    /// Invalid combination of command line arguments
+    #[error("{0}")]
+    Usage(String),
+
+    /// The palette source could not be parsed into usable palettes
+    #[error("{0}")]
+    Palette(String),
+
+    #[error("Encountered error while opening image at path {}: {source}", path.display())]
+    Decode {
+        path: PathBuf,
+        #[source]
+        source: image::ImageError,
+    },
+
+    #[error("Encountered error while trying to save image \"{}\": {source}", path.display())]
+    Encode {
+        path: PathBuf,
+        #[source]
+        source: image::ImageError,
+    },
+
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Conversion was cancelled")]
+    Cancelled,
+
+    /// Some images or jobs of a batch failed with `--keep-going`, after their errors were
+    /// reported
+    #[error("{failed} of {total} {unit} failed")]
+    Batch {
+        failed: usize,
+        total: usize,
+        /// What failed, `images` or `jobs`
+        unit: &'static str,
+        /// The exit code of the first failure
+        exit_code: i32,
+    },
+
+    /// ffmpeg failed while decoding or encoding a video for `dipc video`
+    #[error("ffmpeg failed while {step} with {status}{}", match stderr.as_str() {
+        "" => String::new(),
+        stderr => format!(": {stderr}"),
+    })]
+    Ffmpeg {
+        /// `decoding` or `encoding`
+        step: &'static str,
+        status: ExitStatus,
+        /// What ffmpeg printed before it failed
+        stderr: String,
+    },
+
+    /// A request sent to `dipc daemon` failed, with the message and exit code of the daemon
+    #[error("{message}")]
+    Remote { message: String, exit_code: i32 },
+}
+
+impl DipcError {
+    pub fn exit_code(&self) -> i32 {
+        use crate::exit_code;
+
+        match self {
+            DipcError::Usage(_) => exit_code::USAGE,
+            DipcError::Palette(_) => exit_code::PALETTE,
+            DipcError::Decode { .. } => exit_code::DECODE,
+            DipcError::Encode { .. } => exit_code::ENCODE,
+            DipcError::Io { .. } => exit_code::IO,
+            DipcError::Cancelled => exit_code::CANCELLED,
+            DipcError::Ffmpeg { .. } => exit_code::IO,
+            DipcError::Batch { exit_code, .. } => *exit_code,
+            DipcError::Remote { exit_code, .. } => *exit_code,
+        }
+    }
+
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        DipcError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+}
+
+impl From<io::Error> for DipcError {
+    fn from(source: io::Error) -> Self {
+        DipcError::io("IO error", source)
+    }
+}
+
+impl From<crate::convert::Cancelled> for DipcError {
+    fn from(_: crate::convert::Cancelled) -> Self {
+        DipcError::Cancelled
+    }
+}
+
+pub type Result<T, E = DipcError> = std::result::Result<T, E>;
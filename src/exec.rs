@@ -0,0 +1,85 @@
+use std::{
+    io,
+    path::Path,
+    process::{Command, ExitStatus},
+};
+
+use tracing::debug;
+
+use crate::error::{DipcError, Result};
+
+/// Run the `--exec` command for a converted image. The command goes through the shell, so it
+/// can use pipes and `&&`, with the placeholders replaced by quoted values.
+pub fn run(command: &str, input: &Path, output: &Path, palette: &str) -> Result<()> {
+    let command = expand(
+        command,
+        &[
+            ("input", quote(&input.to_string_lossy())),
+            ("output", quote(&output.to_string_lossy())),
+            ("palette", quote(palette)),
+        ],
+    );
+    debug!("Running {command}");
+    let status = shell(&command)
+        .status()
+        .map_err(|err| DipcError::io(format!("Failed to run `{command}`"), err))?;
+    check(&command, status)
+}
+
+/// Replace `{name}` by its value in a single pass, so values that contain placeholders are kept
+/// as they are. Other braces are left alone.
+fn expand(template: &str, values: &[(&str, String)]) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let placeholder = values.iter().find_map(|(name, value)| {
+            let after = rest.strip_prefix(name)?.strip_prefix('}')?;
+            Some((value, after))
+        });
+        match placeholder {
+            Some((value, after)) => {
+                expanded.push_str(value);
+                rest = after;
+            }
+            None => expanded.push('{'),
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(unix)]
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(not(unix))]
+fn quote(value: &str) -> String {
+    format!("\"{value}\"")
+}
+
+fn check(command: &str, status: ExitStatus) -> Result<()> {
+    if status.success() {
+        return Ok(());
+    }
+    Err(DipcError::io(
+        format!("`{command}` failed"),
+        io::Error::other(status.to_string()),
+    ))
+}
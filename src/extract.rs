@@ -0,0 +1,113 @@
+use image::{imageops::FilterType, RgbaImage};
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::delta::Lab;
+
+/// Images are scaled down to at most this many pixels on their longest side before clustering
+const SAMPLE_SIZE: u32 = 256;
+const MAX_ITERATIONS: usize = 24;
+
+/// A color found in an image and the share of the (opaque) pixels closest to it
+#[derive(Debug, Clone, Copy)]
+pub struct Swatch {
+    pub color: Lab,
+    pub share: f32,
+}
+
+/// The (opaque) pixels of `image` scaled down to at most `size` pixels on its longest side
+pub fn sample(image: &RgbaImage, size: u32) -> Vec<Lab> {
+    let (width, height) = image.dimensions();
+    let sample = if width.max(height) > size {
+        let scale = size as f32 / width.max(height) as f32;
+        image::imageops::resize(
+            image,
+            ((width as f32 * scale) as u32).max(1),
+            ((height as f32 * scale) as u32).max(1),
+            FilterType::Triangle,
+        )
+    } else {
+        image.clone()
+    };
+    sample
+        .pixels()
+        .filter(|pixel| pixel[3] > 0)
+        .map(|pixel| Lab::from(pixel.0))
+        .collect()
+}
+
+/// Mean Lab lightness (`0.0..=100.0`) of the opaque pixels of `image`, 0 if there are none
+pub fn mean_lightness(image: &RgbaImage) -> f32 {
+    let pixels = sample(image, SAMPLE_SIZE);
+    let sum: f32 = pixels.iter().map(|pixel| pixel.to_array()[0]).sum();
+    sum / pixels.len().max(1) as f32
+}
+
+/// Find the `count` dominant colors of `image` with k-means clustering in Lab space, sorted from
+/// the most to the least common. Fewer colors are returned for images with few distinct colors.
+pub fn dominant_colors(image: &RgbaImage, count: usize) -> Vec<Swatch> {
+    let mut pixels = sample(image, SAMPLE_SIZE);
+    if pixels.is_empty() || count == 0 {
+        return Vec::new();
+    }
+
+    // Deterministic start: evenly spaced lightness quantiles
+    pixels.sort_by(|a, b| a.to_array()[0].total_cmp(&b.to_array()[0]));
+    let mut centers: Vec<Lab> = (0..count)
+        .map(|idx| pixels[(2 * idx + 1) * pixels.len() / (2 * count)])
+        .collect();
+    let nearest = |centers: &[Lab], pixel: Lab| {
+        centers
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                pixel
+                    .distance_squared(**a)
+                    .total_cmp(&pixel.distance_squared(**b))
+            })
+            .map(|(idx, _)| idx)
+            .unwrap_or_default()
+    };
+
+    let mut sizes = vec![0usize; count];
+    for _ in 0..MAX_ITERATIONS {
+        let assignments: Vec<usize> = pixels
+            .par_iter()
+            .map(|&pixel| nearest(&centers, pixel))
+            .collect();
+        let mut sums = vec![[0f32; 3]; count];
+        sizes = vec![0; count];
+        for (pixel, &cluster) in pixels.iter().zip(&assignments) {
+            for (sum, value) in sums[cluster].iter_mut().zip(pixel.to_array()) {
+                *sum += value;
+            }
+            sizes[cluster] += 1;
+        }
+        let mut moved = 0f32;
+        for ((center, sum), &size) in centers.iter_mut().zip(&sums).zip(&sizes) {
+            if size == 0 {
+                continue;
+            }
+            let [l, a, b] = sum.map(|value| value / size as f32);
+            let new = Lab::new(l, a, b);
+            moved = moved.max(center.distance_squared(new));
+            *center = new;
+        }
+        if moved < 0.01 {
+            break;
+        }
+    }
+
+    let mut swatches: Vec<Swatch> = centers
+        .into_iter()
+        .zip(sizes)
+        .filter(|(_, size)| *size > 0)
+        .map(|(color, size)| Swatch {
+            color,
+            share: size as f32 / pixels.len() as f32,
+        })
+        .collect();
+    swatches.sort_by(|a, b| b.share.total_cmp(&a.share));
+    // Clusters starting on the same color end up identical
+    swatches.dedup_by(|a, b| a.color.to_rgb() == b.color.to_rgb());
+    swatches
+}
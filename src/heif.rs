@@ -0,0 +1,48 @@
+use std::{fs, path::Path};
+
+use image::{
+    error::{DecodingError, ImageFormatHint},
+    DynamicImage, ImageError, ImageResult, RgbaImage,
+};
+use libheif_rs::{ColorSpace, FileTypeResult, HeifContext, HeifError, LibHeif, RgbChroma};
+
+/// Whether the file at `path` is a HEIF image that libheif can decode
+pub fn is_heif(path: &Path) -> bool {
+    let mut header = [0; 12];
+    fs::File::open(path)
+        .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut header))
+        .is_ok_and(|()| libheif_rs::check_file_type(&header) == FileTypeResult::Supported)
+}
+
+/// Decode the primary image of a HEIF/HEIC file. libheif applies the rotation and mirroring
+/// stored in the file, so the EXIF orientation must not be applied again.
+pub fn decode(path: &Path) -> ImageResult<DynamicImage> {
+    let decode_err = |err: HeifError| {
+        ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Name(String::from("HEIF")),
+            err,
+        ))
+    };
+    let data = fs::read(path)?;
+    let context = HeifContext::read_from_bytes(&data).map_err(decode_err)?;
+    let handle = context.primary_image_handle().map_err(decode_err)?;
+    let image = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(decode_err)?;
+
+    let plane = image
+        .planes()
+        .interleaved
+        .expect("libheif decoded RGBA without an interleaved plane");
+    let row_len = plane.width as usize * 4;
+    let pixels = plane
+        .data
+        .chunks(plane.stride)
+        .take(plane.height as usize)
+        .flat_map(|row| &row[..row_len])
+        .copied()
+        .collect();
+    let image = RgbaImage::from_raw(plane.width, plane.height, pixels)
+        .expect("libheif returned the wrong number of pixels");
+    Ok(DynamicImage::ImageRgba8(image))
+}
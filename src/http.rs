@@ -0,0 +1,49 @@
+use std::{io::Read, time::Duration};
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Download `url`, failing if the response is larger than `max_size` bytes
+pub fn get(url: &str, max_size: u64) -> Result<Vec<u8>, String> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .user_agent(concat!("dipc/", env!("CARGO_PKG_VERSION")))
+        .build();
+    let response = agent
+        .get(url)
+        .call()
+        .map_err(|err| format!("Failed to fetch {url}: {err}"))?;
+    let too_large = || format!("Failed to fetch {url}: larger than {max_size} bytes");
+    if response
+        .header("Content-Length")
+        .and_then(|length| length.parse::<u64>().ok())
+        .is_some_and(|length| length > max_size)
+    {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    // One byte more than allowed tells a body of exactly `max_size` from a larger one
+    response
+        .into_reader()
+        .take(max_size + 1)
+        .read_to_end(&mut body)
+        .map_err(|err| format!("Failed to fetch {url}: {err}"))?;
+    if body.len() as u64 > max_size {
+        return Err(too_large());
+    }
+    Ok(body)
+}
+
+/// Whether `arg` is an http(s) URL rather than a path or name
+pub fn is_url(arg: &str) -> bool {
+    arg.starts_with("https://") || arg.starts_with("http://")
+}
+
+/// The last path segment of `url`, without query or fragment
+pub fn file_name(url: &str) -> &str {
+    let path = url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('/');
+    path.rsplit('/').next().unwrap_or_default()
+}
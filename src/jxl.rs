@@ -0,0 +1,97 @@
+use std::{fs::File, io::Read, path::Path};
+
+use image::{
+    error::{DecodingError, ImageFormatHint},
+    DynamicImage, ImageError, ImageResult,
+};
+
+use crate::convert_image_format::Rgba16Image;
+
+/// A bare JPEG XL codestream starts with this
+const CODESTREAM_SIGNATURE: [u8; 2] = [0xFF, 0x0A];
+/// JPEG XL in an ISOBMFF container starts with this `JXL ` box
+const CONTAINER_SIGNATURE: [u8; 12] = [
+    0x00, 0x00, 0x00, 0x0C, 0x4A, 0x58, 0x4C, 0x20, 0x0D, 0x0A, 0x87, 0x0A,
+];
+
+/// Whether the file at `path` starts with a JPEG XL signature
+pub fn is_jxl(path: &Path) -> bool {
+    let mut header = Vec::with_capacity(CONTAINER_SIGNATURE.len());
+    File::open(path)
+        .and_then(|file| {
+            file.take(CONTAINER_SIGNATURE.len() as u64)
+                .read_to_end(&mut header)
+        })
+        .is_ok_and(|_| {
+            header.starts_with(&CODESTREAM_SIGNATURE) || header.starts_with(&CONTAINER_SIGNATURE)
+        })
+}
+
+/// Decode the first frame of a JPEG XL image at 16 bits per channel, so the Lab conversion gets
+/// the precision of high bit depth images
+pub fn decode(path: &Path) -> ImageResult<DynamicImage> {
+    let decode_err = |err: Box<dyn std::error::Error + Send + Sync>| {
+        ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Name(String::from("JPEG XL")),
+            err,
+        ))
+    };
+    let image = jxl_oxide::JxlImage::builder()
+        .open(path)
+        .map_err(decode_err)?;
+    let render = image.render_frame(0).map_err(decode_err)?;
+    let frame = render.image_all_channels();
+
+    let to_u16 = |value: f32| (value.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+    let pixels = frame
+        .buf()
+        .chunks_exact(frame.channels())
+        .flat_map(|pixel| {
+            let rgba = match *pixel {
+                [gray] => [gray, gray, gray, 1.0],
+                [gray, alpha] => [gray, gray, gray, alpha],
+                [r, g, b] => [r, g, b, 1.0],
+                [r, g, b, alpha, ..] => [r, g, b, alpha],
+                [] => [0.0, 0.0, 0.0, 1.0],
+            };
+            rgba.map(to_u16)
+        })
+        .collect();
+    Rgba16Image::from_raw(frame.width() as u32, frame.height() as u32, pixels)
+        .map(DynamicImage::ImageRgba16)
+        .ok_or_else(|| {
+            ImageError::Decoding(DecodingError::new(
+                ImageFormatHint::Name(String::from("JPEG XL")),
+                "decoder returned the wrong number of samples",
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_a_small_lossless_image() {
+        // 4x2 RGBA, written by a lossless encoder
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/small.jxl");
+        assert!(is_jxl(&path));
+
+        let image = decode(&path).unwrap().into_rgba8();
+        assert_eq!(image.dimensions(), (4, 2));
+        let pixels: Vec<[u8; 4]> = image.pixels().map(|pixel| pixel.0).collect();
+        assert_eq!(
+            pixels,
+            [
+                [255, 0, 0, 255],
+                [0, 255, 0, 255],
+                [0, 0, 255, 255],
+                [255, 255, 255, 255],
+                [0, 0, 0, 255],
+                [128, 128, 128, 255],
+                [46, 52, 64, 128],
+                [136, 192, 208, 0],
+            ]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::{fmt, fs::File, io, path::Path, sync::Arc};
+
+use owo_colors::OwoColorize;
+use tracing::{level_filters::LevelFilter, Event, Level, Subscriber};
+use tracing_subscriber::{
+    fmt::{format::Writer, writer::MakeWriterExt, FmtContext, FormatEvent, FormatFields},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    Layer,
+};
+
+use crate::{
+    display,
+    error::{DipcError, Result},
+};
+
+/// Log events on the terminal the way dipc printed them before it used tracing: messages as they
+/// are, errors in red and other levels behind a prefix
+struct Console;
+
+impl<S, N> FormatEvent<S, N> for Console
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut message = String::new();
+        ctx.field_format()
+            .format_fields(Writer::new(&mut message), event)?;
+        let ansi = writer.has_ansi_escapes();
+        match *event.metadata().level() {
+            Level::INFO => writeln!(writer, "{message}"),
+            Level::ERROR if ansi => writeln!(writer, "{}", message.red()),
+            Level::ERROR => writeln!(writer, "{message}"),
+            Level::WARN if ansi => writeln!(writer, "{} {message}", "warning:".yellow()),
+            Level::WARN => writeln!(writer, "warning: {message}"),
+            level if ansi => writeln!(writer, "{} {message}", level.as_str().dimmed()),
+            level => writeln!(writer, "{level} {message}"),
+        }
+    }
+}
+
+/// Log to the terminal at the level given by `-v` or `--quiet`, and to `log_file` with
+/// timestamps. Messages go to stdout and warnings and errors to stderr, unless stdout is used for
+/// the images with `stderr_only`.
+///
+/// The terminal logger is set up even if `log_file` can't be created, so the error can be
+/// reported.
+pub fn init(verbose: u8, quiet: bool, log_file: Option<&Path>, stderr_only: bool) -> Result<()> {
+    let level = match verbose {
+        _ if quiet => LevelFilter::WARN,
+        0 => LevelFilter::INFO,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let console = tracing_subscriber::fmt::layer()
+        .event_format(Console)
+        .with_writer(
+            io::stderr
+                .with_max_level(match stderr_only {
+                    true => Level::TRACE,
+                    false => Level::WARN,
+                })
+                .or_else(io::stdout),
+        )
+        .with_ansi(display::color_enabled(supports_color::Stream::Stderr))
+        .with_filter(level);
+
+    let file = log_file.map(|path| {
+        File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| DipcError::io(format!("Failed to open log file {:?}", path), err))
+    });
+    match file {
+        Some(Ok(file)) => {
+            // Batch runs are always logged in full, even when the terminal is quiet
+            let file = tracing_subscriber::fmt::layer()
+                .with_writer(Arc::new(file))
+                .with_ansi(false)
+                .with_target(false)
+                .with_filter(level.max(LevelFilter::INFO));
+            tracing_subscriber::registry()
+                .with(console)
+                .with(file)
+                .init();
+            Ok(())
+        }
+        Some(Err(err)) => {
+            tracing_subscriber::registry().with(console).init();
+            Err(err)
+        }
+        None => {
+            tracing_subscriber::registry().with(console).init();
+            Ok(())
+        }
+    }
+}
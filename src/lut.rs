@@ -0,0 +1,245 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{
+    cache::FileCache,
+    delta::{linear_to_srgb, srgb_to_linear},
+    error::{DipcError, Result},
+};
+
+/// A 3D color lookup table, read from an Adobe .cube file or a HALD CLUT image
+#[derive(Debug, Clone)]
+pub struct Lut {
+    /// Entries per channel
+    size: usize,
+    /// `size`^3 output colors in `0.0..=1.0`, red changing fastest and blue slowest
+    table: Vec<[f32; 3]>,
+    /// Input values mapped to the first and the last entry of each channel
+    domain: [[f32; 3]; 2],
+}
+
+/// LUTs that were already read
+static LUT_CACHE: FileCache<Arc<Lut>> = FileCache::new();
+
+impl Lut {
+    /// Like `Lut::open`, but reuses the LUT if it was already read and did not change since
+    pub fn load(path: &Path) -> Result<Arc<Lut>> {
+        LUT_CACHE.get(path, || Lut::open(path).map(Arc::new))
+    }
+
+    /// Read a .cube file, or a HALD CLUT for any other extension
+    pub fn open(path: &Path) -> Result<Lut> {
+        let is_cube = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("cube"));
+        let lut = if is_cube {
+            let text = fs::read_to_string(path)
+                .map_err(|err| DipcError::io(format!("Failed to read LUT {:?}", path), err))?;
+            parse_cube(&text)
+        } else {
+            let image = image::open(path).map_err(|source| DipcError::Decode {
+                path: path.to_path_buf(),
+                source,
+            })?;
+            from_hald(&image.into_rgb8())
+        };
+        lut.map_err(|err| DipcError::Palette(format!("Failed to parse LUT {:?}: {err}", path)))
+    }
+
+    /// Look up `rgb` with trilinear interpolation between the surrounding entries. With `linear`
+    /// the LUT is indexed by linear light values, and the entries are blended in linear light.
+    pub fn map(&self, rgb: [u8; 3], linear: bool) -> [u8; 3] {
+        let max = (self.size - 1) as f32;
+        let [domain_min, domain_max] = self.domain;
+        let position = [0, 1, 2].map(|c| {
+            let value = match linear {
+                true => srgb_to_linear(rgb[c] as f32 / 255.0),
+                false => rgb[c] as f32 / 255.0,
+            };
+            let value = (value - domain_min[c]) / (domain_max[c] - domain_min[c]);
+            value.clamp(0.0, 1.0) * max
+        });
+        let low = position.map(|p| (p.floor() as usize).min(self.size - 2));
+        let fraction = [0, 1, 2].map(|c| position[c] - low[c] as f32);
+        let entry = |r: usize, g: usize, b: usize| {
+            self.table[r + g * self.size + b * self.size * self.size]
+        };
+
+        let mut out = [0.0; 3];
+        for corner in 0..8 {
+            let offset = [corner & 1, corner >> 1 & 1, corner >> 2 & 1];
+            let weight: f32 = (0..3)
+                .map(|c| match offset[c] {
+                    0 => 1.0 - fraction[c],
+                    _ => fraction[c],
+                })
+                .product();
+            if weight == 0.0 {
+                continue;
+            }
+            let mut color = entry(low[0] + offset[0], low[1] + offset[1], low[2] + offset[2]);
+            if linear {
+                color = color.map(srgb_to_linear);
+            }
+            for c in 0..3 {
+                out[c] += color[c] * weight;
+            }
+        }
+        if linear {
+            out = out.map(linear_to_srgb);
+        }
+        out.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+    }
+}
+
+fn parse_cube(text: &str) -> std::result::Result<Lut, String> {
+    let mut size = None;
+    let mut domain_min = [0.0; 3];
+    let mut domain_max = [1.0; 3];
+    let mut domain_line = 0;
+    let mut table = Vec::new();
+    let parse_triple = |values: &[&str]| -> std::result::Result<[f32; 3], String> {
+        let [r, g, b] = values else {
+            return Err(format!("Expected 3 values, found {}", values.len()));
+        };
+        let parse = |value: &str| {
+            value
+                .parse::<f32>()
+                .map_err(|_| format!("`{value}` is not a number"))
+        };
+        Ok([parse(r)?, parse(g)?, parse(b)?])
+    };
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let keyword = words.next().unwrap_or_default();
+        let values: Vec<&str> = words.collect();
+        let at_line = |err: String| format!("line {}: {err}", number + 1);
+        match keyword {
+            "TITLE" => {}
+            "LUT_1D_SIZE" => return Err(String::from("1D LUTs are not supported")),
+            "LUT_3D_SIZE" => {
+                let parsed = values
+                    .first()
+                    .and_then(|value| value.parse::<usize>().ok())
+                    .filter(|size| (2..=256).contains(size))
+                    .ok_or_else(|| at_line(String::from("Invalid LUT_3D_SIZE")))?;
+                size = Some(parsed);
+            }
+            "DOMAIN_MIN" => {
+                domain_min = parse_triple(&values).map_err(at_line)?;
+                domain_line = number + 1;
+            }
+            "DOMAIN_MAX" => {
+                domain_max = parse_triple(&values).map_err(at_line)?;
+                domain_line = number + 1;
+            }
+            _ => {
+                let mut triple = vec![keyword];
+                triple.extend(values);
+                table.push(parse_triple(&triple).map_err(at_line)?);
+            }
+        }
+    }
+
+    let size = size.ok_or("Missing LUT_3D_SIZE")?;
+    if table.len() != size.pow(3) {
+        return Err(format!(
+            "Expected {} entries for LUT_3D_SIZE {size}, found {}",
+            size.pow(3),
+            table.len()
+        ));
+    }
+    // Checked once both are read, they may come in any order
+    if (0..3).any(|c| domain_min[c] >= domain_max[c]) {
+        return Err(format!(
+            "line {domain_line}: DOMAIN_MIN {domain_min:?} must be below DOMAIN_MAX {domain_max:?} in every channel"
+        ));
+    }
+    Ok(Lut {
+        size,
+        table,
+        domain: [domain_min, domain_max],
+    })
+}
+
+fn from_hald(image: &image::RgbImage) -> std::result::Result<Lut, String> {
+    let (width, height) = image.dimensions();
+    let level = (2..=16)
+        .find(|level: &u32| level.pow(3) == width)
+        .filter(|_| width == height)
+        .ok_or_else(|| {
+            format!("A HALD CLUT is a square image of level^3 pixels, found {width}x{height}")
+        })?;
+    Ok(Lut {
+        size: (level * level) as usize,
+        table: image
+            .pixels()
+            .map(|pixel| pixel.0.map(|c| c as f32 / 255.0))
+            .collect(),
+        domain: [[0.0; 3], [1.0; 3]],
+    })
+}
+
+/// Write `lut`, a `size`^3 table in the order of `Lut`, as an Adobe .cube file
+pub fn write_cube(
+    writer: &mut impl Write,
+    title: &str,
+    size: u32,
+    lut: &[[u8; 3]],
+) -> io::Result<()> {
+    writeln!(writer, "# Created by dipc {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(writer, "TITLE \"{title}\"")?;
+    writeln!(writer, "LUT_3D_SIZE {size}")?;
+    for [r, g, b] in lut {
+        let [r, g, b] = [r, g, b].map(|c| *c as f32 / 255.0);
+        writeln!(writer, "{r:.6} {g:.6} {b:.6}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2x2 identity .cube with the given DOMAIN lines
+    fn identity_cube(domain: &str) -> String {
+        let mut text = format!("LUT_3D_SIZE 2\n{domain}\n");
+        for b in 0..2 {
+            for g in 0..2 {
+                for r in 0..2 {
+                    text.push_str(&format!("{r} {g} {b}\n"));
+                }
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn domain_scales_the_input() {
+        let lut = parse_cube(&identity_cube("DOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 1 0.5")).unwrap();
+        // Red covers twice the usual range, blue half of it, the table itself is left alone
+        assert_eq!(lut.map([255, 255, 255], false), [128, 255, 255]);
+        assert_eq!(lut.map([64, 64, 64], false), [32, 64, 128]);
+        assert_eq!(lut.map([0, 0, 0], false), [0, 0, 0]);
+
+        let lut = parse_cube(&identity_cube("DOMAIN_MAX 1 1 1\nDOMAIN_MIN 0.5 0 0")).unwrap();
+        assert_eq!(lut.map([64, 191, 255], false), [0, 191, 255]);
+    }
+
+    #[test]
+    fn rejects_empty_or_inverted_domains() {
+        let err = parse_cube(&identity_cube("DOMAIN_MIN 0 0 0.5\nDOMAIN_MAX 1 1 0.5")).unwrap_err();
+        assert!(err.starts_with("line 3: "), "{err}");
+        let err = parse_cube(&identity_cube("DOMAIN_MIN 1 0 0")).unwrap_err();
+        assert!(err.starts_with("line 2: "), "{err}");
+    }
+}
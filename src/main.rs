@@ -1,265 +1,1578 @@
-use std::io::{self, stdout, BufWriter, Write};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, stdout, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Once,
+    },
+};
 
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 use delta::Lab;
-use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
-use owo_colors::{OwoColorize, Style};
-use rayon::{
-    prelude::{IntoParallelRefIterator, ParallelIterator},
-    slice::ParallelSliceMut,
-};
+use image::{imageops, GrayImage, ImageFormat, Luma};
+use tracing::{debug, error, info, trace, warn};
 
 use crate::{
-    cli::Cli,
-    config::{output_file_name, parse_palette},
+    cli::{Cli, ColorPaletteStyles, OutputFormat, ProgressFormat, StatsFormat},
+    config::{
+        blend_colors, dedup_colors, dedup_identical, expand_shades, filter_colors,
+        output_file_name, parse_palette, Palette,
+    },
+    convert::{Gradient, Mode, Usage},
+    convert_image_format::{Decoded, SaveOptions},
+    error::{DipcError, Result},
+    lut::Lut,
+    metadata::Metadata,
+    output::Output,
+    progress::Progress,
+    regions::Regions,
+    remap::{Remap, RemapFile},
+    report::{Report, Stats},
+    settings::Settings,
 };
 
+mod cache;
 mod cli;
+mod commands;
 mod config;
+mod convert;
+mod convert_image_format;
 mod delta;
+mod display;
+mod error;
+mod exec;
+mod extract;
+mod fuzzy;
+#[cfg(feature = "heif")]
+mod heif;
+mod http;
+#[cfg(feature = "jxl")]
+mod jxl;
+mod logging;
+mod lut;
+mod memory;
+mod metadata;
+mod metrics;
+mod output;
+mod palette_formats;
 mod palettes;
+mod postprocess;
+mod preview;
+mod priority;
+mod progress;
+mod regions;
+mod remap;
+mod report;
+mod resume;
+mod settings;
+mod stream;
+mod strips;
+mod walk;
+mod wallpaper;
 
-fn main() -> io::Result<()> {
-    let total_start = std::time::Instant::now();
-    let cli = Cli::parse();
+/// Images with a lower mean Lab lightness get the dark style with `--styles auto`
+const AUTO_STYLE_LIGHTNESS: f32 = 50.0;
 
-    let stdout = stdout().lock();
-    let mut writer = BufWriter::new(stdout);
+/// Exit codes reported by dipc, so scripts can tell failures apart.
+/// Keep in sync with the "Exit codes" section of the README and `--help`.
+mod exit_code {
+    /// Everything was converted successfully
+    pub const SUCCESS: i32 = 0;
+    /// Invalid or conflicting command line arguments
+    pub const USAGE: i32 = 1;
+    /// The palette could not be found or parsed, or a requested style does not exist
+    pub const PALETTE: i32 = 2;
+    /// An input image could not be read or decoded
+    pub const DECODE: i32 = 3;
+    /// An output image could not be encoded or saved
+    pub const ENCODE: i32 = 4;
+    /// Any other IO failure, e.g. the output directory could not be created
+    pub const IO: i32 = 5;
+    /// The conversion was interrupted with Ctrl-C
+    pub const CANCELLED: i32 = 130;
+}
 
-    if cli.process.is_empty() {
-        eprintln!(
-            "{}",
-            "You need to provide at least a single image to process"
-                .if_supports_color(owo_colors::Stream::Stderr, |text| text.red())
-        );
-        std::process::exit(127)
+fn main() {
+    let (mut cli, matches) = match Cli::command()
+        .try_get_matches()
+        .and_then(|matches| Ok((Cli::from_arg_matches(&matches)?, matches)))
+    {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            let _ = err.print();
+            std::process::exit(clap_exit_code(&err))
+        }
     };
-    if let Some(output_vec) = &cli.output {
-        if output_vec.is_empty() {
-            eprintln!(
-                "{}",
-                "You need to provide at least a single output image name or path"
-                    .if_supports_color(owo_colors::Stream::Stderr, |text| text.red())
-            );
+
+    display::set_color(cli.color);
+    let result = logging::init(
+        cli.verbose,
+        cli.quiet,
+        cli.log_file.as_deref(),
+        cli.stream || cli.raw_frames.is_some(),
+    )
+    .and_then(|_| Settings::load())
+    .and_then(|settings| settings.apply(&mut cli, &matches))
+    .and_then(|_| run(cli));
+    if let Err(err) = result {
+        error!("{err}");
+        std::process::exit(err.exit_code())
+    }
+}
+
+/// Clap exits with 2 on every usage error by default, which would clash with `exit_code::PALETTE`
+fn clap_exit_code(err: &clap::Error) -> i32 {
+    if err.use_stderr() {
+        exit_code::USAGE
+    } else {
+        // --help and --version
+        exit_code::SUCCESS
+    }
+}
+
+/// The variations converted to at once, with everything needed to match pixels to them
+struct Target<'a> {
+    variations: &'a [Palette],
+    palette_lab: Vec<Lab>,
+    weights: Vec<f32>,
+    /// --map, resolved against the variations
+    remap: Option<Remap>,
+    /// Lightness ramp of the variations for --mode gradient-map
+    gradient: Option<Gradient>,
+}
+
+impl<'a> Target<'a> {
+    fn new(variations: &'a [Palette], cli: &Cli, remap_file: Option<&RemapFile>) -> Result<Self> {
+        let palette_lab = convert::palette_lab(variations);
+        let remap = remap_file
+            .map(|file| file.resolve(variations, cli.map_tolerance))
+            .transpose()?;
+        let gradient =
+            (cli.mode == Mode::GradientMap).then(|| Gradient::new(&palette_lab, cli.interpolate));
+        Ok(Target {
+            variations,
+            weights: convert::palette_weights(variations),
+            palette_lab,
+            remap,
+            gradient,
+        })
+    }
+}
+
+/// Set by Ctrl-C, which stops the running conversion before its output is written. A second
+/// Ctrl-C exits immediately. The handler is installed the first time this is called.
+fn cancel_token() -> &'static AtomicBool {
+    static CANCEL: AtomicBool = AtomicBool::new(false);
+    static HANDLER: Once = Once::new();
+    HANDLER.call_once(|| {
+        ctrlc::set_handler(|| {
+            if CANCEL.swap(true, Ordering::SeqCst) {
+                std::process::exit(exit_code::CANCELLED)
+            }
+        })
+        .expect("Failed to set Ctrl-C handler");
+    });
+    &CANCEL
+}
+
+fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Some(command) => commands::run(command),
+        None => {
+            if cli.nice {
+                priority::lower();
+            }
+            process(cli)
         }
     }
+}
+
+/// Convert the images given on the command line
+fn process(cli: Cli) -> Result<()> {
+    with_threads(cli.deterministic, || {
+        process_to(cli, &mut BufWriter::new(stdout().lock()))
+    })
+}
+
+/// Threads of the pool `--deterministic` converts on
+const DETERMINISTIC_THREADS: usize = 1;
+
+/// Run `convert` on `DETERMINISTIC_THREADS` threads with `deterministic`, or on the global pool
+fn with_threads(deterministic: bool, convert: impl FnOnce() -> Result<()> + Send) -> Result<()> {
+    if !deterministic {
+        return convert();
+    }
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(DETERMINISTIC_THREADS)
+        .build()
+        .map_err(|err| {
+            DipcError::io(
+                "Failed to start the conversion threads",
+                io::Error::other(err),
+            )
+        })?
+        .install(convert)
+}
+
+/// Convert the images given on the command line, writing the palettes, stats and metrics to
+/// `writer` instead of stdout
+fn process_to(mut cli: Cli, writer: &mut impl Write) -> Result<()> {
+    let total_start = std::time::Instant::now();
+    let output = Output::new(&cli);
+    let cancel = cancel_token();
+
+    // Streamed images are written to a temporary directory to be converted like files
+    let stream_dir = cli.stream.then(stream::dir).transpose()?;
+    let mut other_files = Vec::new();
+    if let Some(dir) = &stream_dir {
+        cli.dir_output = Some(dir.path().to_path_buf());
+    } else {
+        let inputs = walk::expand(&cli)?;
+        cli.process = inputs.images;
+        cli.input_dirs = inputs.dirs;
+        other_files = inputs.others;
+    }
+    if cli.copy_others && cli.dir_output.is_none() {
+        return Err(DipcError::Usage(
+            "--copy-others needs an output directory (--dir-output)".to_string(),
+        ));
+    }
+    if stream_dir.is_none() && cli.process.is_empty() && cli.raw_frames.is_none() {
+        return Err(DipcError::Usage(
+            "You need to provide at least a single image to process".to_string(),
+        ));
+    };
     match &cli.output {
+        Some(output_vec) if output_vec.is_empty() => {
+            return Err(DipcError::Usage(
+                "You need to provide at least a single output image name or path".to_string(),
+            ));
+        }
         Some(output_vec) if output_vec.len() != cli.process.len() => {
-            eprintln!(
-                "{}",
+            return Err(DipcError::Usage(
                 "You need to provide the same amount of output image names/paths as input images"
-                    .if_supports_color(owo_colors::Stream::Stderr, |text| text.red())
-            );
-            std::process::exit(127)
+                    .to_string(),
+            ));
         }
         _ => {}
     }
 
-    println!(
-        "Color palette: {}\nStyles: {:?}\nDeltaE method: {}",
-        cli.color_palette, cli.styles, cli.method
-    );
+    if cli.set_wallpaper.is_some() && cli.process.len() > 1 {
+        return Err(DipcError::Usage(
+            "--set-wallpaper only works with a single image".to_string(),
+        ));
+    }
+    if cli.indexed
+        && cli
+            .format
+            .is_some_and(|format| !matches!(format, OutputFormat::Png | OutputFormat::Apng))
+    {
+        return Err(DipcError::Usage(
+            "--indexed only works with PNG output".to_string(),
+        ));
+    }
+
+    let lut = cli.apply_lut.as_deref().map(Lut::load).transpose()?;
+    match &cli.apply_lut {
+        Some(path) => info!("LUT: {}", path.display()),
+        None => info!(
+            "Color palette: {}\nStyles: {:?}\nDeltaE method: {}",
+            cli.color_palette, cli.styles, cli.method
+        ),
+    }
     match &cli.dir_output {
         Some(path) if !path.is_dir() => {
-            eprintln!(
+            info!(
                 "Output directory \"{}\" does not exist.\nAttempting to create it.",
                 path.display()
             );
-            if let Err(err) = std::fs::create_dir_all(path) {
-                eprintln!(
-                    "Creating provided output directory failed with error: {}",
-                    err.if_supports_color(owo_colors::Stream::Stderr, |text| text.red())
-                );
-                std::process::exit(127)
-            };
+            std::fs::create_dir_all(path).map_err(|err| {
+                DipcError::io("Creating provided output directory failed with error", err)
+            })?;
         }
         _ => {}
     }
-    if let Some(path) = &cli.dir_output {
-        println!("Writing results to {:#?} directory.", path);
+    let dir_output = cli.dir_output.as_deref().unwrap_or(Path::new(""));
+    walk::mirror(&cli.input_dirs, &other_files, dir_output)?;
+    if stream_dir.is_none() && cli.raw_frames.is_none() {
+        if let Some(path) = &cli.dir_output {
+            info!("Writing results to {:#?} directory.", path);
+        }
+        info!("Processing {:#?}", &cli.process);
     }
-    println!("Processing {:#?}", &cli.process);
     if let Some(output_vec) = &cli.output {
-        println!("Output names: {:#?}", output_vec);
+        info!("Output names: {:#?}", output_vec);
     }
 
-    let mut palettes = match parse_palette(cli.color_palette.clone().get_json(), &cli.styles) {
-        Ok(p) => p,
-        Err(err) => {
-            eprintln!(
-                "{}",
-                err.if_supports_color(owo_colors::Stream::Stderr, |text| text.red())
-            );
-            std::process::exit(127)
+    // With --styles auto both the dark and the light variation are loaded, one is picked per image
+    let auto_styles = match (&cli.styles, &lut) {
+        (ColorPaletteStyles::Auto, None) => {
+            Some(cli.color_palette.auto_styles().ok_or_else(|| {
+                DipcError::Palette(format!(
+                    "{} has no dark and light styles to pick from with `--styles auto`",
+                    cli.color_palette
+                ))
+            })?)
         }
+        _ => None,
+    };
+    let style_sets = match auto_styles {
+        Some((dark, light)) => [dark, light]
+            .map(|style| ColorPaletteStyles::Some {
+                styles: vec![style.to_string()],
+            })
+            .to_vec(),
+        None => vec![cli.styles.clone()],
     };
-    // Print palettes
-    let color = match supports_color::on_cached(supports_color::Stream::Stdout) {
-        Some(level) => level.has_16m,
-        None => false,
+    let blend_colors_with: Option<Vec<_>> = match &cli.blend_with {
+        Some(blend) if lut.is_none() => Some(
+            commands::parse_palettes(&blend.palette, None)?
+                .into_iter()
+                .flat_map(|palette| palette.colors)
+                .collect(),
+        ),
+        _ => None,
     };
-    let max_name = palettes
+    let mut palette_sets = Vec::with_capacity(style_sets.len());
+    for styles in &style_sets {
+        let mut palettes = match lut {
+            Some(_) => Vec::new(),
+            None => parse_palette(
+                cli.color_palette.clone().get_json(),
+                styles,
+                cli.color_palette.style_aliases(),
+            )
+            .map_err(DipcError::Palette)?,
+        };
+        if cli.only_colors.is_some() || cli.exclude_colors.is_some() {
+            filter_colors(
+                &mut palettes,
+                cli.only_colors.as_deref(),
+                cli.exclude_colors.as_deref().unwrap_or_default(),
+            )
+            .map_err(DipcError::Palette)?;
+        }
+        if let (Some(blend), Some(colors)) = (&cli.blend_with, &blend_colors_with) {
+            for palette in &mut palettes {
+                blend_colors(palette, colors, blend.ratio, cli.method.into());
+            }
+        }
+        if let Some(adjust) = cli.palette_adjust {
+            for (_name, color) in palettes.iter_mut().flat_map(|palette| &mut palette.colors) {
+                color.0 = adjust.apply(color.0);
+            }
+        }
+        if output.chatter {
+            display::ansi_paint_palette(writer, &palettes, display::supports_truecolor())?;
+        }
+        // Remove duplicate colors
+        for palette in &mut palettes {
+            match cli.dedup_delta {
+                _ if cli.no_dedup => {}
+                Some(delta) => dedup_colors(palette, delta, cli.method.into()),
+                None => dedup_identical(palette),
+            }
+        }
+        if let Some(steps) = cli.expand_shades {
+            for palette in &mut palettes {
+                expand_shades(palette, steps);
+            }
+        }
+        palette_sets.push(palettes);
+    }
+    writer.flush()?;
+
+    // Each target produces one output image per input: either all variations merged into one
+    // palette, or one output for every variation with --separate
+    let remap_file = cli.map.as_deref().map(RemapFile::open).transpose()?;
+    let target = |variations| Target::new(variations, &cli, remap_file.as_ref());
+    let target_sets: Vec<Vec<Target>> = palette_sets
+        .iter()
+        .map(|palettes| match cli.separate {
+            true => palettes.chunks(1).map(target).collect::<Result<_>>(),
+            false => Ok(vec![target(palettes)?]),
+        })
+        .collect::<Result<_>>()?;
+    let mask = cli
+        .mask
+        .as_deref()
+        .map(|path| {
+            image::open(path)
+                .map(|mask| mask.into_luma8())
+                .map_err(|source| DipcError::Decode {
+                    path: path.to_path_buf(),
+                    source,
+                })
+        })
+        .transpose()?;
+    let method = deltae::DEMethod::from(cli.method);
+    let keep_colors: Vec<Lab> = cli
+        .keep_color
         .iter()
-        .map(|p| p.name.as_ref().map(|n| n.len()).unwrap_or_default())
-        .max()
+        .map(|&color| Lab::from(color))
+        .collect();
+    let regions = cli.regions.as_deref().map(Regions::open).transpose()?;
+    let region_matchers = regions
+        .as_ref()
+        .map(|regions| regions.matchers(method))
         .unwrap_or_default();
-    for palette in &palettes {
-        if let Some(name) = &palette.name {
-            writeln!(
-                writer,
-                "{:<max_name$} - {} colors{}",
-                name.if_supports_color(owo_colors::Stream::Stdout, |text| {
-                    let style = Style::new().bold().bright_white();
-                    text.style(style)
-                }),
-                palette.colors.len(),
-                if color { ":" } else { "" }
-            )?;
-        }
-        const WIDTH: usize = 8;
-        let mut idx = 0;
-        if color {
-            for (_, color) in &palette.colors {
-                let [r, g, b] = color.0;
-                write!(writer, "{}", "  ".on_truecolor(r, g, b))?;
-                if idx % WIDTH == WIDTH - 1 {
-                    writeln!(writer)?;
+    let open_options = convert_image_format::OpenOptions {
+        tone_map: cli.tone_map,
+    };
+
+    let pipeline = Pipeline {
+        cli: &cli,
+        cancel,
+        lut: lut.as_deref(),
+        auto_styles,
+        target_sets: &target_sets,
+        mask: mask.as_ref(),
+        method,
+        keep_colors: &keep_colors,
+        regions: regions.as_ref(),
+        region_matchers: &region_matchers,
+        open_options: &open_options,
+        progress: output.progress,
+    };
+    if stream_dir.is_some() {
+        return stream::run(&pipeline);
+    }
+    if let Some((width, height)) = cli.raw_frames {
+        return stream::run_raw(&pipeline, width, height);
+    }
+
+    // `{palette}` of --exec
+    let palette_name = match &cli.apply_lut {
+        Some(path) => path.display().to_string(),
+        None => cli.palette.clone().unwrap_or_default(),
+    };
+
+    // A failing image stops a single conversion, but only gets reported when converting several
+    let keep_going = !cli.fail_fast && (cli.keep_going || cli.process.len() > 1);
+    let mut failures = Vec::new();
+    for (idx, path) in cli.process.iter().enumerate() {
+        let result = pipeline.convert(idx, path, writer).and_then(|outputs| {
+            if let Some(command) = &cli.exec {
+                writer.flush()?;
+                for output in &outputs {
+                    exec::run(command, path, output, &palette_name)?;
                 }
-                idx += 1;
             }
-            writeln!(writer)?;
+            match (cli.set_wallpaper, outputs.first()) {
+                (Some(backend), Some(output)) => wallpaper::set(output, backend),
+                _ => Ok(()),
+            }
+        });
+        match result {
+            Ok(()) => {}
+            Err(DipcError::Cancelled) => return Err(DipcError::Cancelled),
+            Err(err) if keep_going => {
+                error!("{err}");
+                failures.push((path, err));
+            }
+            Err(err) => return Err(err),
         }
     }
-    // Remove duplicate colors
-    for palette in &mut palettes {
-        palette.colors.sort_by_key(|(_name, color)| color.0);
-        palette.colors.dedup_by_key(|(_name, color)| color.0)
+
+    debug!(
+        "Total duration: {} seconds.",
+        total_start.elapsed().as_secs_f32()
+    );
+    if let Some(peak) = memory::peak() {
+        trace!("Peak memory usage: {}", memory::format(peak));
     }
-    writer.flush()?;
 
-    let palettes_lab: Vec<_> = palettes
-        .par_iter()
-        .flat_map_iter(|palette| {
-            palette
-                .colors
-                .iter()
-                .map(|(_name, color)| Lab::from(color.0))
+    if failures.is_empty() {
+        return Ok(());
+    }
+    error!("Failed images:");
+    for (path, err) in &failures {
+        error!("  {}: {err}", path.display());
+    }
+    Err(DipcError::Batch {
+        failed: failures.len(),
+        total: cli.process.len(),
+        unit: "images",
+        exit_code: failures[0].1.exit_code(),
+    })
+}
+
+/// Everything `run` sets up once to convert each of the input images
+struct Pipeline<'a> {
+    cli: &'a Cli,
+    cancel: &'a AtomicBool,
+    lut: Option<&'a Lut>,
+    /// The dark and light style with `--styles auto`, matching the two `target_sets`
+    auto_styles: Option<(&'static str, &'static str)>,
+    target_sets: &'a [Vec<Target<'a>>],
+    mask: Option<&'a GrayImage>,
+    method: deltae::DEMethod,
+    keep_colors: &'a [Lab],
+    regions: Option<&'a Regions>,
+    region_matchers: &'a [convert::Matcher<'a>],
+    open_options: &'a convert_image_format::OpenOptions,
+    progress: Option<ProgressFormat>,
+}
+
+impl Pipeline<'_> {
+    /// The output format given on the command line. Indexed output is always PNG
+    fn requested_format(&self) -> Option<ImageFormat> {
+        self.cli
+            .format
+            .map(ImageFormat::from)
+            .or(self.cli.indexed.then_some(ImageFormat::Png))
+    }
+
+    /// Whether every output of `path` already exists and was modified after it, judging the
+    /// output format by the file extension of `path`
+    fn up_to_date(&self, idx: usize, path: &Path) -> bool {
+        let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+        let (Ok(input_format), Ok(input_modified)) = (ImageFormat::from_path(path), modified(path))
+        else {
+            return false;
+        };
+        let format = convert_image_format::output_format(input_format, self.requested_format());
+        let extension = convert_image_format::extension(format);
+        // With --styles auto the outputs of either style will do
+        self.target_sets.iter().any(|targets| {
+            targets.iter().all(|target| {
+                let output = output_path(self.cli, idx, path, target.variations, extension);
+                modified(&output).is_ok_and(|output_modified| output_modified >= input_modified)
+            })
         })
-        .collect();
+    }
 
-    for (idx, path) in cli.process.iter().enumerate() {
-        let start = std::time::Instant::now();
-        // Open image
-        let mut image = match image::open(path) {
-            Ok(i) => i.into_rgba8(),
-            Err(err) => {
-                eprintln!(
-                    "Encountered error while opening image at path {}: {}",
+    /// Convert `path`, the input image at `idx` in `--process`, with every target. Returns the
+    /// paths of the images written.
+    fn convert(&self, idx: usize, path: &Path, writer: &mut impl Write) -> Result<Vec<PathBuf>> {
+        if self.cli.incremental && self.up_to_date(idx, path) {
+            info!("Skipping {}, its output is up to date", path.display());
+            return Ok(Vec::new());
+        }
+        // Images from URLs are saved in the current directory, named after the URL
+        if let Some(url) = path.to_str().filter(|path| http::is_url(path)) {
+            let (name, input) = convert_image_format::open_url(url, self.open_options)?;
+            return self.convert_image(idx, &name, input, writer, None);
+        }
+        if let Some(max) = self.cli.max_memory {
+            if let Ok((width, height)) = image::image_dimensions(path) {
+                let needed = memory::decoded_size(width, height);
+                if needed > max {
+                    match self.strips_unsupported(path) {
+                        None => return self.convert_strips(idx, path, max, writer),
+                        Some(reason) => warn!(
+                            "Decoding {} takes about {}, more than --max-memory, but it can't be converted in strips because {reason}",
+                            path.display(),
+                            memory::format(needed)
+                        ),
+                    }
+                }
+            }
+        }
+        let input = convert_image_format::open(path, self.open_options)?;
+        self.convert_image(idx, path, input, writer, None)
+    }
+
+    /// Why `path` can't be converted a strip of rows at a time, or `None` if it can. Strips are
+    /// only mapped pixel by pixel, everything that needs the whole image rules them out
+    fn strips_unsupported(&self, path: &Path) -> Option<String> {
+        let cli = self.cli;
+        let whole_image_option = [
+            (cli.crop.is_some(), "--crop"),
+            (cli.fit.is_some(), "--fit"),
+            (cli.resize.is_some(), "--resize"),
+            (cli.scale.is_some(), "--scale"),
+            (cli.fast_preview.is_some(), "--fast-preview"),
+            (cli.pixelate.is_some(), "--pixelate"),
+            (cli.simulate.is_some(), "--simulate"),
+            (cli.blur.is_some(), "--blur"),
+            (cli.sharpen.is_some(), "--sharpen"),
+            (cli.grain.is_some(), "--grain"),
+            (cli.mask.is_some(), "--mask"),
+            (!cli.keep_color.is_empty(), "--keep-color"),
+            (cli.hue_range.is_some(), "--hue-range"),
+            (cli.regions.is_some(), "--regions"),
+            (self.auto_styles.is_some(), "--styles auto"),
+            (cli.split_preview, "--split-preview"),
+            (cli.metrics, "--metrics"),
+            (cli.report, "--report"),
+            (cli.preview_term.is_some(), "--preview-term"),
+            (cli.no_save, "--no-save"),
+            (cli.indexed, "--indexed"),
+            (
+                self.requested_format()
+                    .is_some_and(|format| format != ImageFormat::Png),
+                "--format",
+            ),
+        ]
+        .into_iter()
+        .find_map(|(given, option)| given.then_some(option));
+        match whole_image_option {
+            Some(option) => Some(format!("of {option}")),
+            None => strips::unsupported(path).map(String::from),
+        }
+    }
+
+    /// Convert `path`, an image too large to decode within --max-memory, a strip of rows at a
+    /// time with every target. The outputs are always PNGs, see `strips::convert`
+    fn convert_strips(
+        &self,
+        idx: usize,
+        path: &Path,
+        max: u64,
+        writer: &mut impl Write,
+    ) -> Result<Vec<PathBuf>> {
+        let Pipeline {
+            cli,
+            cancel,
+            lut,
+            target_sets,
+            method,
+            region_matchers,
+            progress,
+            ..
+        } = *self;
+        let (width, height) =
+            image::image_dimensions(path).map_err(|source| DipcError::Decode {
+                path: path.to_path_buf(),
+                source,
+            })?;
+        let mut outputs = Vec::with_capacity(target_sets[0].len());
+        for target in &target_sets[0] {
+            let matcher = convert::Matcher {
+                palette: &target.palette_lab,
+                weights: &target.weights,
+                method,
+                remap: target.remap.as_ref(),
+                gradient: target.gradient.as_ref(),
+                regions: region_matchers,
+            };
+            info!(
+                "{} Converting image in strips to stay under --max-memory... (this may take a while)",
+                position(cli, idx)
+            );
+            let start = std::time::Instant::now();
+            let output = output_path(cli, idx, path, target.variations, "png");
+            let metadata = Metadata {
+                text: processing_text(cli, target.variations),
+                ..Metadata::read(path, ImageFormat::Png)
+            };
+            let progress = Progress::new(u64::from(width) * u64::from(height), progress, path, 1);
+            // Like whole images, strips with few distinct colors match each of them once. The
+            // colors are remembered for the following strips, up to the same limit
+            let mut color_map = HashMap::new();
+            let usage = write_atomically(&output, cancel, |partial_path| {
+                strips::convert(path, partial_path, max, &metadata, |frame, deep| {
+                    let colors = (lut.is_none() && deep.is_none() && !cli.no_unique_pass)
+                        .then(|| convert::unique_colors([&*frame]))
+                        .flatten();
+                    if let Some(colors) = colors {
+                        if color_map.len() > convert::MAX_UNIQUE_COLORS {
+                            color_map.clear();
+                        }
+                        let unmatched: Vec<_> = colors
+                            .into_iter()
+                            .filter(|rgb| !color_map.contains_key(rgb))
+                            .collect();
+                        color_map.extend(convert::match_colors(&unmatched, &matcher));
+                        return convert::convert_mapped(frame, &color_map, progress.bar(), cancel);
+                    }
+                    match (lut, deep) {
+                        (Some(lut), _) => {
+                            convert::apply_lut(frame, lut, cli.linear_light, progress.bar(), cancel)
+                                .map(|()| Usage::new())
+                        }
+                        (None, Some(deep)) => convert::convert_image16(
+                            frame,
+                            deep,
+                            &matcher,
+                            None,
+                            progress.bar(),
+                            cancel,
+                        ),
+                        (None, None) => {
+                            convert::convert_image(frame, &matcher, None, progress.bar(), cancel)
+                        }
+                    }
+                })
+            });
+            progress.finish();
+            let usage = usage?;
+            info!("Saved image: {:?}", output.display());
+            outputs.push(output);
+            if let Some(format) = cli.stats {
+                print_stats(writer, format, target.variations, &usage)?;
+            }
+            debug!("Conversion took {} seconds.", start.elapsed().as_secs_f32());
+        }
+        Ok(outputs)
+    }
+
+    /// Convert `input`, read from `path`, with every target. The converted frames are written to
+    /// `raw` as RGBA bytes if it is given, and saved otherwise.
+    fn convert_image(
+        &self,
+        idx: usize,
+        path: &Path,
+        mut input: Decoded,
+        writer: &mut impl Write,
+        mut raw: Option<&mut dyn Write>,
+    ) -> Result<Vec<PathBuf>> {
+        let Pipeline {
+            cli,
+            cancel,
+            lut,
+            auto_styles,
+            target_sets,
+            mask,
+            method,
+            keep_colors,
+            regions,
+            region_matchers,
+            open_options: _,
+            progress,
+        } = *self;
+        let (width, height) = input.dimensions();
+        let crop = match (cli.crop, cli.fit) {
+            (Some(crop), _) => Some(crop.within(width, height).ok_or_else(|| {
+                DipcError::Usage(format!(
+                    "The crop starts outside of {} ({width}x{height})",
                     path.display()
-                        .if_supports_color(owo_colors::Stream::Stderr, |text| text.blue()),
-                    err.if_supports_color(owo_colors::Stream::Stderr, |text| text.red())
+                ))
+            })?),
+            (None, Some(aspect)) => Some(aspect.within(width, height)),
+            (None, None) => None,
+        };
+        if let Some((x, y, width, height)) = crop {
+            input.crop(x, y, width, height);
+        }
+        if !cli.resize_after {
+            resize(&mut input, cli);
+        }
+        if let Some(step) = cli.fast_preview {
+            let (width, height) = input.dimensions();
+            input.resize(
+                (width / step).max(1),
+                (height / step).max(1),
+                imageops::FilterType::Nearest,
+                false,
+            );
+        }
+        if input.frames.len() > 1 {
+            if let Some(fps) = cli.gif_fps {
+                let frames = input.frames.len();
+                input.reduce_frame_rate(fps);
+                debug!("Kept {} of {frames} frames", input.frames.len());
+            }
+            if let Some(scale) = cli.gif_scale {
+                let (width, height) = input.dimensions();
+                input.resize(
+                    ((width as f32 * scale).round() as u32).max(1),
+                    ((height as f32 * scale).round() as u32).max(1),
+                    imageops::FilterType::Lanczos3,
+                    cli.linear_light,
                 );
-                std::process::exit(127)
             }
+        }
+        // Pixel art is converted at its block size, everything down to the output works on that
+        let (width, height) = input.dimensions();
+        if let Some(block) = cli.pixelate {
+            input.resize(
+                (width / block).max(1),
+                (height / block).max(1),
+                imageops::FilterType::Triangle,
+                cli.linear_light,
+            );
+        }
+        if let Some(deficiency) = cli.simulate {
+            for frame in &mut input.frames {
+                postprocess::simulate(frame.buffer_mut(), deficiency);
+            }
+            if let Some(deep) = &mut input.deep {
+                postprocess::simulate(deep, deficiency);
+            }
+        }
+        let labels = regions.map(|regions| {
+            let (width, height) = input.dimensions();
+            regions.labels(width, height)
+        });
+        // One mask per frame, from --mask and the colors protected from the conversion by
+        // --keep-color and --hue-range
+        let protecting = !keep_colors.is_empty() || cli.hue_range.is_some();
+        let masks = (mask.is_some() || protecting).then(|| {
+            let (width, height) = input.dimensions();
+            let mask = match mask {
+                Some(mask) if mask.dimensions() == (width, height) => mask.clone(),
+                Some(mask) => imageops::resize(mask, width, height, imageops::FilterType::Triangle),
+                None => GrayImage::from_pixel(width, height, Luma([u8::MAX])),
+            };
+            input
+                .frames
+                .iter()
+                .map(|frame| {
+                    let mut mask = mask.clone();
+                    if protecting {
+                        postprocess::protect(&mut mask, frame.buffer(), |rgb| {
+                            if cli.hue_range.is_some_and(|range| !range.contains(rgb)) {
+                                return true;
+                            }
+                            let lab = Lab::from(rgb);
+                            keep_colors.iter().any(|&color| {
+                                *deltae::DeltaE::new(lab, color, method).value()
+                                    <= cli.keep_tolerance
+                            })
+                        });
+                    }
+                    mask
+                })
+                .collect::<Vec<_>>()
+        });
+        let original = (cli.split_preview || cli.metrics || masks.is_some())
+            .then(|| (input.frames.clone(), input.deep.clone()));
+        // Masked areas, the split preview and the metrics use the image without the blur
+        if let Some(sigma) = cli.blur {
+            input.blur(sigma, cli.linear_light);
+        }
+        let targets = match (auto_styles, target_sets) {
+            (Some((dark, light)), [dark_targets, light_targets]) => {
+                let lightness = extract::mean_lightness(input.frames[0].buffer());
+                let (style, targets) = match lightness < AUTO_STYLE_LIGHTNESS {
+                    true => (dark, dark_targets),
+                    false => (light, light_targets),
+                };
+                info!("Using style {style} (lightness {lightness:.0})");
+                targets
+            }
+            _ => &target_sets[0],
+        };
+        // 16 bit images are converted to Lab from their full precision copy. Otherwise converting
+        // the image to Lab once pays off when a still image is mapped to several palettes
+        // Grayscale images only have 256 colors to match, which beats any Lab cache. Regions
+        // match the same color differently depending on where it is
+        let grayscale = lut.is_none()
+            && labels.is_none()
+            && input.deep.is_none()
+            && input
+                .frames
+                .iter()
+                .all(|frame| convert::is_grayscale(frame.buffer()));
+        if grayscale {
+            debug!("{} is grayscale", path.display());
+        }
+        // Screenshots and flat art have few distinct colors, each is matched once
+        let unique_colors = (lut.is_none()
+            && !grayscale
+            && !cli.no_unique_pass
+            && labels.is_none()
+            && input.deep.is_none())
+        .then(|| convert::unique_colors(input.frames.iter().map(|frame| frame.buffer())))
+        .flatten();
+        if let Some(colors) = &unique_colors {
+            debug!("{} has {} distinct colors", path.display(), colors.len());
+        }
+        let cache_lab = lut.is_none()
+            && !grayscale
+            && unique_colors.is_none()
+            && (input.deep.is_some() || input.frames.len() == 1 && targets.len() > 1)
+            && fits_in_memory(cli, path, &input, original.is_some());
+        let lab = match (&input.deep, input.frames.as_slice()) {
+            _ if !cache_lab => None,
+            (Some(deep), _) => Some(convert::image16_to_lab(deep)),
+            (None, [frame]) => Some(convert::image_to_lab(frame.buffer())),
+            _ => None,
         };
 
-        println!(
-            "[{}/{}] Converting image... (this may take a while)",
-            idx + 1,
-            cli.process.len()
-        );
+        let mut outputs = Vec::with_capacity(targets.len());
+        for (target_idx, target) in targets.iter().enumerate() {
+            let Target {
+                variations,
+                palette_lab,
+                ..
+            } = target;
+            let matcher = convert::Matcher {
+                palette: palette_lab,
+                weights: &target.weights,
+                method,
+                remap: target.remap.as_ref(),
+                gradient: target.gradient.as_ref(),
+                regions: region_matchers,
+            };
+            let start = std::time::Instant::now();
+            let color_map = unique_colors
+                .as_deref()
+                .map(|colors| convert::match_colors(colors, &matcher));
+            let frames = match input.frames.len() {
+                1 => String::new(),
+                n => format!(" ({n} frames)"),
+            };
+            let position = position(cli, idx);
+            match variations {
+                _ if raw.is_some() => {}
+                [Palette {
+                    name: Some(name), ..
+                }] if cli.separate => info!(
+                    "{position} Converting image{frames} with {name}... (this may take a while)"
+                ),
+                _ => info!("{position} Converting image{frames}... (this may take a while)"),
+            }
 
-        const CHUNK: usize = 4;
-        // Convert image to LAB representation
-        // let mut lab = Vec::with_capacity(image.as_raw().len() / CHUNK);
-        // image
-        //     .par_chunks_exact(CHUNK)
-        //     .map(|pixel| {
-        //         let pixel: [u8; CHUNK] = pixel.try_into().unwrap();
-        //         Lab::from(pixel)
-        //     })
-        //     .collect_into_vec(&mut lab);
-        //
-        // LAB conversion moved into palette
-
-        // Apply palettes to image
-        let progress_bar = ProgressBar::new(
-            (image.len() / CHUNK)
-                .try_into()
-                .expect("Failed to convert usize to u64"),
-        );
-        progress_bar.set_style(
-            ProgressStyle::with_template(
-                "[{elapsed_precise}] [{wide_bar}] {pos}/{len} ({eta_precise})",
-            )
-            .expect("Failed to set progress bar style"),
-        );
-        let progress_bar_clone = progress_bar.clone();
-        image
-            .par_chunks_exact_mut(CHUNK)
-            .progress_with(progress_bar)
-            .for_each(|bytes| {
-                let pixel: [u8; CHUNK] = bytes.try_into().unwrap();
-                let lab = Lab::from(pixel);
-                let new_rgb = lab
-                    .to_nearest_palette(&palettes_lab, deltae::DEMethod::from(cli.method))
-                    .to_rgb();
-                bytes[..3].copy_from_slice(&new_rgb);
-            });
-        progress_bar_clone.finish();
-
-        let output_file_name = match &cli.output {
-            Some(output_vec) => {
-                let mut name = output_vec[idx].clone();
-                name.set_extension("png");
-                match &cli.dir_output {
-                    Some(path) => {
-                        let mut output = path.clone();
-                        output.push(name);
-                        output
+            let mut converted = Decoded {
+                frames: if target_idx + 1 == targets.len() {
+                    std::mem::take(&mut input.frames)
+                } else {
+                    input.frames.clone()
+                },
+                container: input.container,
+                format: input.format,
+                deep: input.deep.clone(),
+                metadata: Metadata {
+                    text: processing_text(cli, variations),
+                    ..input.metadata.clone()
+                },
+            };
+
+            // Apply palettes to image
+            let progress = Progress::new(
+                converted
+                    .frames
+                    .iter()
+                    .map(|frame| convert::pixel_count(frame.buffer()))
+                    .sum(),
+                progress,
+                path,
+                converted.frames.len(),
+            );
+            // Frames converted by an interrupted run are reused. The usage of those frames is
+            // unknown, so --stats converts every frame
+            let spill = (converted.frames.len() > 1 && cli.stats.is_none())
+                .then(|| resume::Spill::open(cli, path, target_idx))
+                .flatten();
+            let mut resumed = 0;
+            let result: std::result::Result<Usage, convert::Cancelled> = converted
+                .frames
+                .iter_mut()
+                .enumerate()
+                .try_fold(Usage::new(), |usage, (frame_idx, frame)| {
+                    progress.set_frame(frame_idx);
+                    if let Some(spill) = &spill {
+                        if spill.load(frame_idx, frame.buffer_mut()) {
+                            progress.bar().inc(convert::pixel_count(frame.buffer()));
+                            resumed += 1;
+                            return Ok(usage);
+                        }
                     }
-                    None => {
-                        let mut output = std::path::PathBuf::new();
-                        output.push(name);
-                        output
+                    let frame_usage = match (&lut, &lab) {
+                        (Some(lut), _) => convert::apply_lut(
+                            frame.buffer_mut(),
+                            lut,
+                            cli.linear_light,
+                            progress.bar(),
+                            cancel,
+                        )
+                        .map(|()| Usage::new()),
+                        (None, Some(lab)) => convert::convert_image_lab(
+                            frame.buffer_mut(),
+                            lab,
+                            &matcher,
+                            labels.as_deref(),
+                            progress.bar(),
+                            cancel,
+                        ),
+                        (None, None) if grayscale => convert::convert_grayscale(
+                            frame.buffer_mut(),
+                            &matcher,
+                            progress.bar(),
+                            cancel,
+                        ),
+                        (None, None) if color_map.is_some() => convert::convert_mapped(
+                            frame.buffer_mut(),
+                            color_map.as_ref().unwrap(),
+                            progress.bar(),
+                            cancel,
+                        ),
+                        (None, None) if input.deep.is_some() => convert::convert_image16(
+                            frame.buffer_mut(),
+                            input.deep.as_ref().unwrap(),
+                            &matcher,
+                            labels.as_deref(),
+                            progress.bar(),
+                            cancel,
+                        ),
+                        (None, None) => convert::convert_image(
+                            frame.buffer_mut(),
+                            &matcher,
+                            labels.as_deref(),
+                            progress.bar(),
+                            cancel,
+                        ),
+                    }?;
+                    if let Some(spill) = &spill {
+                        if let Err(err) = spill.save(frame_idx, frame.buffer()) {
+                            debug!("Failed to keep frame {frame_idx} for resuming: {err}");
+                        }
                     }
+                    Ok(convert::merge_usage(usage, frame_usage))
+                });
+            progress.finish();
+            let usage = result?;
+            if resumed > 0 {
+                info!("Resumed {resumed} frames converted by an earlier run");
+            }
+            if let Some(deep) = &mut converted.deep {
+                convert::copy_rgb_to_deep(converted.frames[0].buffer(), deep);
+            }
+            // The metrics measure the palette mapping, before the passes that follow it. Pixel
+            // art is compared at its block size
+            let metrics = original
+                .as_ref()
+                .filter(|_| cli.metrics)
+                .map(|(original, _)| metrics::compare(original, &converted.frames));
+            if let Some(amount) = cli.sharpen {
+                for frame in &mut converted.frames {
+                    postprocess::sharpen(frame.buffer_mut(), amount);
+                }
+                if let Some(deep) = &mut converted.deep {
+                    postprocess::sharpen(deep, amount);
                 }
             }
-            None => {
-                let mut output = std::path::PathBuf::new();
-                output.push(output_file_name(
-                    &cli.dir_output,
-                    path,
-                    &cli.color_palette,
-                    &palettes,
-                    deltae::DEMethod::from(cli.method),
-                ));
-                output
+            if let Some(amount) = cli.grain {
+                // Every frame of an animation gets its own grain
+                for (frame_idx, frame) in converted.frames.iter_mut().enumerate() {
+                    postprocess::grain(frame.buffer_mut(), amount, cli.seed ^ frame_idx as u64);
+                }
+                if let Some(deep) = &mut converted.deep {
+                    postprocess::grain(deep, amount, cli.seed);
+                }
+            }
+
+            if let (Some(masks), Some((original, original_deep))) = (&masks, &original) {
+                for ((original, frame), mask) in
+                    original.iter().zip(converted.frames.iter_mut()).zip(masks)
+                {
+                    postprocess::apply_mask(
+                        original.buffer(),
+                        frame.buffer_mut(),
+                        mask,
+                        cli.linear_light,
+                    );
+                }
+                if let (Some(original), Some(deep)) = (original_deep, &mut converted.deep) {
+                    postprocess::apply_mask(original, deep, &masks[0], cli.linear_light);
+                }
+            }
+            if let Some((original, original_deep)) = original.as_ref().filter(|_| cli.split_preview)
+            {
+                for (original, frame) in original.iter().zip(converted.frames.iter_mut()) {
+                    postprocess::split_preview(original.buffer(), frame.buffer_mut());
+                }
+                if let (Some(original), Some(deep)) = (original_deep, &mut converted.deep) {
+                    postprocess::split_preview(original, deep);
+                }
+            }
+
+            if let Some(metrics) = &metrics {
+                writeln!(writer, "{metrics}")?;
+                writer.flush()?;
             }
-        };
 
-        match image.save_with_format(&output_file_name, image::ImageFormat::Png) {
-            Ok(_) => println!("Saved image: {:?}", output_file_name.display()),
-            Err(err) => {
-                eprintln!(
-                    "Encountered error while trying to save image \"{}\": {}",
-                    output_file_name.display(),
-                    err.if_supports_color(owo_colors::Stream::Stderr, |text| text.red())
+            if cli.pixelate.is_some() {
+                converted.resize(width, height, imageops::FilterType::Nearest, false);
+            }
+            if cli.resize_after {
+                resize(&mut converted, cli);
+            }
+            if let Some(raw) = &mut raw {
+                for frame in &converted.frames {
+                    raw.write_all(frame.buffer().as_raw())?;
+                }
+                continue;
+            }
+
+            if let Some(protocol) = cli.preview_term {
+                preview::show(writer, converted.frames[0].buffer(), protocol)?;
+                writer.flush()?;
+            }
+            if cli.no_save {
+                if let Some(spill) = spill {
+                    spill.remove();
+                }
+                continue;
+            }
+
+            let format = converted.output_format(self.requested_format());
+            if converted.frames.len() > 1 && !convert_image_format::supports_animation(format) {
+                warn!(
+                    "{} can not store animations, only the first frame will be saved",
+                    convert_image_format::extension(format)
                 );
-                std::process::exit(127)
             }
-        };
+            let output_file_name = output_path(
+                cli,
+                idx,
+                path,
+                variations,
+                convert_image_format::extension(format),
+            );
+            let save_options = SaveOptions {
+                // The colors as they come out of the Lab round trip, which can be off by one
+                indexed: cli
+                    .indexed
+                    .then(|| palette_lab.iter().map(|color| color.to_rgb()).collect()),
+                ico_sizes: cli.sizes.clone(),
+            };
+            save_image(&converted, &output_file_name, format, &save_options, cancel)?;
+            if let Some(spill) = spill {
+                spill.remove();
+            }
+            info!("Saved image: {:?}", output_file_name.display());
+            outputs.push(output_file_name.clone());
+
+            if let Some(format) = cli.stats {
+                print_stats(writer, format, variations, &usage)?;
+            }
+
+            if cli.report {
+                let mut palette_rgb: Vec<[u8; 3]> =
+                    palette_lab.iter().map(|color| color.to_rgb()).collect();
+                // Variations of a theme often share colors
+                palette_rgb.sort_unstable();
+                palette_rgb.dedup();
+                let (width, height) = converted.frames[0].buffer().dimensions();
+                Report {
+                    input: path,
+                    output: &output_file_name,
+                    palette: match &cli.apply_lut {
+                        Some(lut) => format!("LUT: {}", lut.display()),
+                        None => cli.color_palette.to_string(),
+                    },
+                    variations: variations
+                        .iter()
+                        .filter_map(|variation| variation.name.as_deref())
+                        .collect(),
+                    method: cli.method.to_string(),
+                    duration_secs: start.elapsed().as_secs_f64(),
+                    width,
+                    height,
+                    frames: converted.frames.len(),
+                    palette_colors: palette_rgb.len(),
+                    colors_used: report::colors_used(&converted.frames, &palette_rgb),
+                    metrics,
+                }
+                .write()?;
+            }
 
-        if cli.verbose >= 1 {
-            let duration = start.elapsed().as_secs_f32();
-            println!("Conversion took {} seconds.", duration);
+            debug!("Conversion took {} seconds.", start.elapsed().as_secs_f32());
+            if let Some(step) = cli.fast_preview {
+                // The conversion takes time in proportion to the pixels
+                let seconds = start.elapsed().as_secs_f32();
+                info!(
+                    "Preview took {seconds:.2} seconds, the full image would take about {:.1} seconds",
+                    seconds * (step * step) as f32
+                );
+            }
         }
+        Ok(outputs)
     }
+}
 
-    if cli.verbose >= 1 {
-        let duration = total_start.elapsed().as_secs_f32();
-        println!("Total duration: {} seconds.", duration);
+/// `[n/total]` for the `idx`th input. Streams don't know how many images will follow
+fn position(cli: &Cli, idx: usize) -> String {
+    match cli.process.len() {
+        0 => format!("[{}]", idx + 1),
+        total => format!("[{}/{total}]", idx + 1),
     }
+}
 
+/// Print how many pixels were mapped to each color of `variations` for --stats
+fn print_stats(
+    writer: &mut impl Write,
+    format: StatsFormat,
+    variations: &[Palette],
+    usage: &Usage,
+) -> Result<()> {
+    let stats = Stats::new(variations, usage);
+    match format {
+        StatsFormat::Text => display::print_stats(writer, &stats, display::supports_truecolor())?,
+        StatsFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, &stats).map_err(io::Error::from)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
     Ok(())
 }
+
+/// Apply --resize or --scale to `image`
+fn resize(image: &mut Decoded, cli: &Cli) {
+    let (width, height) = image.dimensions();
+    let (new_width, new_height) = match (cli.resize, cli.scale) {
+        (Some(size), _) => size.of(width, height),
+        (None, Some(scale)) => (
+            ((width as f32 * scale).round() as u32).max(1),
+            ((height as f32 * scale).round() as u32).max(1),
+        ),
+        (None, None) => return,
+    };
+    if (new_width, new_height) != (width, height) {
+        image.resize(
+            new_width,
+            new_height,
+            imageops::FilterType::Lanczos3,
+            cli.linear_light,
+        );
+    }
+}
+
+/// The settings an output was made with, as PNG text keywords. Palette and styles are given in
+/// the form the command line accepts them, so the output can be reproduced.
+fn processing_text(cli: &Cli, variations: &[Palette]) -> Vec<(String, String)> {
+    let styles = variations
+        .iter()
+        .map(|variation| variation.name.as_deref())
+        .collect::<Option<Vec<_>>>()
+        .map(|names| names.join(","))
+        .unwrap_or_else(|| String::from("none"));
+    let software = (
+        String::from("Software"),
+        format!("dipc {}", env!("CARGO_PKG_VERSION")),
+    );
+    if let Some(lut) = &cli.apply_lut {
+        return vec![
+            software,
+            (String::from("dipc:lut"), lut.display().to_string()),
+        ];
+    }
+    vec![
+        software,
+        (String::from("dipc:palette"), cli.color_palette.to_string()),
+        (String::from("dipc:styles"), styles),
+        (String::from("dipc:method"), cli.method.to_string()),
+    ]
+}
+
+/// Whether converting `input` with the Lab representation of the whole image in memory stays
+/// under --max-memory. Without it every pixel is converted to Lab as it is mapped
+fn fits_in_memory(cli: &Cli, path: &Path, input: &Decoded, keep_original: bool) -> bool {
+    let Some(max) = cli.max_memory else {
+        return true;
+    };
+    if memory::working_set(input, keep_original, true) <= max {
+        return true;
+    }
+    let lean = memory::working_set(input, keep_original, false);
+    match lean > max {
+        true => warn!(
+            "Converting {} needs about {}, more than --max-memory",
+            path.display(),
+            memory::format(lean)
+        ),
+        false => debug!(
+            "Converting {} without its Lab representation to stay under --max-memory",
+            path.display()
+        ),
+    }
+    false
+}
+
+/// Where the conversion of `input` (the `idx`th input) with `variations` is saved
+fn output_path(
+    cli: &Cli,
+    idx: usize,
+    input: &Path,
+    variations: &[Palette],
+    extension: &str,
+) -> PathBuf {
+    // Images found with --recursive go into the same subdirectory of --dir-output, or of the
+    // working directory, so images with the same name in different directories stay apart
+    let subdir = cli
+        .input_dirs
+        .get(idx)
+        .filter(|subdir| !subdir.as_os_str().is_empty());
+    let dir_output = match (&cli.dir_output, subdir) {
+        (Some(dir), Some(subdir)) => Some(dir.join(subdir)),
+        (None, Some(subdir)) => Some(subdir.clone()),
+        (dir, None) => dir.clone(),
+    };
+    let path = match &cli.output {
+        Some(output_vec) => {
+            let mut name = output_vec[idx].clone();
+            if let [Palette {
+                name: Some(variation),
+                ..
+            }] = variations
+            {
+                // Keep the names given with --output apart when writing one image per variation
+                if cli.separate {
+                    let mut file_name = name.file_stem().unwrap_or_default().to_os_string();
+                    file_name.push(format!("-{}", variation.replace(' ', "_")));
+                    name.set_file_name(file_name);
+                }
+            }
+            name.set_extension(extension);
+            match &dir_output {
+                Some(path) => path.join(name),
+                None => name,
+            }
+        }
+        None => match &cli.apply_lut {
+            // `<input>_<lut>.<extension>`
+            Some(lut) => {
+                let mut name = input.file_stem().unwrap_or_default().to_os_string();
+                name.push("_");
+                name.push(lut.file_stem().unwrap_or_default());
+                name.push(".");
+                name.push(extension);
+                match &dir_output {
+                    Some(path) => path.join(name),
+                    None => PathBuf::from(name),
+                }
+            }
+            None => output_file_name(
+                &dir_output,
+                input,
+                &cli.color_palette,
+                variations,
+                deltae::DEMethod::from(cli.method),
+                cli.mode,
+                extension,
+            ),
+        },
+    };
+    // `<name>_preview.<extension>`, next to where the full conversion goes
+    match cli.fast_preview {
+        Some(_) => {
+            let mut name = path.file_stem().unwrap_or_default().to_os_string();
+            name.push("_preview.");
+            name.push(extension);
+            path.with_file_name(name)
+        }
+        None => path,
+    }
+}
+
+/// Save `image` at `path` as `format`, as an animation if it has several frames
+fn save_image(
+    image: &Decoded,
+    path: &Path,
+    format: ImageFormat,
+    options: &SaveOptions,
+    cancel: &AtomicBool,
+) -> Result<()> {
+    write_atomically(path, cancel, |partial_path| {
+        convert_image_format::save(image, partial_path, format, options).map_err(|source| {
+            DipcError::Encode {
+                path: path.to_path_buf(),
+                source,
+            }
+        })
+    })
+}
+
+/// Write the image at `path` with `write`, which is given a temporary file to write to. It is
+/// only moved to `path` once it is complete, so an interrupted save never leaves a truncated
+/// image behind under the final name.
+fn write_atomically<T>(
+    path: &Path,
+    cancel: &AtomicBool,
+    write: impl FnOnce(&Path) -> Result<T>,
+) -> Result<T> {
+    let mut partial_path = path.to_path_buf().into_os_string();
+    partial_path.push(".part");
+    let partial_path = PathBuf::from(partial_path);
+
+    let saved = write(&partial_path).and_then(|value| {
+        if cancel.load(Ordering::SeqCst) {
+            return Err(DipcError::Cancelled);
+        }
+        std::fs::rename(&partial_path, path).map_err(|err| {
+            DipcError::io(
+                format!("Failed to move image into place at {:?}", path),
+                err,
+            )
+        })?;
+        Ok(value)
+    });
+    if saved.is_err() {
+        let _ = std::fs::remove_file(&partial_path);
+    }
+    saved
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn recursive_outputs_keep_their_subdirectory() {
+        let tree = std::env::temp_dir().join(format!("dipc-output-path-{}", std::process::id()));
+        for dir in ["a", "b"] {
+            fs::create_dir_all(tree.join(dir)).unwrap();
+            fs::write(tree.join(dir).join("bg.png"), []).unwrap();
+        }
+        let mut cli = Cli::parse_from([
+            Path::new("dipc"),
+            Path::new("nord"),
+            &tree,
+            Path::new("--recursive"),
+        ]);
+        cli.color_palette = cli::ColorPalette::Nord;
+        let inputs = walk::expand(&cli).unwrap();
+        fs::remove_dir_all(&tree).unwrap();
+        cli.process = inputs.images;
+        cli.input_dirs = inputs.dirs;
+
+        let paths = |cli: &Cli| -> Vec<PathBuf> {
+            (0..cli.process.len())
+                .map(|idx| output_path(cli, idx, &cli.process[idx], &[], "png"))
+                .collect()
+        };
+        assert_eq!(
+            paths(&cli),
+            [Path::new("a/bg_nord.png"), Path::new("b/bg_nord.png")]
+        );
+        cli.dir_output = Some(PathBuf::from("out"));
+        assert_eq!(
+            paths(&cli),
+            [
+                Path::new("out/a/bg_nord.png"),
+                Path::new("out/b/bg_nord.png")
+            ]
+        );
+    }
+
+    /// Convert `input` with the Nord palette and `options` on `threads` threads, returning the
+    /// output image and what was printed
+    fn convert_with(
+        input: &Path,
+        output: &Path,
+        options: &[&str],
+        threads: usize,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let mut cli = Cli::parse_from(
+            [
+                Path::new("dipc"),
+                Path::new("nord"),
+                input,
+                Path::new("-o"),
+                output,
+            ]
+            .into_iter()
+            .chain(options.iter().map(Path::new)),
+        );
+        cli.color_palette = cli::ColorPalette::Nord;
+        let mut printed = Vec::new();
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(|| process_to(cli, &mut printed))
+            .unwrap();
+        (fs::read(output).unwrap(), printed)
+    }
+
+    /// A photo-like image, with too many colors to match each of them once
+    fn photo(path: &Path) {
+        image::RgbImage::from_fn(97, 61, |x, y| {
+            image::Rgb([(x * 7 + y) as u8, (y * 5) as u8, (x * y) as u8])
+        })
+        .save(path)
+        .unwrap();
+    }
+
+    #[test]
+    fn output_does_not_depend_on_the_thread_count() {
+        let dir = std::env::temp_dir().join(format!("dipc-threads-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("photo.png");
+        photo(&input);
+
+        let single = convert_with(&input, &dir.join("single.png"), &["--metrics"], 1);
+        let several = convert_with(&input, &dir.join("several.png"), &["--metrics"], 4);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(String::from_utf8_lossy(&single.1).contains("Mean DeltaE"));
+        assert!(
+            single == several,
+            "the output changed with the thread count"
+        );
+    }
+
+    #[test]
+    fn deterministic_matches_the_global_pool() {
+        let dir = std::env::temp_dir().join(format!("dipc-deterministic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("photo.png");
+        photo(&input);
+
+        let options = ["--metrics", "--grain", "3"];
+        let several = convert_with(&input, &dir.join("several.png"), &options, 4);
+        let pinned = convert_with(
+            &input,
+            &dir.join("pinned.png"),
+            &[&options[..], &["--deterministic"]].concat(),
+            4,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(several == pinned, "--deterministic changed the output");
+    }
+
+    #[test]
+    fn identical_colors_are_removed_in_palette_order() {
+        let dir = std::env::temp_dir().join(format!("dipc-ties-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("black.png");
+        image::RgbImage::new(4, 4).save(&input).unwrap();
+
+        let mut cli = Cli::parse_from([
+            Path::new("dipc"),
+            Path::new("nord"),
+            &input,
+            Path::new("-o"),
+            &dir.join("out.png"),
+            Path::new("--quiet"),
+            Path::new("--stats=json"),
+        ]);
+        cli.color_palette = cli::ColorPalette::RawJSON {
+            map: serde_json::from_str(
+                r##"{"mono": {"white": "#fff", "ink": "#000", "gray": "#808080", "black": "#000"}}"##,
+            )
+            .unwrap(),
+        };
+        let mut printed = Vec::new();
+        process_to(cli, &mut printed).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Black is listed twice: the first name is kept, and the others keep their order
+        let stats: serde_json::Value = serde_json::from_slice(&printed).unwrap();
+        assert_eq!(stats["colors"][0]["name"], "ink");
+        assert_eq!(stats["unused"], serde_json::json!(["white", "gray"]));
+    }
+
+    #[test]
+    fn max_memory_converts_in_strips() {
+        let dir = std::env::temp_dir().join(format!("dipc-max-memory-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("photo.png");
+        photo(&input);
+
+        let whole = convert_with(&input, &dir.join("whole.png"), &[], 2);
+        let strips = convert_with(&input, &dir.join("strips.png"), &["--max-memory", "4K"], 2);
+        // Options that need the whole image still convert it, over the limit
+        let blurred = convert_with(
+            &input,
+            &dir.join("blurred.png"),
+            &["--max-memory", "4K", "--blur", "1"],
+            2,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        let decode = |png: &[u8]| image::load_from_memory(png).unwrap().into_rgba8();
+        assert_eq!(decode(&strips.0), decode(&whole.0));
+        assert_eq!(decode(&blurred.0).dimensions(), (97, 61));
+    }
+}
@@ -0,0 +1,91 @@
+use crate::convert_image_format::Decoded;
+
+/// Bytes of the Lab representation of one pixel, see `convert::image_to_lab`
+const LAB_PIXEL: u64 = 3 * 4;
+
+/// Bytes of a `width`x`height` image decoded to RGBA
+pub fn decoded_size(width: u32, height: u32) -> u64 {
+    u64::from(width) * u64::from(height) * 4
+}
+
+/// Rough peak memory of converting `input` to one target after the other: the decoded image, the
+/// copy every target converts, the unconverted copy kept by `keep_original` and the Lab cache
+/// if `lab`
+pub fn working_set(input: &Decoded, keep_original: bool, lab: bool) -> u64 {
+    let (width, height) = input.dimensions();
+    let pixels = u64::from(width) * u64::from(height);
+    let frames = decoded_size(width, height) * input.frames.len() as u64;
+    let deep = input.deep.as_ref().map_or(0, |_| pixels * 8);
+    let copies = if keep_original { 3 } else { 2 };
+    copies * (frames + deep) + if lab { pixels * LAB_PIXEL } else { 0 }
+}
+
+/// The most memory the process has used so far, where the platform reports it
+pub fn peak() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+        let kib: u64 = line
+            .trim_start_matches("VmHWM:")
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse()
+            .ok()?;
+        Some(kib * 1024)
+    }
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+/// `bytes` in the largest binary unit that keeps the number at least 1, e.g. `1.5 GiB`
+pub fn format(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    match unit {
+        "B" => format!("{bytes} B"),
+        unit => format!("{value:.1} {unit}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Frame, ImageFormat, RgbaImage};
+
+    use super::*;
+    use crate::{convert_image_format::Container, metadata::Metadata};
+
+    #[test]
+    fn working_set_counts_the_copies() {
+        let input = Decoded {
+            frames: vec![Frame::new(RgbaImage::new(100, 50))],
+            container: Container::Still,
+            format: ImageFormat::Png,
+            deep: None,
+            metadata: Metadata::default(),
+        };
+        assert_eq!(decoded_size(100, 50), 20_000);
+        assert_eq!(working_set(&input, false, false), 2 * 20_000);
+        assert_eq!(working_set(&input, true, false), 3 * 20_000);
+        assert_eq!(
+            working_set(&input, true, true),
+            3 * 20_000 + 5_000 * LAB_PIXEL
+        );
+    }
+
+    #[test]
+    fn formats_sizes_in_binary_units() {
+        assert_eq!(format(512), "512 B");
+        assert_eq!(format(1536), "1.5 KiB");
+        assert_eq!(format(2 << 30), "2.0 GiB");
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Write},
+    path::Path,
+};
+
+use flate2::{write::ZlibEncoder, Compression};
+use image::{
+    codecs::{jpeg::JpegDecoder, png::PngDecoder, tiff::TiffDecoder, webp::WebPDecoder},
+    DynamicImage, ImageDecoder, ImageFormat,
+};
+
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// EXIF data and ICC profile carried over from an input image to its converted outputs
+#[derive(Debug, Default, Clone)]
+pub struct Metadata {
+    /// Raw TIFF structure of the EXIF data, without the `Exif\0\0` header used by JPEG
+    pub exif: Option<Vec<u8>>,
+    /// ICC color profile
+    pub icc: Option<Vec<u8>>,
+    /// Keyword and text pairs describing how the image was made, only written to PNG
+    pub text: Vec<(String, String)>,
+}
+
+impl Metadata {
+    /// Read the metadata of the image at `path`. Metadata that is missing or can not be parsed is
+    /// left out, it never stops the image from being converted.
+    pub fn read(path: &Path, format: ImageFormat) -> Metadata {
+        Metadata {
+            exif: read_exif(path, format),
+            icc: read_icc(path, format),
+            text: Vec::new(),
+        }
+    }
+
+    /// The EXIF orientation (1 to 8), 1 meaning the pixels are stored upright
+    pub fn orientation(&self) -> u16 {
+        self.exif
+            .as_deref()
+            .and_then(|tiff| {
+                let (offset, little_endian) = orientation_offset(tiff)?;
+                read_u16(tiff, offset, little_endian)
+            })
+            .filter(|orientation| (1..=8).contains(orientation))
+            .unwrap_or(1)
+    }
+
+    /// Rotate and flip `image` as described by its EXIF orientation, and mark the orientation as
+    /// applied so viewers do not rotate the converted image a second time
+    pub fn apply_orientation(&mut self, image: DynamicImage) -> DynamicImage {
+        let image = match self.orientation() {
+            2 => image.fliph(),
+            3 => image.rotate180(),
+            4 => image.flipv(),
+            5 => image.rotate90().fliph(),
+            6 => image.rotate90(),
+            7 => image.rotate270().fliph(),
+            8 => image.rotate270(),
+            _ => return image,
+        };
+        if let Some(tiff) = &mut self.exif {
+            if let Some((offset, little_endian)) = orientation_offset(tiff) {
+                let upright = if little_endian {
+                    1u16.to_le_bytes()
+                } else {
+                    1u16.to_be_bytes()
+                };
+                tiff[offset..offset + 2].copy_from_slice(&upright);
+            }
+        }
+        image
+    }
+
+    fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.icc.is_none() && self.text.is_empty()
+    }
+}
+
+fn read_exif(path: &Path, format: ImageFormat) -> Option<Vec<u8>> {
+    // For TIFF the "EXIF data" is the whole file, pixels included
+    if !matches!(
+        format,
+        ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP
+    ) {
+        return None;
+    }
+    let mut file = BufReader::new(File::open(path).ok()?);
+    exif::Reader::new()
+        .read_from_container(&mut file)
+        .ok()
+        .map(|exif| exif.buf().to_vec())
+}
+
+fn read_icc(path: &Path, format: ImageFormat) -> Option<Vec<u8>> {
+    let file = BufReader::new(File::open(path).ok()?);
+    match format {
+        ImageFormat::Png => PngDecoder::new(file).ok()?.icc_profile(),
+        ImageFormat::Jpeg => JpegDecoder::new(file).ok()?.icc_profile(),
+        ImageFormat::WebP => WebPDecoder::new(file).ok()?.icc_profile(),
+        ImageFormat::Tiff => TiffDecoder::new(file).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+/// Offset of the orientation value in the first IFD of `tiff`, and whether `tiff` is little endian
+fn orientation_offset(tiff: &[u8]) -> Option<(usize, bool)> {
+    let little_endian = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let ifd = read_u32(tiff, 4, little_endian)? as usize;
+    let count = read_u16(tiff, ifd, little_endian)? as usize;
+    (0..count)
+        .map(|entry| ifd + 2 + entry * 12)
+        .find(|&entry| read_u16(tiff, entry, little_endian) == Some(ORIENTATION_TAG))
+        // A single SHORT is stored at the start of the value field of the entry
+        .map(|entry| (entry + 8, little_endian))
+        .filter(|&(offset, _)| offset + 2 <= tiff.len())
+}
+
+fn read_u16(data: &[u8], offset: usize, little_endian: bool) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?.try_into().ok()?;
+    Some(if little_endian {
+        u16::from_le_bytes(bytes)
+    } else {
+        u16::from_be_bytes(bytes)
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize, little_endian: bool) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?.try_into().ok()?;
+    Some(if little_endian {
+        u32::from_le_bytes(bytes)
+    } else {
+        u32::from_be_bytes(bytes)
+    })
+}
+
+/// Write `metadata` into the image that was just saved at `path` as `format`.
+///
+/// The encoders of the image crate can not write metadata, so the chunks are spliced into the
+/// encoded file. Only PNG, JPEG and WebP are supported, other formats are left untouched.
+pub fn embed(path: &Path, format: ImageFormat, metadata: &Metadata) -> io::Result<()> {
+    if metadata.is_empty()
+        || !matches!(
+            format,
+            ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP
+        )
+    {
+        return Ok(());
+    }
+    let data = std::fs::read(path)?;
+    let data = match format {
+        ImageFormat::Png => embed_png(data, metadata)?,
+        ImageFormat::Jpeg => embed_jpeg(data, metadata),
+        _ => embed_webp(data, metadata),
+    };
+    std::fs::write(path, data)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Insert iCCP, eXIf and text chunks right after IHDR, ahead of the image data
+fn embed_png(data: Vec<u8>, metadata: &Metadata) -> io::Result<Vec<u8>> {
+    const IHDR_END: usize = 8 + 8 + 13 + 4;
+    if data.get(12..16) != Some(b"IHDR") || data.len() < IHDR_END {
+        return Err(invalid_data(
+            "Encoded PNG does not start with an IHDR chunk",
+        ));
+    }
+    let chunks: Vec<u8> = png_chunks(metadata)?
+        .iter()
+        .flat_map(|(kind, payload)| png_chunk(kind, payload))
+        .collect();
+    Ok(splice(data, IHDR_END, &chunks))
+}
+
+/// The type and payload of the iCCP, eXIf and text chunks holding `metadata` in a PNG
+pub fn png_chunks(metadata: &Metadata) -> io::Result<Vec<([u8; 4], Vec<u8>)>> {
+    let mut chunks = Vec::new();
+    if let Some(icc) = &metadata.icc {
+        let mut payload = b"ICC profile\0\0".to_vec();
+        let mut encoder = ZlibEncoder::new(&mut payload, Compression::default());
+        encoder.write_all(icc)?;
+        encoder.finish()?;
+        chunks.push((*b"iCCP", payload));
+    }
+    if let Some(exif) = &metadata.exif {
+        chunks.push((*b"eXIf", exif.clone()));
+    }
+    for (keyword, text) in &metadata.text {
+        // tEXt is Latin-1, anything else needs an uncompressed iTXt chunk
+        let latin1: Option<Vec<u8>> = text.chars().map(|c| u8::try_from(c).ok()).collect();
+        chunks.push(match latin1 {
+            Some(text) => (*b"tEXt", [keyword.as_bytes(), b"\0", &text].concat()),
+            None => (
+                *b"iTXt",
+                [keyword.as_bytes(), b"\0\0\0\0\0", text.as_bytes()].concat(),
+            ),
+        });
+    }
+    Ok(chunks)
+}
+
+fn png_chunk(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(payload);
+    let mut chunk = Vec::with_capacity(payload.len() + 12);
+    chunk.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(payload);
+    chunk.extend_from_slice(&hasher.finalize().to_be_bytes());
+    chunk
+}
+
+/// Insert an APP1 segment with the EXIF data and APP2 segments with the ICC profile after the
+/// JFIF header
+fn embed_jpeg(data: Vec<u8>, metadata: &Metadata) -> Vec<u8> {
+    // Segment payloads are limited by their 16 bit length, which counts its own 2 bytes
+    const MAX_PAYLOAD: usize = u16::MAX as usize - 2;
+    let mut position = 2;
+    if data.get(2..4) == Some(&[0xFF, 0xE0]) {
+        if let Some(length) = read_u16(&data, 4, false) {
+            position = 4 + length as usize;
+        }
+    }
+    let mut segments = Vec::new();
+    if let Some(exif) = &metadata.exif {
+        let payload = [b"Exif\0\0", exif.as_slice()].concat();
+        // EXIF data can not be split across segments, it is dropped if it does not fit
+        if payload.len() <= MAX_PAYLOAD {
+            segments.extend(jpeg_segment(0xE1, &payload));
+        }
+    }
+    if let Some(icc) = &metadata.icc {
+        const HEADER: &[u8] = b"ICC_PROFILE\0";
+        let parts = icc.chunks(MAX_PAYLOAD - HEADER.len() - 2);
+        let count = parts.len();
+        // Profiles split in more than 255 parts can not be numbered
+        if count <= 255 {
+            for (idx, part) in parts.enumerate() {
+                let payload = [HEADER, &[idx as u8 + 1, count as u8], part].concat();
+                segments.extend(jpeg_segment(0xE2, &payload));
+            }
+        }
+    }
+    let position = position.min(data.len());
+    splice(data, position, &segments)
+}
+
+fn jpeg_segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+    let mut segment = vec![0xFF, marker];
+    segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    segment.extend_from_slice(payload);
+    segment
+}
+
+/// Convert the file to the extended WebP format if needed, add ICCP and EXIF chunks and set
+/// their flags in the VP8X header
+fn embed_webp(data: Vec<u8>, metadata: &Metadata) -> Vec<u8> {
+    const HEADER: usize = 12;
+    const ICC_FLAG: u8 = 0x20;
+    const ALPHA_FLAG: u8 = 0x10;
+    const EXIF_FLAG: u8 = 0x08;
+    if data.get(..4) != Some(b"RIFF") || data.get(8..12) != Some(b"WEBP") {
+        return data;
+    }
+
+    let mut data = match data.get(HEADER..HEADER + 4) {
+        Some(b"VP8X") => data,
+        Some(kind @ (b"VP8 " | b"VP8L")) => {
+            let Some((width, height, alpha)) = webp_canvas(&data[HEADER + 8..], kind == b"VP8L")
+            else {
+                return data;
+            };
+            let mut vp8x = [0; 10];
+            vp8x[0] = if alpha { ALPHA_FLAG } else { 0 };
+            vp8x[4..7].copy_from_slice(&(width - 1).to_le_bytes()[..3]);
+            vp8x[7..10].copy_from_slice(&(height - 1).to_le_bytes()[..3]);
+            splice(data, HEADER, &webp_chunk(b"VP8X", &vp8x))
+        }
+        _ => return data,
+    };
+
+    let flags = HEADER + 8;
+    if let Some(icc) = &metadata.icc {
+        data[flags] |= ICC_FLAG;
+        // ICCP has to directly follow VP8X
+        data = splice(data, flags + 10, &webp_chunk(b"ICCP", icc));
+    }
+    if let Some(exif) = &metadata.exif {
+        data[flags] |= EXIF_FLAG;
+        data.extend(webp_chunk(b"EXIF", exif));
+    }
+    let riff_size = (data.len() - 8) as u32;
+    data[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    data
+}
+
+/// Canvas width, height and whether alpha is used, read from the bitstream of a simple WebP
+fn webp_canvas(bitstream: &[u8], lossless: bool) -> Option<(u32, u32, bool)> {
+    if lossless {
+        // Signature byte, then 14 bits width - 1, 14 bits height - 1 and the alpha bit
+        if *bitstream.first()? != 0x2F {
+            return None;
+        }
+        let bits = read_u32(bitstream, 1, true)?;
+        let width = (bits & 0x3FFF) + 1;
+        let height = ((bits >> 14) & 0x3FFF) + 1;
+        Some((width, height, bits >> 28 & 1 == 1))
+    } else {
+        // 3 byte frame tag and start code, then 14 bits of width and height each
+        if bitstream.get(3..6)? != [0x9D, 0x01, 0x2A] {
+            return None;
+        }
+        let width = read_u16(bitstream, 6, true)? & 0x3FFF;
+        let height = read_u16(bitstream, 8, true)? & 0x3FFF;
+        Some((width.into(), height.into(), false))
+    }
+}
+
+pub fn webp_chunk(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(payload.len() + 9);
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(payload);
+    // Chunks are padded to an even size
+    if payload.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+fn splice(mut data: Vec<u8>, position: usize, insert: &[u8]) -> Vec<u8> {
+    data.splice(position..position, insert.iter().copied());
+    data
+}
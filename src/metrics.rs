@@ -0,0 +1,144 @@
+use image::{Frame, RgbaImage};
+use rayon::{
+    prelude::{IndexedParallelIterator, ParallelIterator},
+    slice::ParallelSlice,
+};
+use serde::Serialize;
+
+use crate::delta::Lab;
+
+/// Side of the square windows SSIM is computed over
+const WINDOW: u32 = 8;
+/// Distance between two SSIM windows
+const STRIDE: usize = 4;
+/// Pixels summed together before the sums of the blocks are added up, see `mean_delta_e`
+const SUM_BLOCK: usize = 4096;
+
+/// How close a converted image is to its source, printed with `--metrics`
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Metrics {
+    /// Mean CIEDE2000 DeltaE between the source and converted colors, whatever method the
+    /// conversion used, so conversions with different methods can be compared
+    pub delta_e: f32,
+    /// Peak signal-to-noise ratio of the RGB channels in dB, `None` for identical images
+    pub psnr: Option<f64>,
+    /// Mean structural similarity of the luma, in `-1.0..=1.0`
+    pub ssim: f64,
+}
+
+impl std::fmt::Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Mean DeltaE: {:.3}, PSNR: ", self.delta_e)?;
+        match self.psnr {
+            Some(psnr) => write!(f, "{psnr:.2} dB")?,
+            None => write!(f, "inf")?,
+        }
+        write!(f, ", SSIM: {:.4}", self.ssim)
+    }
+}
+
+/// Compare every frame of `converted` to the same frame of `original`. The results of the frames
+/// of an animation are averaged.
+pub fn compare(original: &[Frame], converted: &[Frame]) -> Metrics {
+    let frames = original.len().min(converted.len()).max(1);
+    let (mut delta_e, mut squared_error, mut ssim_sum) = (0.0, 0.0, 0.0);
+    for (original, converted) in original.iter().zip(converted) {
+        let (original, converted) = (original.buffer(), converted.buffer());
+        delta_e += mean_delta_e(original, converted);
+        squared_error += mean_squared_error(original, converted);
+        ssim_sum += ssim(original, converted);
+    }
+    let squared_error = squared_error / frames as f64;
+    Metrics {
+        delta_e: delta_e / frames as f32,
+        psnr: (squared_error > 0.0)
+            .then(|| 10.0 * (f64::from(u8::MAX).powi(2) / squared_error).log10()),
+        ssim: ssim_sum / frames as f64,
+    }
+}
+
+fn mean_delta_e(original: &RgbaImage, converted: &RgbaImage) -> f32 {
+    let pixels = (original.len() / 4).max(1);
+    // Floating point addition is not associative, so the pixels are summed in blocks of a fixed
+    // size and the blocks in order. Letting rayon split the sum would make the result depend on
+    // the number of threads.
+    let blocks: Vec<f64> = original
+        .par_chunks(4 * SUM_BLOCK)
+        .zip(converted.par_chunks(4 * SUM_BLOCK))
+        .map(|(a, b)| {
+            a.chunks_exact(4)
+                .zip(b.chunks_exact(4))
+                .map(|(a, b)| {
+                    let a = Lab::from([a[0], a[1], a[2]]);
+                    let b = Lab::from([b[0], b[1], b[2]]);
+                    f64::from(*deltae::DeltaE::new(a, b, deltae::DEMethod::DE2000).value())
+                })
+                .sum()
+        })
+        .collect();
+    (blocks.iter().sum::<f64>() / pixels as f64) as f32
+}
+
+fn mean_squared_error(original: &RgbaImage, converted: &RgbaImage) -> f64 {
+    let pixels = (original.len() / 4).max(1);
+    let sum: f64 = original
+        .par_chunks_exact(4)
+        .zip(converted.par_chunks_exact(4))
+        .map(|(a, b)| {
+            (0..3)
+                .map(|c| (f64::from(a[c]) - f64::from(b[c])).powi(2))
+                .sum::<f64>()
+        })
+        .sum();
+    sum / (pixels * 3) as f64
+}
+
+/// Mean SSIM over overlapping `WINDOW`x`WINDOW` windows of the luma of both images
+fn ssim(original: &RgbaImage, converted: &RgbaImage) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let luma = |image: &RgbaImage| -> Vec<f64> {
+        image
+            .pixels()
+            .map(|pixel| {
+                let [r, g, b, _] = pixel.0.map(f64::from);
+                0.299 * r + 0.587 * g + 0.114 * b
+            })
+            .collect()
+    };
+    let (width, height) = original.dimensions();
+    // Images smaller than a window are compared as a single window
+    let (window_width, window_height) = (WINDOW.min(width), WINDOW.min(height));
+    let (a, b) = (luma(original), luma(converted));
+    let mut sum = 0.0;
+    let mut windows = 0;
+    for y in (0..=height - window_height).step_by(STRIDE) {
+        for x in (0..=width - window_width).step_by(STRIDE) {
+            let samples = (window_width * window_height) as f64;
+            let indices = (y..y + window_height)
+                .flat_map(|y| (x..x + window_width).map(move |x| (y * width + x) as usize));
+            let (mut mean_a, mut mean_b) = (0.0, 0.0);
+            for idx in indices.clone() {
+                mean_a += a[idx];
+                mean_b += b[idx];
+            }
+            mean_a /= samples;
+            mean_b /= samples;
+            let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+            for idx in indices {
+                let (da, db) = (a[idx] - mean_a, b[idx] - mean_b);
+                var_a += da * da;
+                var_b += db * db;
+                covariance += da * db;
+            }
+            var_a /= samples;
+            var_b /= samples;
+            covariance /= samples;
+            sum += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    sum / windows.max(1) as f64
+}
@@ -0,0 +1,21 @@
+use crate::cli::{Cli, ProgressFormat};
+
+/// What a conversion prints besides the output asked for, decided once from the command line
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    /// The palette swatches, other messages are logged at the level set by `--quiet`
+    pub chatter: bool,
+    /// How to report progress, `None` to not report it at all
+    pub progress: Option<ProgressFormat>,
+}
+
+impl Output {
+    pub fn new(cli: &Cli) -> Self {
+        Output {
+            // stdout carries the images when streaming
+            chatter: !cli.quiet && !cli.stream && cli.raw_frames.is_none(),
+            // A bar for every frame of a video would only flicker
+            progress: (!cli.no_progress && cli.raw_frames.is_none()).then_some(cli.progress),
+        }
+    }
+}
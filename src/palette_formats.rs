@@ -0,0 +1,753 @@
+use std::{fs, path::Path};
+
+use serde_json::{json, Map, Value};
+
+use crate::{cli::PaletteFormat, config::Palette};
+
+/// Palette file formats besides dipc's own JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Xresources,
+    Kitty,
+    /// dipc palette, Base16/Base24 scheme or alacritty.yml
+    Yaml,
+    /// dipc palette or alacritty.toml
+    Toml,
+    Css,
+    /// GIMP palette
+    Gpl,
+    /// Adobe Swatch Exchange
+    Ase,
+}
+
+impl Format {
+    /// Guess the format from a file name, `None` meaning dipc JSON
+    pub fn from_file_name(file_name: &str) -> Option<Format> {
+        let file_name = file_name.to_ascii_lowercase();
+        if file_name.contains("xresources") || file_name.contains("xdefaults") {
+            return Some(Format::Xresources);
+        }
+        match file_name.rsplit_once('.')?.1 {
+            "conf" => Some(Format::Kitty),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "toml" => Some(Format::Toml),
+            "css" | "scss" => Some(Format::Css),
+            "gpl" => Some(Format::Gpl),
+            "ase" => Some(Format::Ase),
+            _ => None,
+        }
+    }
+}
+
+/// Read a palette file in a format other than dipc's own JSON, or `None` if `path` is JSON
+pub fn read(path: &Path) -> Option<Result<Map<String, Value>, String>> {
+    let format = Format::from_file_name(path.file_name()?.to_str()?)?;
+    let map = fs::read(path)
+        .map_err(|err| format!("Failed to read {}: {err}", path.display()))
+        .and_then(|bytes| parse(format, &bytes, palette_name(path)));
+    Some(map.map_err(|err| format!("Failed to parse {}: {err}", path.display())))
+}
+
+/// Name for palettes without one of their own, from the file stem of `path`
+pub fn palette_name(path: &Path) -> String {
+    path.file_stem()
+        // `.Xresources` is all stem
+        .map(|stem| stem.to_string_lossy().trim_start_matches('.').to_string())
+        .unwrap_or_default()
+}
+
+/// The dipc JSON representation of `palettes`: a flat map of colors for a single palette without
+/// a name, a map of variations otherwise. Colors with a weight are written as objects.
+pub fn to_json(palettes: &[Palette]) -> Map<String, Value> {
+    let colors = |palette: &Palette| -> Map<String, Value> {
+        palette
+            .colors
+            .iter()
+            .map(|(name, color)| {
+                let [r, g, b] = color.0;
+                let hex = format!("#{r:02X}{g:02X}{b:02X}");
+                let value = match palette.weights.get(name) {
+                    Some(weight) => json!({ "hex": hex, "weight": weight }),
+                    None => Value::String(hex),
+                };
+                (name.clone(), value)
+            })
+            .collect()
+    };
+    match palettes {
+        [palette] if palette.name.is_none() => colors(palette),
+        palettes => palettes
+            .iter()
+            .map(|palette| {
+                let name = palette.name.clone().unwrap_or_default();
+                (name, Value::Object(colors(palette)))
+            })
+            .collect(),
+    }
+}
+
+/// Write `palettes` as `format`. `name` names the palette in formats that have a name for it,
+/// when the palette is a single variation without one.
+pub fn write(format: PaletteFormat, palettes: &[Palette], name: &str) -> Result<Vec<u8>, String> {
+    let single = || match palettes {
+        [palette] => Ok((palette, palette.name.as_deref().unwrap_or(name))),
+        _ => Err(format!(
+            "{format} holds a single palette, pick one of the {} variations with --styles",
+            palettes.len()
+        )),
+    };
+    let hex = |color: &image::Rgb<u8>| {
+        let [r, g, b] = color.0;
+        format!("{r:02x}{g:02x}{b:02x}")
+    };
+    let text = match format {
+        PaletteFormat::Json => {
+            serde_json::to_string_pretty(&to_json(palettes)).map_err(|err| err.to_string())? + "\n"
+        }
+        PaletteFormat::Gpl => {
+            let (palette, name) = single()?;
+            let mut text = format!("GIMP Palette\nName: {name}\nColumns: 0\n#\n");
+            for (color_name, color) in &palette.colors {
+                let [r, g, b] = color.0;
+                text += &format!("{r:3} {g:3} {b:3}\t{color_name}\n");
+            }
+            text
+        }
+        PaletteFormat::Css => {
+            let mut text = String::from(":root {\n");
+            for palette in palettes {
+                // Variations are told apart by a prefix
+                let prefix = match (&palette.name, palettes.len()) {
+                    (Some(name), 2..) => format!("{}-", css_identifier(name)),
+                    _ => String::new(),
+                };
+                for (color_name, color) in &palette.colors {
+                    let property = css_identifier(color_name);
+                    text += &format!("  --{prefix}{property}: #{};\n", hex(color));
+                }
+            }
+            text + "}\n"
+        }
+        PaletteFormat::Base16 => {
+            let (palette, name) = single()?;
+            let named: Vec<_> = palette
+                .colors
+                .iter()
+                .filter(|(color_name, _)| is_base_key(color_name))
+                .collect();
+            // Schemes read from Base16 files keep their keys, other palettes are numbered in order
+            let colors: Vec<(String, &image::Rgb<u8>)> = if named.len() >= 16 {
+                named
+                    .into_iter()
+                    .map(|(color_name, color)| (color_name.clone(), color))
+                    .collect()
+            } else if palette.colors.len() >= 16 {
+                palette.colors[..16]
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, (_, color))| (format!("base{idx:02X}"), color))
+                    .collect()
+            } else {
+                return Err(format!(
+                    "A Base16 scheme needs 16 colors, the palette has {}",
+                    palette.colors.len()
+                ));
+            };
+            let mut text = format!("scheme: {name:?}\nauthor: \"\"\n");
+            for (key, color) in colors {
+                text += &format!("{key}: \"{}\"\n", hex(color));
+            }
+            text
+        }
+        PaletteFormat::Ase => return Ok(write_ase(palettes)),
+    };
+    Ok(text.into_bytes())
+}
+
+/// `name` as a CSS custom property name: lowercase, with everything but letters and digits
+/// replaced by dashes
+fn css_identifier(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// An Adobe Swatch Exchange file with one group per named variation
+fn write_ase(palettes: &[Palette]) -> Vec<u8> {
+    fn utf16(text: &str) -> Vec<u8> {
+        let units: Vec<u16> = text.encode_utf16().chain([0]).collect();
+        let mut bytes = (units.len() as u16).to_be_bytes().to_vec();
+        bytes.extend(units.iter().flat_map(|unit| unit.to_be_bytes()));
+        bytes
+    }
+    let mut blocks: Vec<(u16, Vec<u8>)> = Vec::new();
+    for palette in palettes {
+        if let Some(name) = &palette.name {
+            blocks.push((ASE_GROUP_START, utf16(name)));
+        }
+        for (color_name, color) in &palette.colors {
+            let mut block = utf16(color_name);
+            block.extend(b"RGB ");
+            for channel in color.0 {
+                block.extend((channel as f32 / 255.0).to_be_bytes());
+            }
+            // Normal (not global or spot) color
+            block.extend(2u16.to_be_bytes());
+            blocks.push((ASE_COLOR, block));
+        }
+        if palette.name.is_some() {
+            blocks.push((ASE_GROUP_END, Vec::new()));
+        }
+    }
+
+    let mut bytes = b"ASEF".to_vec();
+    bytes.extend(1u16.to_be_bytes());
+    bytes.extend(0u16.to_be_bytes());
+    bytes.extend((blocks.len() as u32).to_be_bytes());
+    for (kind, block) in blocks {
+        bytes.extend(kind.to_be_bytes());
+        bytes.extend((block.len() as u32).to_be_bytes());
+        bytes.extend(block);
+    }
+    bytes
+}
+
+/// Parse `bytes` as `format`. `name` is used for the variation of formats holding a single palette.
+pub fn parse(format: Format, bytes: &[u8], name: String) -> Result<Map<String, Value>, String> {
+    if format == Format::Ase {
+        return ase(bytes, name);
+    }
+    let text = std::str::from_utf8(bytes).map_err(|_| String::from("Not a text file"))?;
+    match format {
+        Format::Xresources => terminal(xresources(text), name),
+        Format::Kitty => terminal(kitty(text), name),
+        Format::Yaml => {
+            let yaml: serde_yaml::Value =
+                serde_yaml::from_str(text).map_err(|err| err.to_string())?;
+            if is_base16(&yaml) {
+                return base16(&yaml, name);
+            }
+            let json = serde_json::to_value(&yaml).map_err(|err| err.to_string())?;
+            match json.get("colors").filter(|colors| is_alacritty(colors)) {
+                Some(colors) => terminal(alacritty(colors), name),
+                None => object(json),
+            }
+        }
+        Format::Toml => {
+            let json: Value = toml::from_str(text).map_err(|err| err.to_string())?;
+            match json.get("colors").filter(|colors| is_alacritty(colors)) {
+                Some(colors) => terminal(alacritty(colors), name),
+                None => object(json),
+            }
+        }
+        Format::Css => css(text, name),
+        Format::Gpl => gpl(text, name),
+        Format::Ase => unreachable!("ASE palettes are binary"),
+    }
+}
+
+/// A palette with the same structure as the JSON ones
+fn object(value: Value) -> Result<Map<String, Value>, String> {
+    match value {
+        Value::Object(map) => Ok(map),
+        _ => Err(String::from(
+            "The palette is not a mapping of names to colors",
+        )),
+    }
+}
+
+fn is_base16(yaml: &serde_yaml::Value) -> bool {
+    yaml.get("palette")
+        .unwrap_or(yaml)
+        .as_mapping()
+        .is_some_and(|entries| {
+            entries
+                .keys()
+                .any(|key| key.as_str().is_some_and(is_base_key))
+        })
+}
+
+fn is_alacritty(colors: &Value) -> bool {
+    ["primary", "normal", "bright"]
+        .iter()
+        .any(|section| colors.get(section).is_some_and(Value::is_object))
+}
+
+/// A Base16 or Base24 scheme, in the classic format with `base00` keys at the top level or the
+/// tinted-theming format with a `palette` table. The scheme becomes a single variation named
+/// after it, holding the `base00` to `base0F` (`base17` for Base24) colors.
+fn base16(yaml: &serde_yaml::Value, fallback_name: String) -> Result<Map<String, Value>, String> {
+    let colors = yaml.get("palette").unwrap_or(yaml);
+    let serde_yaml::Value::Mapping(entries) = colors else {
+        return Err(String::from("Expected a YAML mapping"));
+    };
+
+    let mut palette: Vec<(String, String)> = Vec::new();
+    for (key, value) in entries {
+        let Some(key) = key.as_str().filter(|key| is_base_key(key)) else {
+            continue;
+        };
+        let Some(hex) = value.as_str() else {
+            return Err(format!("`{key}` is not a color string"));
+        };
+        let hex = hex.trim().trim_start_matches('#');
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("`{key}` is not a 6 digit hex color: `{hex}`"));
+        }
+        // Keys are written `base0A` by convention, `base0a` also shows up
+        palette.push((
+            key[..4].to_ascii_lowercase() + &key[4..].to_ascii_uppercase(),
+            format!("#{hex}"),
+        ));
+    }
+    if palette.is_empty() {
+        return Err(String::from(
+            "Not a Base16 scheme, no `base00` to `base0F` colors found",
+        ));
+    }
+    palette.sort();
+
+    let name = ["scheme", "name"]
+        .into_iter()
+        .find_map(|key| yaml.get(key).and_then(serde_yaml::Value::as_str))
+        .map(String::from)
+        .unwrap_or(fallback_name);
+    let colors = palette
+        .into_iter()
+        .map(|(key, hex)| (key, Value::String(hex)))
+        .collect();
+    Ok(Map::from_iter([(name, Value::Object(colors))]))
+}
+
+/// `base00` to `base0F` and the Base24 additions `base10` to `base17`
+fn is_base_key(key: &str) -> bool {
+    key.len() == 6
+        && key[..4].eq_ignore_ascii_case("base")
+        && u8::from_str_radix(&key[4..], 16).is_ok_and(|idx| idx <= 0x17)
+}
+
+/// Names of the colors taken from terminal configurations, in palette order
+const TERMINAL_COLORS: [&str; 18] = [
+    "background",
+    "foreground",
+    "color0",
+    "color1",
+    "color2",
+    "color3",
+    "color4",
+    "color5",
+    "color6",
+    "color7",
+    "color8",
+    "color9",
+    "color10",
+    "color11",
+    "color12",
+    "color13",
+    "color14",
+    "color15",
+];
+
+/// A single variation named `name` with the background, foreground and ANSI colors found in a
+/// terminal configuration. Other entries are ignored.
+fn terminal(colors: Vec<(String, String)>, name: String) -> Result<Map<String, Value>, String> {
+    let mut palette = Map::new();
+    for key in TERMINAL_COLORS {
+        // Later definitions override earlier ones
+        let Some((_, value)) = colors.iter().rev().find(|(entry, _)| entry == key) else {
+            continue;
+        };
+        let hex =
+            normalize_color(value).ok_or_else(|| format!("`{key}` is not a color: `{value}`"))?;
+        palette.insert(String::from(key), Value::String(hex));
+    }
+    if palette.is_empty() {
+        return Err(String::from(
+            "No background, foreground or color0 to color15 entries found",
+        ));
+    }
+    Ok(Map::from_iter([(name, Value::Object(palette))]))
+}
+
+/// `#rgb`, `#rrggbb`, `0xrrggbb` or the X11 `rgb:rr/gg/bb` as `#rrggbb`
+fn normalize_color(value: &str) -> Option<String> {
+    let value = value.trim().trim_matches(|c| c == '\'' || c == '"');
+    let hex = if let Some(rgb) = value.strip_prefix("rgb:") {
+        rgb.split('/')
+            .map(|channel| match channel.len() {
+                1 => Some(channel.repeat(2)),
+                // Only the most significant byte of 12 and 16 bit channels is kept
+                2..=4 => Some(channel[..2].to_string()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?
+            .concat()
+    } else {
+        let hex = value
+            .strip_prefix('#')
+            .or_else(|| value.strip_prefix("0x"))?;
+        match hex.len() {
+            3 => hex.chars().flat_map(|c| [c, c]).collect(),
+            6 => hex.to_string(),
+            _ => return None,
+        }
+    };
+    (hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit())).then(|| format!("#{hex}"))
+}
+
+/// `*.color0: #282828` style resources, with `#define` macros expanded
+fn xresources(text: &str) -> Vec<(String, String)> {
+    let mut defines: Vec<(&str, &str)> = Vec::new();
+    let mut colors = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if let Some(define) = line.strip_prefix("#define") {
+            let mut words = define.split_whitespace();
+            if let (Some(name), Some(value)) = (words.next(), words.next()) {
+                defines.push((name, value));
+            }
+            continue;
+        }
+        if line.starts_with('!') || line.starts_with('#') {
+            continue;
+        }
+        let Some((resource, value)) = line.split_once(':') else {
+            continue;
+        };
+        // `*.color0`, `*color0`, `URxvt.color0` and `URxvt*color0` all name `color0`
+        let key = resource
+            .rsplit(['.', '*'])
+            .next()
+            .unwrap_or_default()
+            .trim();
+        let value = value.trim();
+        let value = defines
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == value)
+            .map_or(value, |(_, defined)| *defined);
+        colors.push((key.to_string(), value.to_string()));
+    }
+    colors
+}
+
+/// `color0 #282828` lines of a kitty.conf
+fn kitty(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let (key, value) = line.split_once(char::is_whitespace)?;
+            Some((key.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// The `colors` section of an alacritty configuration, YAML or TOML
+fn alacritty(colors: &Value) -> Vec<(String, String)> {
+    const ANSI: [&str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    let entry = |section: &str, key: &str| {
+        colors
+            .get(section)
+            .and_then(|section| section.get(key))
+            .and_then(Value::as_str)
+            .map(String::from)
+    };
+    let mut entries = Vec::new();
+    for key in ["background", "foreground"] {
+        if let Some(value) = entry("primary", key) {
+            entries.push((key.to_string(), value));
+        }
+    }
+    for (offset, section) in [(0, "normal"), (8, "bright")] {
+        for (idx, key) in ANSI.into_iter().enumerate() {
+            if let Some(value) = entry(section, key) {
+                entries.push((format!("color{}", idx + offset), value));
+            }
+        }
+    }
+    entries
+}
+
+/// CSS custom properties (`--accent: #ff8800;`) and SCSS variables (`$accent: #ff8800;`) holding
+/// colors, as a single variation. Properties referring to another color with `var(--name)` or
+/// `$name` are resolved, values that are not colors are skipped.
+fn css(text: &str, name: String) -> Result<Map<String, Value>, String> {
+    let mut palette = Map::new();
+    for declaration in strip_css_comments(text).split([';', '{', '}']) {
+        let Some((property, value)) = declaration.split_once(':') else {
+            continue;
+        };
+        let property = property.trim();
+        let Some(color_name) = property
+            .strip_prefix("--")
+            .or_else(|| property.strip_prefix('$'))
+        else {
+            continue;
+        };
+        let value = value
+            .trim()
+            .trim_end_matches("!default")
+            .trim_end_matches("!important")
+            .trim();
+        let reference = value
+            .strip_prefix("var(--")
+            .and_then(|var| var.strip_suffix(')'))
+            .or_else(|| value.strip_prefix('$'));
+        let color = match reference {
+            Some(reference) => palette.get(reference.trim()).cloned(),
+            None => {
+                css_color(value).map(|[r, g, b]| Value::String(format!("#{r:02x}{g:02x}{b:02x}")))
+            }
+        };
+        if let Some(color) = color {
+            palette.insert(color_name.to_string(), color);
+        }
+    }
+    if palette.is_empty() {
+        return Err(String::from(
+            "No custom properties or variables holding colors found",
+        ));
+    }
+    Ok(Map::from_iter([(name, Value::Object(palette))]))
+}
+
+/// A GIMP palette: a `GIMP Palette` header, optional `Name:` and `Columns:` lines, and one
+/// `R G B name` line per color. The palette becomes a single variation named after its `Name:`.
+fn gpl(text: &str, fallback_name: String) -> Result<Map<String, Value>, String> {
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some("GIMP Palette") {
+        return Err(String::from("Missing the `GIMP Palette` header"));
+    }
+    let mut name = fallback_name;
+    let mut palette = Map::new();
+    for (idx, line) in lines.enumerate() {
+        let line = line.trim();
+        if let Some(palette_name) = line.strip_prefix("Name:") {
+            name = palette_name.trim().to_string();
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') || line.starts_with("Columns:") {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let mut channel = || -> Result<u8, String> {
+            fields
+                .next()
+                .and_then(|channel| channel.parse().ok())
+                .ok_or_else(|| format!("Line {} is not a `R G B name` color: `{line}`", idx + 2))
+        };
+        let [r, g, b] = [channel()?, channel()?, channel()?];
+        let hex = format!("#{r:02x}{g:02x}{b:02x}");
+        let color_name = fields.collect::<Vec<_>>().join(" ");
+        let color_name = match color_name.as_str() {
+            "" | "Untitled" => hex.clone(),
+            _ => color_name,
+        };
+        palette.insert(unique_key(&palette, color_name), Value::String(hex));
+    }
+    if palette.is_empty() {
+        return Err(String::from("The palette has no colors"));
+    }
+    Ok(Map::from_iter([(name, Value::Object(palette))]))
+}
+
+/// An Adobe Swatch Exchange file. Every group becomes a variation, colors outside of a group are
+/// put in a variation named `fallback_name`. RGB, gray, CMYK and Lab colors are read.
+fn ase(bytes: &[u8], fallback_name: String) -> Result<Map<String, Value>, String> {
+    let mut reader = BigEndian(bytes);
+    if reader.take(4)? != b"ASEF" {
+        return Err(String::from("Not an Adobe Swatch Exchange file"));
+    }
+    reader.take(4)?; // Version
+    let blocks = reader.u32()?;
+
+    let mut variations: Vec<(String, Map<String, Value>)> = Vec::new();
+    let mut group: Option<String> = None;
+    for _ in 0..blocks {
+        let kind = reader.u16()?;
+        let length = reader.u32()? as usize;
+        let mut block = BigEndian(reader.take(length)?);
+        match kind {
+            ASE_GROUP_START => group = Some(block.utf16()?),
+            ASE_GROUP_END => group = None,
+            ASE_COLOR => {
+                let name = block.utf16()?;
+                let model = block.take(4)?;
+                let mut value = || block.f32();
+                let rgb = match model {
+                    b"RGB " => [value()?, value()?, value()?],
+                    b"Gray" => [value()?; 3],
+                    b"CMYK" => {
+                        let [c, m, y, k] = [value()?, value()?, value()?, value()?];
+                        [c, m, y].map(|channel| (1.0 - channel) * (1.0 - k))
+                    }
+                    b"LAB " => {
+                        let [l, a, b] = [value()?, value()?, value()?];
+                        let [r, g, b] = crate::delta::Lab::new(l * 100.0, a, b).to_rgb();
+                        [r, g, b].map(|channel| channel as f32 / 255.0)
+                    }
+                    model => {
+                        return Err(format!(
+                            "Unsupported color model `{}`",
+                            String::from_utf8_lossy(model)
+                        ))
+                    }
+                };
+                let [r, g, b] = rgb.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
+                let hex = format!("#{r:02x}{g:02x}{b:02x}");
+                let variation_name = group.clone().unwrap_or_else(|| fallback_name.clone());
+                let idx = match variations
+                    .iter()
+                    .position(|(name, _)| *name == variation_name)
+                {
+                    Some(idx) => idx,
+                    None => {
+                        variations.push((variation_name, Map::new()));
+                        variations.len() - 1
+                    }
+                };
+                let colors = &mut variations[idx].1;
+                let name = match name.is_empty() {
+                    true => hex.clone(),
+                    false => name,
+                };
+                colors.insert(unique_key(colors, name), Value::String(hex));
+            }
+            _ => {}
+        }
+    }
+    if variations.is_empty() {
+        return Err(String::from("The swatch file has no colors"));
+    }
+    Ok(variations
+        .into_iter()
+        .map(|(name, colors)| (name, Value::Object(colors)))
+        .collect())
+}
+
+const ASE_GROUP_START: u16 = 0xC001;
+const ASE_GROUP_END: u16 = 0xC002;
+const ASE_COLOR: u16 = 0x0001;
+
+/// Reads big endian values from the front of a byte slice
+struct BigEndian<'a>(&'a [u8]);
+
+impl<'a> BigEndian<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < count {
+            return Err(String::from("The file ends unexpectedly"));
+        }
+        let (head, rest) = self.0.split_at(count);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// A length prefixed, null terminated UTF-16 string
+    fn utf16(&mut self) -> Result<String, String> {
+        let length = self.u16()? as usize;
+        let units: Vec<u16> = (0..length).map(|_| self.u16()).collect::<Result<_, _>>()?;
+        Ok(String::from_utf16_lossy(&units)
+            .trim_end_matches('\0')
+            .to_string())
+    }
+}
+
+/// `key`, or `key 2`, `key 3`... if `map` already has it, as names in palette files can repeat
+fn unique_key(map: &Map<String, Value>, key: String) -> String {
+    if !map.contains_key(&key) {
+        return key;
+    }
+    (2..)
+        .map(|idx| format!("{key} {idx}"))
+        .find(|key| !map.contains_key(key))
+        .unwrap()
+}
+
+/// Remove `/* */` comments, and SCSS `//` line comments (but not the `//` of URLs)
+fn strip_css_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("/*") {
+        out.push_str(&rest[..start]);
+        rest = rest[start..]
+            .find("*/")
+            .map_or("", |end| &rest[start + end + 2..]);
+    }
+    out.push_str(rest);
+    out.lines()
+        .map(|line| match line.find("//") {
+            Some(idx) if !line[..idx].ends_with(':') => &line[..idx],
+            _ => line,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A CSS color in hex, `rgb()`/`rgba()` or `hsl()`/`hsla()` notation, ignoring its alpha
+fn css_color(value: &str) -> Option<[u8; 3]> {
+    if let Some(hex) = value.strip_prefix('#') {
+        let hex = match hex.len() {
+            3 | 4 => hex[..3].chars().flat_map(|c| [c, c]).collect(),
+            6 | 8 => hex[..6].to_string(),
+            _ => return None,
+        };
+        let channel = |idx: usize| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok();
+        return Some([channel(0)?, channel(2)?, channel(4)?]);
+    }
+
+    let (function, arguments) = value.strip_suffix(')')?.split_once('(')?;
+    // Both `rgb(255, 128, 0)` and `rgb(255 128 0 / 50%)`
+    let arguments: Vec<&str> = arguments
+        .split('/')
+        .next()?
+        .split([',', ' '])
+        .map(str::trim)
+        .filter(|argument| !argument.is_empty())
+        .collect();
+    let number = |argument: &str, percent_of: f32| -> Option<f32> {
+        match argument.strip_suffix('%') {
+            Some(percent) => percent.parse::<f32>().ok().map(|p| p / 100.0 * percent_of),
+            None => argument.trim_end_matches("deg").parse().ok(),
+        }
+    };
+    match function.trim() {
+        "rgb" | "rgba" => {
+            let [r, g, b] = arguments.get(..3)? else {
+                return None;
+            };
+            let channel =
+                |argument| number(argument, 255.0).map(|c| c.round().clamp(0.0, 255.0) as u8);
+            Some([channel(r)?, channel(g)?, channel(b)?])
+        }
+        "hsl" | "hsla" => {
+            let [h, s, l] = arguments.get(..3)? else {
+                return None;
+            };
+            let (h, s, l) = (number(h, 360.0)?, number(s, 1.0)?, number(l, 1.0)?);
+            let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+            let channel = |n: f32| {
+                let k = (n + h / 30.0).rem_euclid(12.0);
+                let value = l - chroma / 2.0 * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0);
+                (value * 255.0).round().clamp(0.0, 255.0) as u8
+            };
+            Some([channel(0.0), channel(8.0), channel(4.0)])
+        }
+        _ => None,
+    }
+}
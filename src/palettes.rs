@@ -1,48 +1,103 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
 use serde_json::Value;
 
 use crate::cli::ColorPalette;
 
+/// Builtin palettes that were already parsed, so `dipc daemon` parses each of them once
+static BUILTIN_CACHE: Mutex<BTreeMap<&str, serde_json::Map<String, Value>>> =
+    Mutex::new(BTreeMap::new());
+
 impl ColorPalette {
+    /// Short names accepted by `--styles` for variations of the builtin palettes, as
+    /// (alias, variation name) pairs
+    pub fn style_aliases(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            ColorPalette::Catppuccin => &[("light", "latte"), ("dark", "mocha")],
+            ColorPalette::Edge => &[
+                ("dark", "Edge Dark"),
+                ("aura", "Edge Dark (Aura)"),
+                ("neon", "Edge Dark (Neon)"),
+                ("aura dim", "Edge Dark (Aura Dim)"),
+                ("light", "Edge Light"),
+            ],
+            ColorPalette::Github => &[("dimmed", "Dark Dimmed")],
+            ColorPalette::Gruvbox => &[("dark", "Dark mode"), ("light", "Light mode")],
+            ColorPalette::Horizon => &[("light", "Bright")],
+            ColorPalette::Kanagawa => &[("dark", "Wave"), ("light", "Lotus")],
+            ColorPalette::Material => &[("dark", "Default"), ("light", "Lighter")],
+            ColorPalette::NightOwl => &[("dark", "Night Owl"), ("light", "Light Owl")],
+            ColorPalette::Nord => &[("dark", "Polar Night"), ("light", "Snow Storm")],
+            ColorPalette::RosePine => &[
+                ("main", "Rose Pine"),
+                ("dark", "Rose Pine"),
+                ("moon", "Rose Pine Moon"),
+                ("dawn", "Rose Pine Dawn"),
+                ("light", "Rose Pine Dawn"),
+            ],
+            ColorPalette::TokyoNight => &[("dark", "Night"), ("light", "Day")],
+            _ => &[],
+        }
+    }
+
+    /// The (dark, light) variations `--styles auto` picks from
+    pub fn auto_styles(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            ColorPalette::Ayu => Some(("Dark", "Light")),
+            ColorPalette::Catppuccin => Some(("mocha", "latte")),
+            ColorPalette::Edge => Some(("Edge Dark", "Edge Light")),
+            ColorPalette::Everforest | ColorPalette::GruvboxMaterial => Some(("Dark", "Light")),
+            ColorPalette::Github => Some(("Dark", "Light")),
+            ColorPalette::Gruvbox => Some(("Dark mode", "Light mode")),
+            ColorPalette::Horizon => Some(("Dark", "Bright")),
+            ColorPalette::Kanagawa => Some(("Wave", "Lotus")),
+            ColorPalette::Material => Some(("Default", "Lighter")),
+            ColorPalette::NightOwl => Some(("Night Owl", "Light Owl")),
+            ColorPalette::OneDark => Some(("dark", "light")),
+            ColorPalette::RosePine => Some(("Rose Pine", "Rose Pine Dawn")),
+            ColorPalette::TokyoNight => Some(("Night", "Day")),
+            _ => None,
+        }
+    }
+
     pub fn get_json(self) -> serde_json::Map<String, Value> {
-        let colors = match self {
+        let (name, source) = match self {
             ColorPalette::RawJSON { map } => return map,
-            ColorPalette::Catppuccin => {
-                serde_json::from_str(include_str!("./palettes/catppuccin.json")).unwrap()
-            }
-            ColorPalette::Dracula => {
-                serde_json::from_str(include_str!("./palettes/dracula.json")).unwrap()
-            }
-            ColorPalette::Edge => {
-                serde_json::from_str(include_str!("./palettes/edge.json")).unwrap()
-            }
-            ColorPalette::Everforest => {
-                serde_json::from_str(include_str!("./palettes/everforest.json")).unwrap()
-            }
-            ColorPalette::Gruvbox => {
-                serde_json::from_str(include_str!("./palettes/gruvbox.json")).unwrap()
-            }
-            ColorPalette::GruvboxMaterial => {
-                serde_json::from_str(include_str!("./palettes/gruvbox-material.json")).unwrap()
-            }
-            ColorPalette::Nord => {
-                serde_json::from_str(include_str!("./palettes/nord.json")).unwrap()
-            }
-            ColorPalette::OneDark => {
-                serde_json::from_str(include_str!("./palettes/onedark.json")).unwrap()
-            }
-            ColorPalette::RosePine => {
-                serde_json::from_str(include_str!("./palettes/rose-pine.json")).unwrap()
-            }
-            ColorPalette::Solarized => {
-                serde_json::from_str(include_str!("./palettes/solarized.json")).unwrap()
-            }
+            ColorPalette::Ayu => ("ayu", include_str!("./palettes/ayu.json")),
+            ColorPalette::Catppuccin => ("catppuccin", include_str!("./palettes/catppuccin.json")),
+            ColorPalette::Dracula => ("dracula", include_str!("./palettes/dracula.json")),
+            ColorPalette::Edge => ("edge", include_str!("./palettes/edge.json")),
+            ColorPalette::Everforest => ("everforest", include_str!("./palettes/everforest.json")),
+            ColorPalette::Github => ("github", include_str!("./palettes/github.json")),
+            ColorPalette::Gruvbox => ("gruvbox", include_str!("./palettes/gruvbox.json")),
+            ColorPalette::GruvboxMaterial => (
+                "gruvbox-material",
+                include_str!("./palettes/gruvbox-material.json"),
+            ),
+            ColorPalette::Horizon => ("horizon", include_str!("./palettes/horizon.json")),
+            ColorPalette::Kanagawa => ("kanagawa", include_str!("./palettes/kanagawa.json")),
+            ColorPalette::Material => ("material", include_str!("./palettes/material.json")),
+            ColorPalette::Monokai => ("monokai", include_str!("./palettes/monokai.json")),
+            ColorPalette::NightOwl => ("night-owl", include_str!("./palettes/night-owl.json")),
+            ColorPalette::Nord => ("nord", include_str!("./palettes/nord.json")),
+            ColorPalette::OneDark => ("onedark", include_str!("./palettes/onedark.json")),
+            ColorPalette::RosePine => ("rose-pine", include_str!("./palettes/rose-pine.json")),
+            ColorPalette::Solarized => ("solarized", include_str!("./palettes/solarized.json")),
             ColorPalette::TokyoNight => {
-                serde_json::from_str(include_str!("./palettes/tokyo-night.json")).unwrap()
+                ("tokyo-night", include_str!("./palettes/tokyo-night.json"))
             }
+            ColorPalette::Zenburn => ("zenburn", include_str!("./palettes/zenburn.json")),
         };
-        let Value::Object(obj) = colors else {
-            panic!("An included theme appears to not be a JSON object?")
-        };
-        obj
+        BUILTIN_CACHE
+            .lock()
+            .unwrap()
+            .entry(name)
+            .or_insert_with(|| {
+                let Ok(Value::Object(obj)) = serde_json::from_str(source) else {
+                    panic!("An included theme appears to not be a JSON object?")
+                };
+                obj
+            })
+            .clone()
     }
 }
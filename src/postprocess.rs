@@ -0,0 +1,180 @@
+use image::{imageops, GrayImage, ImageBuffer, Pixel, Primitive, RgbaImage};
+use num_traits::{NumCast, ToPrimitive};
+
+use crate::{
+    cli::Deficiency,
+    delta::{linear_to_srgb, srgb_to_linear},
+};
+
+/// Copy the left half of `original` over `converted` and draw a thin divider between the halves,
+/// for before/after comparisons
+pub fn split_preview<P: Pixel>(
+    original: &ImageBuffer<P, Vec<P::Subpixel>>,
+    converted: &mut ImageBuffer<P, Vec<P::Subpixel>>,
+) {
+    let (width, height) = converted.dimensions();
+    let middle = width / 2;
+    // Roughly 2px on a 1080p image, but always visible
+    let divider = (width / 960).max(1);
+    let divider_start = middle.saturating_sub(divider / 2);
+    let white = *P::from_slice(&vec![
+        <P::Subpixel as Primitive>::DEFAULT_MAX_VALUE;
+        P::CHANNEL_COUNT as usize
+    ]);
+
+    for y in 0..height {
+        for x in 0..middle {
+            converted.put_pixel(x, y, *original.get_pixel(x, y));
+        }
+        for x in divider_start..(divider_start + divider).min(width) {
+            converted.put_pixel(x, y, white);
+        }
+    }
+}
+
+/// Blend `original` back into `converted` through `mask`: white areas keep the conversion, black
+/// areas the original, and gray ones a proportional mix of both, in linear light if `linear`. The
+/// alpha channel is untouched.
+pub fn apply_mask<P: Pixel>(
+    original: &ImageBuffer<P, Vec<P::Subpixel>>,
+    converted: &mut ImageBuffer<P, Vec<P::Subpixel>>,
+    mask: &GrayImage,
+    linear: bool,
+) {
+    let max = <P::Subpixel as Primitive>::DEFAULT_MAX_VALUE
+        .to_f32()
+        .unwrap();
+    let color_channels = color_channels::<P>();
+    for ((original, converted), mask) in original
+        .pixels()
+        .zip(converted.pixels_mut())
+        .zip(mask.pixels())
+    {
+        let amount = match mask.0[0] {
+            u8::MAX => continue,
+            0 => {
+                *converted = *original;
+                continue;
+            }
+            gray => gray as f32 / u8::MAX as f32,
+        };
+        let channels = converted.channels_mut();
+        for (channel, &original) in channels[..color_channels]
+            .iter_mut()
+            .zip(original.channels())
+        {
+            let (from, to) = (original.to_f32().unwrap(), channel.to_f32().unwrap());
+            let blended = match linear {
+                true => {
+                    let (from, to) = (srgb_to_linear(from / max), srgb_to_linear(to / max));
+                    linear_to_srgb(from + (to - from) * amount) * max
+                }
+                false => from + (to - from) * amount,
+            };
+            *channel = NumCast::from(blended.round()).unwrap();
+        }
+    }
+}
+
+/// Black out the pixels of `mask` where `protect` holds for the color of `image`, so
+/// `apply_mask` passes them through untouched
+pub fn protect(mask: &mut GrayImage, image: &RgbaImage, protect: impl Fn([u8; 3]) -> bool) {
+    for (mask, pixel) in mask.pixels_mut().zip(image.pixels()) {
+        if mask.0[0] != 0 && protect([pixel[0], pixel[1], pixel[2]]) {
+            mask.0[0] = 0;
+        }
+    }
+}
+
+/// Unsharp mask: push every color channel away from a slightly blurred copy by `amount`
+pub fn sharpen<P: Pixel + 'static>(image: &mut ImageBuffer<P, Vec<P::Subpixel>>, amount: f32) {
+    let blurred = imageops::blur(image, 1.0);
+    let color_channels = color_channels::<P>();
+    let max = <P::Subpixel as Primitive>::DEFAULT_MAX_VALUE
+        .to_f32()
+        .unwrap();
+    for (pixel, blurred) in image.pixels_mut().zip(blurred.pixels()) {
+        for (channel, &blurred) in pixel.channels_mut()[..color_channels]
+            .iter_mut()
+            .zip(blurred.channels())
+        {
+            let (value, blurred) = (channel.to_f32().unwrap(), blurred.to_f32().unwrap());
+            let sharpened = value + (value - blurred) * amount;
+            *channel = NumCast::from(sharpened.round().clamp(0.0, max)).unwrap();
+        }
+    }
+}
+
+/// Add monochromatic noise of up to `amount` 8-bit levels to the color channels, hiding banding.
+/// The noise only depends on `seed` and the pixel position, so outputs are reproducible.
+pub fn grain<P: Pixel>(image: &mut ImageBuffer<P, Vec<P::Subpixel>>, amount: f32, seed: u64) {
+    let color_channels = color_channels::<P>();
+    let max = <P::Subpixel as Primitive>::DEFAULT_MAX_VALUE
+        .to_f32()
+        .unwrap();
+    let amount = amount * max / u8::MAX as f32;
+    for (idx, pixel) in image.pixels_mut().enumerate() {
+        let random = splitmix64(seed ^ splitmix64(idx as u64));
+        // Triangular distribution in -1..1 from two uniform halves of the random bits
+        let uniform = |bits: u64| (bits & 0xffff_ffff) as f32 / u32::MAX as f32;
+        let offset = (uniform(random) + uniform(random >> 32) - 1.0) * amount;
+        for channel in &mut pixel.channels_mut()[..color_channels] {
+            let value = channel.to_f32().unwrap() + offset;
+            *channel = NumCast::from(value.round().clamp(0.0, max)).unwrap();
+        }
+    }
+}
+
+/// Simulate how `image` looks with a color vision deficiency, with the full severity matrices of
+/// Machado, Oliveira and Fernandes (2009) applied in linear light
+pub fn simulate<P: Pixel>(image: &mut ImageBuffer<P, Vec<P::Subpixel>>, deficiency: Deficiency) {
+    let matrix = match deficiency {
+        Deficiency::Protanopia => [
+            [0.152286, 1.052583, -0.204868],
+            [0.114503, 0.786281, 0.099216],
+            [-0.003882, -0.048116, 1.051998],
+        ],
+        Deficiency::Deuteranopia => [
+            [0.367322, 0.860646, -0.227968],
+            [0.280085, 0.672501, 0.047413],
+            [-0.011820, 0.042940, 0.968881],
+        ],
+        Deficiency::Tritanopia => [
+            [1.255528, -0.076749, -0.178779],
+            [-0.078411, 0.930809, 0.147602],
+            [0.004733, 0.691367, 0.303900],
+        ],
+    };
+    if color_channels::<P>() < 3 {
+        return;
+    }
+    let max = <P::Subpixel as Primitive>::DEFAULT_MAX_VALUE
+        .to_f32()
+        .unwrap();
+    for pixel in image.pixels_mut() {
+        let channels = pixel.channels_mut();
+        let rgb: [f32; 3] =
+            std::array::from_fn(|idx| srgb_to_linear(channels[idx].to_f32().unwrap() / max));
+        for (channel, row) in channels.iter_mut().zip(matrix) {
+            let linear = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+            let value = linear_to_srgb(linear.clamp(0.0, 1.0)) * max;
+            *channel = NumCast::from(value.round()).unwrap();
+        }
+    }
+}
+
+/// SplitMix64 finalizer, a cheap and well mixed hash
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Number of channels of `P` that are not alpha
+fn color_channels<P: Pixel>() -> usize {
+    match P::COLOR_MODEL {
+        "RGBA" | "YA" => P::CHANNEL_COUNT as usize - 1,
+        _ => P::CHANNEL_COUNT as usize,
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    io::{self, Cursor, Write},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{
+    imageops::{self, FilterType},
+    ImageFormat, Rgba, RgbaImage,
+};
+
+use crate::cli::PreviewProtocol;
+
+/// Columns and rows of the terminal when `COLUMNS` and `LINES` are not set
+const DEFAULT_TERMINAL_SIZE: (u32, u32) = (80, 24);
+
+/// The usual size of a terminal cell in pixels, for the protocols that draw pixels
+const CELL_SIZE: (u32, u32) = (8, 16);
+
+/// Draw a downscaled `image` in the terminal, below the cursor
+pub fn show(
+    writer: &mut impl Write,
+    image: &RgbaImage,
+    protocol: PreviewProtocol,
+) -> io::Result<()> {
+    let (columns, rows) = terminal_size();
+    match protocol {
+        PreviewProtocol::Auto => show(writer, image, detect()),
+        PreviewProtocol::Kitty => kitty(
+            writer,
+            &fit(image, columns * CELL_SIZE.0, rows * CELL_SIZE.1),
+        ),
+        PreviewProtocol::Sixel => sixel(
+            writer,
+            &fit(image, columns * CELL_SIZE.0, rows * CELL_SIZE.1),
+        ),
+        // Two pixels per cell, one above the other
+        PreviewProtocol::Halfblocks => halfblocks(writer, &fit(image, columns, rows * 2)),
+    }
+}
+
+/// Guess the protocol from the environment. `TERM` is passed over SSH, so this also works for
+/// remote sessions.
+fn detect() -> PreviewProtocol {
+    let var = |name| std::env::var(name).unwrap_or_default();
+    let term = var("TERM");
+    let program = var("TERM_PROGRAM");
+    if matches!(term.as_str(), "xterm-kitty" | "xterm-ghostty")
+        || matches!(program.as_str(), "ghostty" | "WezTerm")
+        || std::env::var_os("KITTY_WINDOW_ID").is_some()
+    {
+        PreviewProtocol::Kitty
+    } else if term.starts_with("foot") || term.starts_with("mlterm") || term.contains("sixel") {
+        PreviewProtocol::Sixel
+    } else {
+        PreviewProtocol::Halfblocks
+    }
+}
+
+/// The space left for the preview, keeping a line for the prompt
+fn terminal_size() -> (u32, u32) {
+    let var = |name| {
+        std::env::var(name)
+            .ok()?
+            .parse::<u32>()
+            .ok()
+            .filter(|size| *size > 0)
+    };
+    let columns = var("COLUMNS").unwrap_or(DEFAULT_TERMINAL_SIZE.0);
+    let rows = var("LINES").unwrap_or(DEFAULT_TERMINAL_SIZE.1);
+    (columns, rows.saturating_sub(2).max(1))
+}
+
+/// Downscale `image` to fit in `width`x`height`. Nearest neighbor keeps the palette colors
+/// instead of blending them.
+fn fit(image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let (image_width, image_height) = image.dimensions();
+    let scale = (width as f32 / image_width as f32)
+        .min(height as f32 / image_height as f32)
+        .min(1.0);
+    let width = ((image_width as f32 * scale).round() as u32).max(1);
+    let height = ((image_height as f32 * scale).round() as u32).max(1);
+    imageops::resize(image, width, height, FilterType::Nearest)
+}
+
+/// The kitty graphics protocol, with the image sent as base64 PNG in chunks of 4096 bytes
+fn kitty(writer: &mut impl Write, image: &RgbaImage) -> io::Result<()> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(io::Error::other)?;
+    let data = STANDARD.encode(png);
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+    for (idx, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(idx + 1 < chunks.len());
+        match idx {
+            // Transmit and display a PNG, without responses from the terminal
+            0 => write!(writer, "\x1b_Gf=100,a=T,q=2,m={more};")?,
+            _ => write!(writer, "\x1b_Gm={more};")?,
+        }
+        writer.write_all(chunk)?;
+        writer.write_all(b"\x1b\\")?;
+    }
+    writeln!(writer)
+}
+
+/// Sixel graphics, drawn in bands of six rows with one pass per color
+fn sixel(writer: &mut impl Write, image: &RgbaImage) -> io::Result<()> {
+    let (width, height) = image.dimensions();
+    let Indexed { palette, pixels } = index_colors(image);
+    // Transparent pixels keep the background
+    write!(writer, "\x1bP0;1q\"1;1;{width};{height}")?;
+    for (idx, rgb) in palette.iter().enumerate() {
+        let [r, g, b] = rgb.map(|channel| u32::from(channel) * 100 / 255);
+        write!(writer, "#{idx};2;{r};{g};{b}")?;
+    }
+    let mut line = Vec::with_capacity(width as usize);
+    for top in (0..height).step_by(6) {
+        let band = (height - top).min(6);
+        for color in 0..palette.len() {
+            line.clear();
+            for x in 0..width {
+                let bits = (0..band)
+                    .filter(|&dy| pixels[((top + dy) * width + x) as usize] == Some(color as u8))
+                    .fold(0, |bits, dy| bits | 1 << dy);
+                line.push(63 + bits);
+            }
+            if line.iter().all(|&sixel| sixel == 63) {
+                continue;
+            }
+            write!(writer, "#{color}")?;
+            for run in line.chunk_by(|a, b| a == b) {
+                match run.len() {
+                    1..=3 => writer.write_all(run)?,
+                    length => write!(writer, "!{length}{}", run[0] as char)?,
+                }
+            }
+            // Back to the start of the band for the next color
+            writer.write_all(b"$")?;
+        }
+        writer.write_all(b"-")?;
+    }
+    writer.write_all(b"\x1b\\")?;
+    writeln!(writer)
+}
+
+/// The colors of an image and the index of every pixel in them, `None` for transparent pixels
+struct Indexed {
+    palette: Vec<[u8; 3]>,
+    pixels: Vec<Option<u8>>,
+}
+
+/// Sixel terminals have at least 256 color registers, more colors are reduced to a 6x6x6 cube
+fn index_colors(image: &RgbaImage) -> Indexed {
+    index_with(image, |rgb| rgb).unwrap_or_else(|| {
+        let cube = |channel: u8| ((u32::from(channel) * 5 + 127) / 255 * 51) as u8;
+        index_with(image, |rgb| rgb.map(cube)).expect("216 colors fit in 256 registers")
+    })
+}
+
+fn index_with(image: &RgbaImage, reduce: impl Fn([u8; 3]) -> [u8; 3]) -> Option<Indexed> {
+    let mut palette = Vec::new();
+    let mut lookup = HashMap::new();
+    let pixels = image
+        .pixels()
+        .map(|&Rgba([r, g, b, a])| {
+            if a < 128 {
+                return Some(None);
+            }
+            let rgb = reduce([r, g, b]);
+            let idx = *lookup.entry(rgb).or_insert_with(|| {
+                palette.push(rgb);
+                palette.len() - 1
+            });
+            u8::try_from(idx).ok().map(Some)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(Indexed { palette, pixels })
+}
+
+/// Upper half blocks with the upper pixel as foreground and the lower one as background color
+fn halfblocks(writer: &mut impl Write, image: &RgbaImage) -> io::Result<()> {
+    let (width, height) = image.dimensions();
+    let visible = |x, y| {
+        let Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+        (a >= 128).then_some([r, g, b])
+    };
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            let top = visible(x, y);
+            let bottom = (y + 1 < height).then(|| visible(x, y + 1)).flatten();
+            writer.write_all(b"\x1b[0m")?;
+            match (top, bottom) {
+                (Some([r, g, b]), Some([br, bg, bb])) => write!(
+                    writer,
+                    "\x1b[38;2;{r};{g};{b}m\x1b[48;2;{br};{bg};{bb}m\u{2580}"
+                )?,
+                (Some([r, g, b]), None) => write!(writer, "\x1b[38;2;{r};{g};{b}m\u{2580}")?,
+                (None, Some([r, g, b])) => write!(writer, "\x1b[38;2;{r};{g};{b}m\u{2584}")?,
+                (None, None) => writer.write_all(b" ")?,
+            }
+        }
+        writeln!(writer, "\x1b[0m")?;
+    }
+    Ok(())
+}
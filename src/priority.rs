@@ -0,0 +1,78 @@
+use std::io;
+
+use tracing::{debug, warn};
+
+/// Lower the scheduling priority of dipc and leave one CPU core to the rest of the system, for
+/// `--nice`. Has to run before rayon is first used: its threads inherit the priority of the
+/// thread that starts them, and the size of the global pool can only be set once.
+/// `RAYON_NUM_THREADS` still overrides the number of threads.
+pub fn lower() {
+    if let Err(err) = lower_native() {
+        warn!("Failed to lower the priority: {err}");
+    }
+
+    if std::env::var_os("RAYON_NUM_THREADS").is_some() {
+        return;
+    }
+    let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+    let threads = cores.saturating_sub(1).max(1);
+    match rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+    {
+        Ok(()) => debug!("Converting with {threads} of {cores} threads"),
+        Err(err) => warn!("Failed to limit the number of threads: {err}"),
+    }
+}
+
+#[cfg(unix)]
+fn lower_native() -> io::Result<()> {
+    use std::ffi::{c_int, c_uint};
+
+    const PRIO_PROCESS: c_int = 0;
+    /// The niceness `nice` gives commands by default
+    const NICENESS: c_int = 10;
+
+    extern "C" {
+        fn setpriority(which: c_int, who: c_uint, priority: c_int) -> c_int;
+    }
+
+    // SAFETY: setpriority takes no pointers. On Linux it only changes the calling thread, the
+    // threads started after it inherit the priority
+    if unsafe { setpriority(PRIO_PROCESS, 0, NICENESS) } == 0 {
+        return Ok(());
+    }
+    match io::Error::last_os_error() {
+        // Only raising the priority needs privileges, so dipc already runs at a lower one
+        err if err.kind() == io::ErrorKind::PermissionDenied => Ok(()),
+        err => Err(err),
+    }
+}
+
+#[cfg(windows)]
+fn lower_native() -> io::Result<()> {
+    use std::ffi::c_void;
+
+    const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x4000;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentProcess() -> *mut c_void;
+        fn SetPriorityClass(process: *mut c_void, priority_class: u32) -> i32;
+    }
+
+    // SAFETY: the pseudo handle of the current process is always valid and needs no closing
+    let set = unsafe { SetPriorityClass(GetCurrentProcess(), BELOW_NORMAL_PRIORITY_CLASS) };
+    if set == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn lower_native() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "not supported on this platform",
+    ))
+}
@@ -0,0 +1,122 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde_json::json;
+
+use crate::cli::ProgressFormat;
+
+/// Time between two JSON progress events
+const INTERVAL: Duration = Duration::from_millis(250);
+
+/// Progress of converting the frames of one image, drawn as a bar or reported as JSON events
+pub struct Progress {
+    bar: ProgressBar,
+    frame: Arc<AtomicUsize>,
+    reporter: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl Progress {
+    /// Track the conversion of `len` pixels of `file`, spread over `frames` frames. Nothing is
+    /// reported without a `format`.
+    pub fn new(len: u64, format: Option<ProgressFormat>, file: &Path, frames: usize) -> Self {
+        let frame = Arc::new(AtomicUsize::new(0));
+        match format {
+            None => Self {
+                bar: ProgressBar::hidden(),
+                frame,
+                reporter: None,
+            },
+            Some(ProgressFormat::Bar) => {
+                let bar = ProgressBar::new(len);
+                bar.set_style(
+                    ProgressStyle::with_template(
+                        "[{elapsed_precise}] [{wide_bar}] {pos}/{len} ({eta_precise})",
+                    )
+                    .expect("Failed to set progress bar style"),
+                );
+                Self {
+                    bar,
+                    frame,
+                    reporter: None,
+                }
+            }
+            Some(ProgressFormat::Json) => {
+                // The bar only counts the pixels, a thread samples it to emit events
+                let bar = ProgressBar::with_draw_target(Some(len), ProgressDrawTarget::hidden());
+                let (stop, stopped) = mpsc::channel();
+                let reporter = {
+                    let bar = bar.clone();
+                    let frame = Arc::clone(&frame);
+                    let file = file.display().to_string();
+                    thread::spawn(move || {
+                        let start = Instant::now();
+                        let mut last = None;
+                        loop {
+                            let done = match stopped.recv_timeout(INTERVAL) {
+                                Err(RecvTimeoutError::Timeout) => false,
+                                Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+                            };
+                            let position = bar.position();
+                            if done || last != Some(position) {
+                                last = Some(position);
+                                let percent = match len {
+                                    0 => 100.0,
+                                    len => position as f64 * 100.0 / len as f64,
+                                };
+                                eprintln!(
+                                    "{}",
+                                    json!({
+                                        "event": if done { "done" } else { "progress" },
+                                        "file": file,
+                                        "frame": frame.load(Ordering::Relaxed) + 1,
+                                        "frames": frames,
+                                        "percent": (percent * 10.0).round() / 10.0,
+                                        "eta": bar.eta().as_secs_f64(),
+                                        "elapsed": start.elapsed().as_secs_f64(),
+                                    })
+                                );
+                            }
+                            if done {
+                                break;
+                            }
+                        }
+                    })
+                };
+                Self {
+                    bar,
+                    frame,
+                    reporter: Some((stop, reporter)),
+                }
+            }
+        }
+    }
+
+    /// The bar to pass to the conversion, counting converted pixels
+    pub fn bar(&self) -> ProgressBar {
+        self.bar.clone()
+    }
+
+    /// Mark the frame with index `frame` as the one being converted
+    pub fn set_frame(&self, frame: usize) {
+        self.frame.store(frame, Ordering::Relaxed);
+    }
+
+    /// Finish the bar, or emit the final JSON event
+    pub fn finish(self) {
+        self.bar.finish();
+        if let Some((stop, reporter)) = self.reporter {
+            // The reporter also stops when the sender is dropped
+            let _ = stop.send(());
+            let _ = reporter.join();
+        }
+    }
+}
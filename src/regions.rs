@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use image::{imageops, RgbImage};
+use serde::Deserialize;
+
+use crate::{
+    cli::ColorPaletteStyles,
+    commands::parse_palettes,
+    config::parse_hex,
+    convert::{self, Matcher},
+    delta::Lab,
+    error::{DipcError, Result},
+};
+
+/// Palettes for the regions of a color-coded mask, read with `--regions`
+///
+/// ```json
+/// {
+///     "mask": "regions.png",
+///     "regions": {
+///         "#ff0000": { "palette": "catppuccin", "styles": "mocha" },
+///         "#0000ff": { "palette": "catppuccin", "styles": "latte" }
+///     }
+/// }
+/// ```
+///
+/// The mask path is relative to the JSON file. Pixels of a mask color that is not listed use the
+/// main palette.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegionsFile {
+    mask: PathBuf,
+    regions: HashMap<String, RegionFile>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegionFile {
+    palette: String,
+    styles: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Regions {
+    mask: RgbImage,
+    regions: Vec<Region>,
+}
+
+#[derive(Debug)]
+struct Region {
+    color: [u8; 3],
+    palette: Vec<Lab>,
+    weights: Vec<f32>,
+}
+
+impl Regions {
+    pub fn open(path: &Path) -> Result<Regions> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| DipcError::io(format!("Failed to read regions {:?}", path), err))?;
+        let file: RegionsFile = serde_json::from_str(&text).map_err(|err| {
+            DipcError::Palette(format!("Failed to parse regions {:?}: {err}", path))
+        })?;
+        if file.regions.len() > usize::from(u8::MAX) {
+            return Err(DipcError::Palette(format!(
+                "Regions {:?} has more than {} regions",
+                path,
+                u8::MAX
+            )));
+        }
+
+        let mask_path = path.parent().unwrap_or(Path::new("")).join(&file.mask);
+        let mask = image::open(&mask_path)
+            .map_err(|source| DipcError::Decode {
+                path: mask_path,
+                source,
+            })?
+            .into_rgb8();
+        let mut regions = Vec::with_capacity(file.regions.len());
+        for (color, region) in &file.regions {
+            let context = |err: String| {
+                DipcError::Palette(format!(
+                    "Failed to parse region `{color}` of {:?}: {err}",
+                    path
+                ))
+            };
+            let styles = region
+                .styles
+                .as_deref()
+                .map(ColorPaletteStyles::from_str)
+                .transpose()
+                .map_err(context)?;
+            let palettes = parse_palettes(&region.palette, styles.as_ref())
+                .map_err(|err| context(err.to_string()))?;
+            regions.push(Region {
+                color: parse_hex(color).map_err(context)?,
+                palette: convert::palette_lab(&palettes),
+                weights: convert::palette_weights(&palettes),
+            });
+        }
+        Ok(Regions { mask, regions })
+    }
+
+    /// One matcher per region, in the order of the labels returned by `labels`
+    pub fn matchers(&self, method: deltae::DEMethod) -> Vec<Matcher<'_>> {
+        self.regions
+            .iter()
+            .map(|region| Matcher {
+                palette: &region.palette,
+                weights: &region.weights,
+                method,
+                remap: None,
+                gradient: None,
+                regions: &[],
+            })
+            .collect()
+    }
+
+    /// The region of every pixel of a `width`x`height` image, as an index into `matchers` plus
+    /// one, or 0 for pixels outside of all regions. The mask is stretched to the size of the image.
+    pub fn labels(&self, width: u32, height: u32) -> Vec<u8> {
+        let resized;
+        let mask = match self.mask.dimensions() == (width, height) {
+            true => &self.mask,
+            false => {
+                // Nearest neighbor keeps the mask colors exact
+                resized =
+                    imageops::resize(&self.mask, width, height, imageops::FilterType::Nearest);
+                &resized
+            }
+        };
+        let labels: HashMap<[u8; 3], u8> = self
+            .regions
+            .iter()
+            .zip(1..)
+            .map(|(region, label)| (region.color, label))
+            .collect();
+        mask.pixels()
+            .map(|pixel| labels.get(&pixel.0).copied().unwrap_or(0))
+            .collect()
+    }
+}
@@ -0,0 +1,119 @@
+use std::{fs, path::Path};
+
+use serde_json::Value;
+
+use crate::{
+    config::{normalize_style, parse_hex, Palette},
+    delta::Lab,
+    error::{DipcError, Result},
+};
+
+/// Explicit source → target color pairs read with `--map`, e.g.
+///
+/// ```json
+/// {
+///     "#2e3440": "base",
+///     "#88c0d0": "#89b4fa"
+/// }
+/// ```
+///
+/// Targets are either hex colors or names of palette colors, which are looked up in the palette of
+/// every output with `resolve`.
+#[derive(Debug, Clone)]
+pub struct RemapFile {
+    entries: Vec<(Lab, Target)>,
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    Color([u8; 3]),
+    Name(String),
+}
+
+/// Remap entries with their targets resolved against one palette
+#[derive(Debug, Clone)]
+pub struct Remap {
+    entries: Vec<(Lab, [u8; 3])>,
+    /// Maximum DeltaE between a pixel and a source color
+    tolerance: f32,
+}
+
+impl RemapFile {
+    pub fn open(path: &Path) -> Result<RemapFile> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| DipcError::io(format!("Failed to read color map {:?}", path), err))?;
+        parse(&text).map_err(|err| {
+            DipcError::Palette(format!("Failed to parse color map {:?}: {err}", path))
+        })
+    }
+
+    /// Look up the targets given as color names in `palettes`
+    pub fn resolve(&self, palettes: &[Palette], tolerance: f32) -> Result<Remap> {
+        let entries = self
+            .entries
+            .iter()
+            .map(|(source, target)| {
+                let color = match target {
+                    Target::Color(color) => *color,
+                    Target::Name(name) => {
+                        let normalized = normalize_style(name);
+                        palettes
+                            .iter()
+                            .flat_map(|palette| &palette.colors)
+                            .find(|(color_name, _)| normalize_style(color_name) == normalized)
+                            .map(|(_, color)| color.0)
+                            .ok_or_else(|| {
+                                let candidates = palettes.iter().flat_map(|palette| {
+                                    palette.colors.iter().map(|(name, _)| name.as_str())
+                                });
+                                DipcError::Palette(format!(
+                                    "Color map target `{name}` is not a color of the palette.{}",
+                                    crate::fuzzy::did_you_mean(name, candidates)
+                                ))
+                            })?
+                    }
+                };
+                Ok((*source, color))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Remap { entries, tolerance })
+    }
+}
+
+impl Remap {
+    /// The target of the source color closest to `lab`, if it is within the tolerance
+    pub fn target(&self, lab: Lab, method: deltae::DEMethod) -> Option<[u8; 3]> {
+        self.entries
+            .iter()
+            .map(|(source, target)| (*deltae::DeltaE::new(lab, *source, method).value(), target))
+            .filter(|(delta, _)| *delta <= self.tolerance)
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, target)| *target)
+    }
+}
+
+fn parse(text: &str) -> std::result::Result<RemapFile, String> {
+    let json: Value = serde_json::from_str(text).map_err(|err| err.to_string())?;
+    let Value::Object(map) = json else {
+        return Err(String::from(
+            "Expected a JSON object of source → target colors",
+        ));
+    };
+    let entries = map
+        .into_iter()
+        .map(|(source, target)| {
+            let source = Lab::from(parse_hex(&source)?);
+            let Value::String(target) = target else {
+                return Err(format!(
+                    "Expected a hex color or a palette color name as target, found {target}"
+                ));
+            };
+            let target = match target.starts_with('#') {
+                true => Target::Color(parse_hex(&target)?),
+                false => Target::Name(target),
+            };
+            Ok((source, target))
+        })
+        .collect::<std::result::Result<_, String>>()?;
+    Ok(RemapFile { entries })
+}
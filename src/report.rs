@@ -0,0 +1,108 @@
+use std::{collections::HashSet, fs::File, io::BufWriter, path::Path};
+
+use image::Frame;
+use serde::Serialize;
+
+use crate::{
+    config::Palette,
+    convert::Usage,
+    error::{DipcError, Result},
+    metrics::Metrics,
+};
+
+/// Summary of one conversion, written next to the output image with `--report`
+#[derive(Debug, Serialize)]
+pub struct Report<'a> {
+    pub input: &'a Path,
+    pub output: &'a Path,
+    pub palette: String,
+    pub variations: Vec<&'a str>,
+    pub method: String,
+    pub duration_secs: f64,
+    pub width: u32,
+    pub height: u32,
+    pub frames: usize,
+    /// Number of colors in the palette the image was mapped to
+    pub palette_colors: usize,
+    /// Number of those colors that ended up in the image
+    pub colors_used: usize,
+    /// Difference to the input, with `--metrics`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<Metrics>,
+}
+
+impl Report<'_> {
+    /// Write the report to `<output>.json`
+    pub fn write(&self) -> Result<()> {
+        let mut path = self.output.as_os_str().to_os_string();
+        path.push(".json");
+        let context = || format!("Failed to write report {:?}", path);
+        let file = File::create(&path).map_err(|err| DipcError::io(context(), err))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)
+            .map_err(|err| DipcError::io(context(), err.into()))
+    }
+}
+
+/// Number of distinct colors of `palette` found in `frames`. Other colors, like the untouched half
+/// of a split preview, are not counted.
+pub fn colors_used(frames: &[Frame], palette: &[[u8; 3]]) -> usize {
+    let palette: HashSet<[u8; 3]> = palette.iter().copied().collect();
+    let mut used = HashSet::with_capacity(palette.len());
+    for pixel in frames.iter().flat_map(|frame| frame.buffer().pixels()) {
+        let [r, g, b, _] = pixel.0;
+        if palette.contains(&[r, g, b]) {
+            used.insert([r, g, b]);
+        }
+    }
+    used.len()
+}
+
+/// How many pixels were mapped to each palette color, printed with `--stats`
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    /// Number of visible pixels converted
+    pub pixels: u64,
+    /// The colors used, most used first
+    pub colors: Vec<ColorUsage>,
+    /// Names of the colors that were never used
+    pub unused: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ColorUsage {
+    pub name: String,
+    pub hex: String,
+    pub pixels: u64,
+}
+
+impl Stats {
+    /// Count the pixels of every color of `variations` in `usage`. Colors shared by several
+    /// variations are counted once, under their first name.
+    pub fn new(variations: &[Palette], usage: &Usage) -> Self {
+        let mut seen = HashSet::new();
+        let mut colors = Vec::new();
+        let mut unused = Vec::new();
+        for (name, color) in variations.iter().flat_map(|variation| &variation.colors) {
+            if !seen.insert(color.0) {
+                continue;
+            }
+            match usage.get(&color.0) {
+                Some(&pixels) => {
+                    let [r, g, b] = color.0;
+                    colors.push(ColorUsage {
+                        name: name.clone(),
+                        hex: format!("#{r:02x}{g:02x}{b:02x}"),
+                        pixels,
+                    });
+                }
+                None => unused.push(name.clone()),
+            }
+        }
+        colors.sort_by_key(|color| std::cmp::Reverse(color.pixels));
+        Stats {
+            pixels: usage.values().sum(),
+            colors,
+            unused,
+        }
+    }
+}
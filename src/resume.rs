@@ -0,0 +1,67 @@
+use std::{
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+};
+
+use image::RgbaImage;
+
+use crate::{cli::Cli, settings};
+
+/// Converted frames of an animation, kept in `$XDG_CACHE_HOME/dipc/resume` until the output is
+/// saved, so running the same command again after a crash or Ctrl-C continues from the last
+/// converted frame
+pub struct Spill {
+    dir: PathBuf,
+}
+
+impl Spill {
+    /// The frames of converting the image at `path` to the `target_idx`th target. The directory
+    /// depends on the image, its modification time and every option, so a changed command
+    /// starts over.
+    pub fn open(cli: &Cli, path: &Path, target_idx: usize) -> Option<Spill> {
+        let metadata = fs::metadata(path).ok()?;
+        let mut hasher = DefaultHasher::new();
+        format!("{cli:?}").hash(&mut hasher);
+        std::path::absolute(path).ok()?.hash(&mut hasher);
+        metadata.len().hash(&mut hasher);
+        metadata.modified().ok()?.hash(&mut hasher);
+        target_idx.hash(&mut hasher);
+        let dir = settings::cache_dir()?
+            .join("dipc")
+            .join("resume")
+            .join(format!("{:016x}", hasher.finish()));
+        fs::create_dir_all(&dir).ok()?;
+        Some(Spill { dir })
+    }
+
+    /// Replace `frame` by its converted pixels if an earlier run saved them
+    pub fn load(&self, idx: usize, frame: &mut RgbaImage) -> bool {
+        match fs::read(self.frame_path(idx)) {
+            Ok(pixels) if pixels.len() == frame.len() => {
+                frame.copy_from_slice(&pixels);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Keep the converted pixels of a frame. They are written to a `.part` file first, so a
+    /// crash while writing doesn't leave a truncated frame behind
+    pub fn save(&self, idx: usize, frame: &RgbaImage) -> io::Result<()> {
+        let path = self.frame_path(idx);
+        let part = path.with_extension("part");
+        fs::write(&part, frame.as_raw())?;
+        fs::rename(part, path)
+    }
+
+    /// Remove the frames once the output is saved
+    pub fn remove(self) {
+        let _ = fs::remove_dir_all(self.dir);
+    }
+
+    fn frame_path(&self, idx: usize) -> PathBuf {
+        self.dir.join(format!("{idx}.rgba"))
+    }
+}
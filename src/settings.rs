@@ -0,0 +1,169 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use clap::{parser::ValueSource, ArgMatches, ValueEnum};
+use serde::Deserialize;
+
+use crate::{
+    cli::{Cli, ColorPalette, ColorPaletteStyles},
+    delta::CLIDEMethod,
+    error::{DipcError, Result},
+};
+
+/// Defaults read from `$XDG_CONFIG_HOME/dipc/config.toml`
+///
+/// ```toml
+/// palette = "nord"
+/// styles = "Frost,Aurora"
+/// method = "de1976"
+/// dir_output = "~/Pictures/wallpapers"
+/// nice = true
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub palette: Option<String>,
+    pub styles: Option<String>,
+    pub method: Option<String>,
+    pub dir_output: Option<PathBuf>,
+    pub nice: Option<bool>,
+}
+
+/// `$XDG_CONFIG_HOME`, falling back to `~/.config`
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_CACHE_HOME`, falling back to `~/.cache`
+pub fn cache_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// `$XDG_DATA_HOME/dipc/palettes`, falling back to `~/.local/share/dipc/palettes`
+pub fn user_palette_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share").map(|dir| dir.join("dipc").join("palettes"))
+}
+
+fn xdg_dir(var: &str, home_fallback: &str) -> Option<PathBuf> {
+    match std::env::var_os(var) {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => home_dir().map(|home| home.join(home_fallback)),
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Expand a leading `~/` to the home directory
+fn expand_tilde(path: PathBuf) -> PathBuf {
+    match (path.strip_prefix("~"), home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path,
+    }
+}
+
+impl Settings {
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("dipc").join("config.toml"))
+    }
+
+    /// Load the configuration file, returning the defaults if it does not exist
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(DipcError::io(
+                    format!("Failed to read configuration file {}", path.display()),
+                    err,
+                ))
+            }
+        };
+        toml::from_str(&content).map_err(|err| {
+            DipcError::Usage(format!(
+                "Failed to parse configuration file {}: {err}",
+                path.display()
+            ))
+        })
+    }
+
+    /// Fill in every option of `cli` that was not given on the command line, then resolve the
+    /// palette argument
+    pub fn apply(self, cli: &mut Cli, matches: &ArgMatches) -> Result<()> {
+        if cli.command.is_some() {
+            return Ok(());
+        }
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+        if let Some(styles) = self.styles.filter(|_| !from_cli("styles")) {
+            cli.styles = ColorPaletteStyles::from_str(&styles)
+                .map_err(|err| DipcError::Usage(format!("Invalid `styles` in config: {err}")))?;
+        }
+        if let Some(method) = self.method.filter(|_| !from_cli("method")) {
+            cli.method = CLIDEMethod::from_str(&method, true)
+                .map_err(|err| DipcError::Usage(format!("Invalid `method` in config: {err}")))?;
+        }
+        if let Some(dir) = self.dir_output.filter(|_| !from_cli("dir_output")) {
+            cli.dir_output = Some(expand_tilde(dir));
+        }
+        if let Some(nice) = self.nice.filter(|_| !from_cli("nice")) {
+            cli.nice = nice;
+        }
+
+        // A LUT replaces the palette, so the first positional argument is an image
+        if cli.apply_lut.is_some() {
+            if let Some(arg) = cli.palette.take() {
+                cli.process.insert(0, arg.into());
+            }
+            return Ok(());
+        }
+
+        // With a default palette configured, `dipc image.png` is allowed: the first positional
+        // argument is then treated as an image if it is one
+        let palette = match (cli.palette.take(), self.palette) {
+            (Some(arg), Some(default))
+                if is_image_file(&arg) || cli.recursive && Path::new(&arg).is_dir() =>
+            {
+                cli.process.insert(0, arg.into());
+                default
+            }
+            (Some(arg), _) => arg,
+            (None, Some(default)) => default,
+            (None, None) => {
+                return Err(DipcError::Usage(
+                    "You need to provide a color palette".to_string(),
+                ))
+            }
+        };
+        cli.color_palette = ColorPalette::from_str(&palette).map_err(DipcError::Palette)?;
+        cli.palette = Some(palette);
+        Ok(())
+    }
+}
+
+fn is_image_file(arg: &str) -> bool {
+    let path = Path::new(arg);
+    path.is_file() && is_image_path(path)
+}
+
+/// Whether `path` has the extension of an image format dipc can read
+pub fn is_image_path(path: &Path) -> bool {
+    image::ImageFormat::from_path(path).is_ok()
+        || cfg!(feature = "jxl") && has_extension(path, &["jxl"])
+        || cfg!(feature = "heif") && has_extension(path, &["heic", "heif"])
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
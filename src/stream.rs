@@ -0,0 +1,140 @@
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use image::{Frame, ImageFormat, RgbaImage};
+use tracing::info;
+
+use crate::{
+    convert_image_format::{Container, Decoded},
+    error::{DipcError, Result},
+    metadata::Metadata,
+    Pipeline,
+};
+
+/// A directory for the images of a stream, removed with everything in it when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Create the directory the streamed images are converted in
+pub fn dir() -> Result<TempDir> {
+    let path = std::env::temp_dir().join(format!("dipc-stream-{}", std::process::id()));
+    fs::create_dir_all(&path)
+        .map_err(|err| DipcError::io("Failed to create a temporary directory", err))?;
+    Ok(TempDir(path))
+}
+
+/// Convert every image read from stdin until it is closed, writing the outputs of each image to
+/// stdout before reading the next one. `pipeline` saves its outputs in the directory from `dir`.
+pub fn run(pipeline: &Pipeline) -> Result<()> {
+    let dir = pipeline
+        .cli
+        .dir_output
+        .as_deref()
+        .expect("Streams are converted in a temporary directory");
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    let mut idx = 0;
+    while let Some(image) = read_image(&mut stdin)? {
+        let input = dir.join(format!("stdin-{idx}"));
+        fs::write(&input, image)
+            .map_err(|err| DipcError::io("Failed to write a temporary file", err))?;
+        for output in pipeline.convert(idx, &input, &mut io::sink())? {
+            let image = fs::read(&output)
+                .map_err(|err| DipcError::io("Failed to read a converted image", err))?;
+            stdout.write_all(&(image.len() as u64).to_be_bytes())?;
+            stdout.write_all(&image)?;
+            let _ = fs::remove_file(output);
+        }
+        stdout.flush()?;
+        let _ = fs::remove_file(input);
+        idx += 1;
+    }
+    Ok(())
+}
+
+/// Convert raw RGBA frames of `width`x`height` pixels read from stdin until it is closed, writing
+/// every converted frame to stdout before reading the next one
+pub fn run_raw(pipeline: &Pipeline, width: u32, height: u32) -> Result<()> {
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    let length = width as usize * height as usize * 4;
+    let mut frames = 0;
+    loop {
+        let mut buffer = Vec::with_capacity(length);
+        (&mut stdin).take(length as u64).read_to_end(&mut buffer)?;
+        match buffer.len() {
+            0 => break,
+            read if read < length => {
+                return Err(DipcError::io(
+                    format!("The stream ended after {read} of {length} bytes of a frame"),
+                    io::ErrorKind::UnexpectedEof.into(),
+                ))
+            }
+            _ => {}
+        }
+        let frame = RgbaImage::from_raw(width, height, buffer).expect("Read a whole frame");
+        let input = Decoded {
+            frames: vec![Frame::new(frame)],
+            container: Container::Still,
+            format: ImageFormat::Png,
+            deep: None,
+            metadata: Metadata::default(),
+        };
+        pipeline.convert_image(
+            frames,
+            Path::new("stdin"),
+            input,
+            &mut io::sink(),
+            Some(&mut stdout),
+        )?;
+        stdout.flush()?;
+        frames += 1;
+    }
+    info!("Converted {frames} frames");
+    Ok(())
+}
+
+/// Read the next length-prefixed image, `None` if the stream ended in between two images
+fn read_image(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut length = [0; 8];
+    let mut read = 0;
+    while read < length.len() {
+        match reader.read(&mut length[read..])? {
+            0 if read == 0 => return Ok(None),
+            0 => {
+                return Err(DipcError::io(
+                    "The stream ended in the length of an image",
+                    io::ErrorKind::UnexpectedEof.into(),
+                ))
+            }
+            n => read += n,
+        }
+    }
+    let length = u64::from_be_bytes(length);
+    let mut image = Vec::new();
+    reader.take(length).read_to_end(&mut image)?;
+    if (image.len() as u64) < length {
+        return Err(DipcError::io(
+            format!(
+                "The stream ended after {} of {length} bytes of an image",
+                image.len()
+            ),
+            io::ErrorKind::UnexpectedEof.into(),
+        ));
+    }
+    Ok(Some(image))
+}
@@ -0,0 +1,272 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use image::{DynamicImage, ImageBuffer, ImageFormat, Luma, LumaA, Rgb, Rgba, RgbaImage};
+
+use crate::{
+    convert::{self, Cancelled, Usage},
+    convert_image_format::Rgba16Image,
+    error::{DipcError, Result},
+    metadata::{self, Metadata},
+};
+
+/// Rough bytes per pixel of a strip: the decoded rows, the image they are expanded to, the 8 bit
+/// frame and its 16 bit copy
+const STRIP_PIXEL: u64 = 24;
+
+/// Why the image at `path` can't be read a strip of rows at a time, or `None` if it can. Only
+/// still PNGs that are not interlaced are read row by row, and rotating an image by its EXIF
+/// orientation needs all of it.
+pub fn unsupported(path: &Path) -> Option<&'static str> {
+    let Ok(reader) = File::open(path)
+        .map_err(png::DecodingError::from)
+        .and_then(|file| png::Decoder::new(BufReader::new(file)).read_info())
+    else {
+        return Some("it is not a PNG");
+    };
+    let info = reader.info();
+    if info.interlaced {
+        return Some("it is interlaced");
+    }
+    if info.animation_control.is_some() {
+        return Some("it is animated");
+    }
+    if Metadata::read(path, ImageFormat::Png).orientation() != 1 {
+        return Some("it is rotated by its EXIF orientation");
+    }
+    None
+}
+
+/// Convert the PNG at `input` a strip of rows at a time with `map` and write it to `output` as
+/// it goes, so neither image is ever whole in memory. A strip takes about `max` bytes.
+///
+/// Every strip is decoded like `convert_image_format::open` decodes a whole image: an 8 bit RGBA
+/// frame, plus a 16 bit copy for 16 bit images which is saved instead of the frame once `map`
+/// converted it.
+pub fn convert(
+    input: &Path,
+    output: &Path,
+    max: u64,
+    metadata: &Metadata,
+    mut map: impl FnMut(&mut RgbaImage, Option<&Rgba16Image>) -> Result<Usage, Cancelled>,
+) -> Result<Usage> {
+    let decode_err = |source| DipcError::Decode {
+        path: input.to_path_buf(),
+        source,
+    };
+    let encode_err = |source| DipcError::Encode {
+        path: output.to_path_buf(),
+        source,
+    };
+
+    let file = File::open(input).map_err(|err| decode_err(err.into()))?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    decoder.set_ignore_text_chunk(true);
+    let mut reader = decoder
+        .read_info()
+        .map_err(png_decoding_error)
+        .map_err(decode_err)?;
+    let (width, height) = (reader.info().width, reader.info().height);
+    let (color, depth) = reader.output_color_type();
+    let deep = depth == png::BitDepth::Sixteen;
+
+    let file = File::create(output).map_err(|err| encode_err(err.into()))?;
+    let mut writer = png_writer(file, width, height, deep, metadata).map_err(encode_err)?;
+
+    let rows_per_strip = (max / (u64::from(width) * STRIP_PIXEL)).clamp(1, u64::from(height));
+    let mut usage = Usage::new();
+    let mut data = Vec::new();
+    let mut first_row = 0;
+    while first_row < height {
+        let rows = (height - first_row).min(rows_per_strip as u32);
+        data.clear();
+        for _ in 0..rows {
+            let row = reader
+                .next_row()
+                .map_err(png_decoding_error)
+                .map_err(decode_err)?
+                .ok_or_else(|| decode_err(truncated()))?;
+            data.extend_from_slice(row.data());
+        }
+        let strip =
+            strip_image(color, deep, width, rows, &data).ok_or_else(|| decode_err(truncated()))?;
+        let strip_deep = deep.then(|| strip.to_rgba16());
+        let mut frame = strip.into_rgba8();
+
+        usage = convert::merge_usage(usage, map(&mut frame, strip_deep.as_ref())?);
+        let written = match strip_deep {
+            Some(mut strip_deep) => {
+                convert::copy_rgb_to_deep(&frame, &mut strip_deep);
+                let bytes: Vec<u8> = strip_deep
+                    .as_raw()
+                    .iter()
+                    .flat_map(|channel| channel.to_be_bytes())
+                    .collect();
+                writer.write_all(&bytes)
+            }
+            None => writer.write_all(frame.as_raw()),
+        };
+        written.map_err(|err| encode_err(err.into()))?;
+        first_row += rows;
+    }
+    writer
+        .finish()
+        .map_err(png_encoding_error)
+        .map_err(encode_err)?;
+    Ok(usage)
+}
+
+/// A PNG encoder taking the RGBA rows of a `width`x`height` image, with the chunks of `metadata`
+/// written ahead of them
+fn png_writer(
+    file: File,
+    width: u32,
+    height: u32,
+    deep: bool,
+    metadata: &Metadata,
+) -> image::ImageResult<png::StreamWriter<'static, BufWriter<File>>> {
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(match deep {
+        true => png::BitDepth::Sixteen,
+        false => png::BitDepth::Eight,
+    });
+    let mut writer = encoder.write_header().map_err(png_encoding_error)?;
+    for (kind, payload) in metadata::png_chunks(metadata)? {
+        writer
+            .write_chunk(png::chunk::ChunkType(kind), &payload)
+            .map_err(png_encoding_error)?;
+    }
+    writer.into_stream_writer().map_err(png_encoding_error)
+}
+
+/// The image made of `rows` rows of `data` as decoded by `png` with `Transformations::EXPAND`
+fn strip_image(
+    color: png::ColorType,
+    deep: bool,
+    width: u32,
+    rows: u32,
+    data: &[u8],
+) -> Option<DynamicImage> {
+    if deep {
+        let data: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|channel| u16::from_be_bytes([channel[0], channel[1]]))
+            .collect();
+        return match color {
+            png::ColorType::Grayscale => {
+                ImageBuffer::<Luma<u16>, _>::from_raw(width, rows, data).map(Into::into)
+            }
+            png::ColorType::GrayscaleAlpha => {
+                ImageBuffer::<LumaA<u16>, _>::from_raw(width, rows, data).map(Into::into)
+            }
+            png::ColorType::Rgb => {
+                ImageBuffer::<Rgb<u16>, _>::from_raw(width, rows, data).map(Into::into)
+            }
+            png::ColorType::Rgba => {
+                ImageBuffer::<Rgba<u16>, _>::from_raw(width, rows, data).map(Into::into)
+            }
+            png::ColorType::Indexed => None,
+        };
+    }
+    let data = data.to_vec();
+    match color {
+        png::ColorType::Grayscale => {
+            ImageBuffer::<Luma<u8>, _>::from_raw(width, rows, data).map(Into::into)
+        }
+        png::ColorType::GrayscaleAlpha => {
+            ImageBuffer::<LumaA<u8>, _>::from_raw(width, rows, data).map(Into::into)
+        }
+        png::ColorType::Rgb => {
+            ImageBuffer::<Rgb<u8>, _>::from_raw(width, rows, data).map(Into::into)
+        }
+        png::ColorType::Rgba => {
+            ImageBuffer::<Rgba<u8>, _>::from_raw(width, rows, data).map(Into::into)
+        }
+        png::ColorType::Indexed => None,
+    }
+}
+
+fn truncated() -> image::ImageError {
+    image::ImageError::IoError(std::io::ErrorKind::UnexpectedEof.into())
+}
+
+fn png_decoding_error(err: png::DecodingError) -> image::ImageError {
+    image::ImageError::Decoding(image::error::DecodingError::new(
+        ImageFormat::Png.into(),
+        err,
+    ))
+}
+
+fn png_encoding_error(err: png::EncodingError) -> image::ImageError {
+    image::ImageError::Encoding(image::error::EncodingError::new(
+        ImageFormat::Png.into(),
+        err,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    /// Stands in for a palette: inverts every pixel and counts it under its new color
+    fn invert(frame: &mut RgbaImage, _: Option<&Rgba16Image>) -> Result<Usage, Cancelled> {
+        let mut usage = Usage::new();
+        for pixel in frame.pixels_mut() {
+            for channel in &mut pixel.0[..3] {
+                *channel = u8::MAX - *channel;
+            }
+            *usage.entry([pixel[0], pixel[1], pixel[2]]).or_default() += 1;
+        }
+        Ok(usage)
+    }
+
+    /// Convert `image` in strips of two rows and whole, the strips must not show
+    fn assert_strips_match(name: &str, image: DynamicImage) {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("dipc-strips-{}-{name}.png", std::process::id()));
+        let output = input.with_extension("out.png");
+        image.save(&input).unwrap();
+
+        let two_rows = 2 * u64::from(image.width()) * STRIP_PIXEL;
+        let usage = convert(&input, &output, two_rows, &Metadata::default(), invert).unwrap();
+        let converted = image::open(&output).unwrap();
+        let _ = std::fs::remove_file(&input);
+        let _ = std::fs::remove_file(&output);
+
+        let deep = image.to_rgba16();
+        let mut expected = image.into_rgba8();
+        let expected_usage = invert(&mut expected, None).unwrap();
+        assert_eq!(usage, expected_usage);
+        match converted {
+            DynamicImage::ImageRgba16(converted) => {
+                assert_eq!(converted.dimensions(), deep.dimensions());
+                for ((converted, original), mapped) in
+                    converted.pixels().zip(deep.pixels()).zip(expected.pixels())
+                {
+                    let rgb = [mapped[0], mapped[1], mapped[2]].map(|c| u16::from(c) * 257);
+                    assert_eq!(converted.0, [rgb[0], rgb[1], rgb[2], original[3]]);
+                }
+            }
+            converted => assert_eq!(converted.to_rgba8(), expected),
+        }
+    }
+
+    #[test]
+    fn strips_match_the_whole_image() {
+        // An odd height leaves a shorter strip at the end
+        let image = RgbImage::from_fn(7, 5, |x, y| Rgb([x as u8 * 30, y as u8 * 50, 128]));
+        assert_strips_match("rgb8", image.into());
+        let image =
+            ImageBuffer::from_fn(3, 5, |x, y| LumaA([x as u16 * 20_000, y as u16 * 16_000]));
+        assert_strips_match("la16", DynamicImage::ImageLumaA16(image));
+        let image = ImageBuffer::from_fn(4, 3, |x, y| Rgb([x as u16 * 257, y as u16, 65_535]));
+        assert_strips_match("rgb16", DynamicImage::ImageRgb16(image));
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ignore::WalkBuilder;
+use tracing::{info, warn};
+
+use crate::{
+    cli::Cli,
+    error::{DipcError, Result},
+    settings::is_image_path,
+};
+
+/// The inputs with their directories expanded, see `expand`
+#[derive(Debug, Default)]
+pub struct Inputs {
+    pub images: Vec<PathBuf>,
+    /// The directory of every image relative to the directory it was found in, empty for the
+    /// images given directly
+    pub dirs: Vec<PathBuf>,
+    /// The files that are not images with their paths relative to the directory they were found
+    /// in, only collected for --copy-others
+    pub others: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Replace every directory in the inputs with the images inside it and its subdirectories,
+/// sorted by path. Files listed in `.dipcignore` files are skipped, like with `.gitignore`.
+/// Hidden entries are only included with --hidden, and symbolic links are only followed with
+/// --follow-symlinks. Files and URLs given directly are kept as they are.
+pub fn expand(cli: &Cli) -> Result<Inputs> {
+    let mut inputs = Inputs::default();
+    for input in &cli.process {
+        if !input.is_dir() {
+            inputs.images.push(input.clone());
+            inputs.dirs.push(PathBuf::new());
+            continue;
+        }
+        if !cli.recursive {
+            return Err(DipcError::Usage(format!(
+                "{:?} is a directory, add --recursive to convert the images in it",
+                input
+            )));
+        }
+
+        let found = inputs.images.len();
+        let walk = WalkBuilder::new(input)
+            .standard_filters(false)
+            .hidden(!cli.hidden)
+            .follow_links(cli.follow_symlinks)
+            .add_custom_ignore_filename(".dipcignore")
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+        for entry in walk {
+            match entry {
+                // Without --follow-symlinks links are neither files nor directories here
+                Ok(entry) if entry.file_type().is_some_and(|kind| kind.is_file()) => {
+                    let relative = entry
+                        .path()
+                        .strip_prefix(input)
+                        .unwrap_or(entry.path())
+                        .to_path_buf();
+                    if is_image_path(entry.path()) {
+                        inputs
+                            .dirs
+                            .push(relative.parent().unwrap_or(Path::new("")).to_path_buf());
+                        inputs.images.push(entry.into_path());
+                    } else if cli.copy_others && entry.file_name() != ".dipcignore" {
+                        inputs.others.push((entry.into_path(), relative));
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => warn!("Skipping part of {:?}: {err}", input),
+            }
+        }
+        if inputs.images.len() == found {
+            return Err(DipcError::Usage(format!(
+                "No images found in directory {:?}",
+                input
+            )));
+        }
+        info!(
+            "Found {} images in {:?}",
+            inputs.images.len() - found,
+            input
+        );
+    }
+    Ok(inputs)
+}
+
+/// Recreate the subdirectories `dirs` of the inputs under `dir_output`, and copy the files that
+/// are not images there
+pub fn mirror(dirs: &[PathBuf], others: &[(PathBuf, PathBuf)], dir_output: &Path) -> Result<()> {
+    let mut dirs: Vec<&Path> = dirs.iter().map(PathBuf::as_path).collect();
+    dirs.extend(others.iter().filter_map(|(_, path)| path.parent()));
+    dirs.sort_unstable();
+    dirs.dedup();
+    for dir in dirs.into_iter().filter(|dir| !dir.as_os_str().is_empty()) {
+        let dir = dir_output.join(dir);
+        fs::create_dir_all(&dir).map_err(|err| {
+            DipcError::io(format!("Failed to create output directory {:?}", dir), err)
+        })?;
+    }
+    for (source, relative) in others {
+        let destination = dir_output.join(relative);
+        fs::copy(source, &destination).map_err(|err| {
+            DipcError::io(
+                format!("Failed to copy {:?} to {:?}", source, destination),
+                err,
+            )
+        })?;
+    }
+    if !others.is_empty() {
+        info!("Copied {} other files", others.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn mirrors_the_tree_into_the_output_directory() {
+        let root = std::env::temp_dir().join(format!("dipc-walk-{}", std::process::id()));
+        let tree = root.join("tree");
+        for dir in ["a/deep", "b", "drafts", ".hidden"] {
+            fs::create_dir_all(tree.join(dir)).unwrap();
+        }
+        let files = [
+            "top.png",
+            "a/deep/bg.png",
+            "b/bg.jpg",
+            "b/notes.txt",
+            "drafts/wip.png",
+            ".hidden/secret.png",
+        ];
+        for file in files {
+            fs::write(tree.join(file), []).unwrap();
+        }
+        fs::write(tree.join(".dipcignore"), "drafts/\n").unwrap();
+
+        let cli = Cli::parse_from([
+            Path::new("dipc"),
+            Path::new("nord"),
+            &tree,
+            Path::new("--recursive"),
+            Path::new("--copy-others"),
+        ]);
+        let inputs = expand(&cli).unwrap();
+        let out = root.join("out");
+        mirror(&inputs.dirs, &inputs.others, &out).unwrap();
+        let images: Vec<_> = inputs
+            .images
+            .iter()
+            .map(|image| image.strip_prefix(&tree).unwrap().to_path_buf())
+            .collect();
+        let mirrored = ["a/deep", "b", "b/notes.txt", "drafts", ".hidden"]
+            .map(|path| (path, out.join(path).exists()));
+        fs::remove_dir_all(&root).unwrap();
+
+        // Sorted by path, without the ignored and hidden images
+        assert_eq!(
+            images,
+            [
+                Path::new("a/deep/bg.png"),
+                Path::new("b/bg.jpg"),
+                Path::new("top.png")
+            ]
+        );
+        assert_eq!(
+            inputs.dirs,
+            [Path::new("a/deep"), Path::new("b"), Path::new("")]
+        );
+        assert_eq!(
+            mirrored,
+            [
+                ("a/deep", true),
+                ("b", true),
+                ("b/notes.txt", true),
+                ("drafts", false),
+                (".hidden", false)
+            ]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    ffi::OsString,
+    io,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use tracing::{debug, info};
+
+use crate::{
+    cli::WallpaperBackend,
+    error::{DipcError, Result},
+};
+
+/// Set the image at `path` as the wallpaper of every monitor
+pub fn set(path: &Path, backend: WallpaperBackend) -> Result<()> {
+    // The wallpaper is loaded by another process, from another working directory
+    let path = path
+        .canonicalize()
+        .map_err(|err| DipcError::io(format!("Failed to resolve {}", path.display()), err))?;
+    match backend {
+        WallpaperBackend::Auto => set_native(&path)?,
+        WallpaperBackend::Swww => swww(&path)?,
+        WallpaperBackend::Hyprpaper => hyprpaper(&path)?,
+        WallpaperBackend::Feh => feh(&path)?,
+    }
+    info!("Set {} as the wallpaper", path.display());
+    Ok(())
+}
+
+fn swww(path: &Path) -> Result<()> {
+    run(Command::new("swww").arg("img").arg(path))
+}
+
+fn hyprpaper(path: &Path) -> Result<()> {
+    run(Command::new("hyprctl")
+        .args(["hyprpaper", "preload"])
+        .arg(path))?;
+    // An empty monitor name before the comma applies it to all monitors
+    let mut wallpaper = OsString::from(",");
+    wallpaper.push(path);
+    run(Command::new("hyprctl")
+        .args(["hyprpaper", "wallpaper"])
+        .arg(wallpaper))?;
+    // Otherwise the previous wallpaper stays in memory
+    run(Command::new("hyprctl").args(["hyprpaper", "unload", "unused"]))
+}
+
+fn feh(path: &Path) -> Result<()> {
+    run(Command::new("feh").arg("--bg-fill").arg(path))
+}
+
+/// Pick the backend the desktop is already using: a running swww or hyprpaper daemon on
+/// Wayland, feh on X11
+#[cfg(all(unix, not(target_os = "macos")))]
+fn set_native(path: &Path) -> Result<()> {
+    if succeeds(Command::new("pgrep").args(["-x", "swww-daemon"])) {
+        return swww(path);
+    }
+    if succeeds(Command::new("pgrep").args(["-x", "hyprpaper"])) {
+        return hyprpaper(path);
+    }
+    let x11 =
+        std::env::var_os("DISPLAY").is_some() && std::env::var_os("WAYLAND_DISPLAY").is_none();
+    if x11 && succeeds(Command::new("feh").arg("--version")) {
+        return feh(path);
+    }
+    Err(DipcError::Usage(String::from(
+        "No wallpaper backend found: start swww-daemon or hyprpaper, or install feh on X11",
+    )))
+}
+
+#[cfg(target_os = "macos")]
+fn set_native(path: &Path) -> Result<()> {
+    let path = path
+        .to_string_lossy()
+        .replace('\\', r"\\")
+        .replace('"', "\\\"");
+    let script = format!(
+        "tell application \"System Events\" to tell every desktop to set picture to \"{path}\""
+    );
+    run(Command::new("osascript").arg("-e").arg(script))
+}
+
+#[cfg(windows)]
+fn set_native(path: &Path) -> Result<()> {
+    use std::ffi::c_void;
+
+    const SPI_SETDESKWALLPAPER: u32 = 0x0014;
+    const SPIF_UPDATEINIFILE: u32 = 0x01;
+    const SPIF_SENDCHANGE: u32 = 0x02;
+
+    #[link(name = "user32")]
+    extern "system" {
+        fn SystemParametersInfoW(action: u32, param: u32, value: *mut c_void, flags: u32) -> i32;
+    }
+
+    // `canonicalize` returns a `\\?\` path, which the desktop doesn't accept
+    let path = path.to_string_lossy();
+    let path = path.strip_prefix(r"\\?\").unwrap_or(&path);
+    let mut wide: Vec<u16> = path.encode_utf16().chain(Some(0)).collect();
+    // SAFETY: `wide` is a null terminated UTF-16 string that lives until the call returns
+    let set = unsafe {
+        SystemParametersInfoW(
+            SPI_SETDESKWALLPAPER,
+            0,
+            wide.as_mut_ptr().cast(),
+            SPIF_UPDATEINIFILE | SPIF_SENDCHANGE,
+        )
+    };
+    if set == 0 {
+        return Err(DipcError::io(
+            "Failed to set the wallpaper",
+            io::Error::last_os_error(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn set_native(_path: &Path) -> Result<()> {
+    Err(DipcError::Usage(String::from(
+        "Setting the wallpaper is not supported on this platform",
+    )))
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    debug!("Running {command:?}");
+    // hyprctl prints `ok`, errors go to stderr
+    let status = command
+        .stdout(Stdio::null())
+        .status()
+        .map_err(|err| DipcError::io(format!("Failed to run {program}"), err))?;
+    if !status.success() {
+        return Err(DipcError::io(
+            format!("{program} failed to set the wallpaper"),
+            io::Error::other(status.to_string()),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn succeeds(command: &mut Command) -> bool {
+    command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}